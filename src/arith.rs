@@ -0,0 +1,345 @@
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use std::{error::Error, fmt};
+
+// How integer arithmetic behaves when a result doesn't fit in an i64
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithError {
+    Overflow,
+    DivisionByZero,
+    NegativeExponent,
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArithError::Overflow => write!(f, "Arithmetic overflow"),
+            ArithError::DivisionByZero => write!(f, "Division by zero"),
+            ArithError::NegativeExponent => write!(f, "Negative exponent"),
+        }
+    }
+}
+
+impl Error for ArithError {}
+
+// Functions every program can call without declaring them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    Abs,
+    Pow,
+    Min,
+    Max,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 4] = [Builtin::Abs, Builtin::Pow, Builtin::Min, Builtin::Max];
+
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "abs" => Some(Builtin::Abs),
+            "pow" => Some(Builtin::Pow),
+            "min" => Some(Builtin::Min),
+            "max" => Some(Builtin::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Abs => "abs",
+            Builtin::Pow => "pow",
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+        }
+    }
+
+    // All builtins take and return ints
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Abs => 1,
+            Builtin::Pow | Builtin::Min | Builtin::Max => 2,
+        }
+    }
+}
+
+// `base` to the power of `exp`, wrapping, for exponents too large for
+// i64::wrapping_pow
+fn wrapping_pow(mut base: i64, mut exp: u64) -> i64 {
+    let mut result: i64 = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    result
+}
+
+// The single definition of binary op semantics, shared by every evaluator.
+// Overflow is an error, as under OverflowPolicy::Checked.
+pub fn eval_binary(op: &BinaryOp, left: i64, right: i64) -> Result<i64, ArithError> {
+    OverflowPolicy::Checked.eval(op, left, right)
+}
+
+// Evaluates an expression made only of literals, operators and builtin
+// calls. Anything reading a variable, and anything that overflows or divides
+// by zero, has no compile-time value.
+pub fn try_const_eval(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Integer(value, _) => Some(*value),
+        Expr::Boolean(value) => Some(*value as i64),
+        Expr::Variable(_) => None,
+        Expr::Binary { op, left, right } => {
+            eval_binary(op, try_const_eval(left)?, try_const_eval(right)?).ok()
+        }
+        Expr::Unary { op, operand } => {
+            let operand = try_const_eval(operand)?;
+            match op {
+                UnaryOp::Negate => eval_binary(&BinaryOp::Subtract, 0, operand).ok(),
+                UnaryOp::Not => Some((operand == 0) as i64),
+            }
+        }
+        Expr::Call { callee, args } => {
+            let Expr::Variable(name) = &callee.node else {
+                return None;
+            };
+            let builtin = Builtin::from_name(name)?;
+            let args: Vec<i64> = args
+                .iter()
+                .map(|arg| try_const_eval(arg))
+                .collect::<Option<_>>()?;
+            if args.len() != builtin.arity() {
+                return None;
+            }
+            OverflowPolicy::Checked.eval_builtin(builtin, &args).ok()
+        }
+        // an earlier expression that fails would fail at runtime too
+        Expr::Seq(exprs) => exprs
+            .iter()
+            .map(|expr| try_const_eval(expr))
+            .collect::<Option<Vec<i64>>>()?
+            .pop(),
+        Expr::Tuple(_) | Expr::Assign { .. } | Expr::Named { .. } => None,
+    }
+}
+
+impl OverflowPolicy {
+    pub fn from_name(name: &str) -> Option<OverflowPolicy> {
+        match name {
+            "checked" => Some(OverflowPolicy::Checked),
+            "wrapping" => Some(OverflowPolicy::Wrapping),
+            "saturating" => Some(OverflowPolicy::Saturating),
+            _ => None,
+        }
+    }
+
+    // Division and modulo by zero are errors under every policy. Comparisons and logical
+    // ops produce 1 for true and 0 for false, treating any nonzero operand as
+    // true, and never overflow.
+    pub fn eval(self, op: &BinaryOp, left: i64, right: i64) -> Result<i64, ArithError> {
+        use OverflowPolicy::*;
+        if matches!(op, BinaryOp::Divide | BinaryOp::Modulo) && right == 0 {
+            return Err(ArithError::DivisionByZero);
+        }
+        let result = match (op, self) {
+            (BinaryOp::Add, Checked) => left.checked_add(right),
+            (BinaryOp::Add, Wrapping) => Some(left.wrapping_add(right)),
+            (BinaryOp::Add, Saturating) => Some(left.saturating_add(right)),
+            (BinaryOp::WrappingAdd, _) => Some(left.wrapping_add(right)),
+            (BinaryOp::SaturatingAdd, _) => Some(left.saturating_add(right)),
+            (BinaryOp::Subtract, Checked) => left.checked_sub(right),
+            (BinaryOp::Subtract, Wrapping) => Some(left.wrapping_sub(right)),
+            (BinaryOp::Subtract, Saturating) => Some(left.saturating_sub(right)),
+            (BinaryOp::Multiply, Checked) => left.checked_mul(right),
+            (BinaryOp::Multiply, Wrapping) => Some(left.wrapping_mul(right)),
+            (BinaryOp::Multiply, Saturating) => Some(left.saturating_mul(right)),
+            (BinaryOp::Divide, Checked) => left.checked_div(right),
+            (BinaryOp::Divide, Wrapping) => Some(left.wrapping_div(right)),
+            (BinaryOp::Divide, Saturating) => Some(left.saturating_div(right)),
+            // i64::MIN % -1 is 0 mathematically, but overflows like the division
+            (BinaryOp::Modulo, Checked) => left.checked_rem(right),
+            (BinaryOp::Modulo, Wrapping | Saturating) => Some(left.wrapping_rem(right)),
+            (BinaryOp::Equal, _) => Some((left == right) as i64),
+            (BinaryOp::NotEqual, _) => Some((left != right) as i64),
+            (BinaryOp::Less, _) => Some((left < right) as i64),
+            (BinaryOp::LessEqual, _) => Some((left <= right) as i64),
+            (BinaryOp::Greater, _) => Some((left > right) as i64),
+            (BinaryOp::GreaterEqual, _) => Some((left >= right) as i64),
+            (BinaryOp::And, _) => Some((left != 0 && right != 0) as i64),
+            (BinaryOp::Or, _) => Some((left != 0 || right != 0) as i64),
+        };
+        result.ok_or(ArithError::Overflow)
+    }
+
+    // `args` must have the builtin's arity
+    pub fn eval_builtin(self, builtin: Builtin, args: &[i64]) -> Result<i64, ArithError> {
+        use OverflowPolicy::*;
+        let result = match (builtin, args, self) {
+            (Builtin::Abs, [value], Checked) => value.checked_abs(),
+            (Builtin::Abs, [value], Wrapping) => Some(value.wrapping_abs()),
+            (Builtin::Abs, [value], Saturating) => Some(value.saturating_abs()),
+            // these stay small however large the exponent, so they're
+            // exact under every policy
+            (Builtin::Pow, [1, _], _) => Some(1),
+            (Builtin::Pow, [-1, exp], _) => Some(if exp % 2 == 0 { 1 } else { -1 }),
+            (Builtin::Pow, [_, exp], _) if *exp < 0 => return Err(ArithError::NegativeExponent),
+            (Builtin::Pow, [0, exp], _) => Some((*exp == 0) as i64),
+            (Builtin::Pow, [base, exp], _) => match (u32::try_from(*exp), self) {
+                (Ok(exp), Checked) => base.checked_pow(exp),
+                (Ok(exp), Wrapping) => Some(base.wrapping_pow(exp)),
+                (Ok(exp), Saturating) => Some(base.saturating_pow(exp)),
+                // anything else is at least 2 in size, so overflows
+                (Err(_), Checked) => None,
+                (Err(_), Wrapping) => Some(wrapping_pow(*base, *exp as u64)),
+                (Err(_), Saturating) if *base < 0 && exp % 2 == 1 => Some(i64::MIN),
+                (Err(_), Saturating) => Some(i64::MAX),
+            },
+            (Builtin::Min, [left, right], _) => Some(*left.min(right)),
+            (Builtin::Max, [left, right], _) => Some(*left.max(right)),
+            _ => panic!("{} called with {} arguments", builtin.name(), args.len()),
+        };
+        result.ok_or(ArithError::Overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Statement;
+    use crate::{lexer, parser};
+
+    #[test]
+    fn test_eval_binary() {
+        let cases = [
+            (BinaryOp::Add, 7, 3, 10),
+            (BinaryOp::WrappingAdd, 7, 3, 10),
+            (BinaryOp::SaturatingAdd, 7, 3, 10),
+            (BinaryOp::Subtract, 7, 3, 4),
+            (BinaryOp::Multiply, 7, 3, 21),
+            (BinaryOp::Divide, 7, 3, 2),
+            (BinaryOp::Modulo, 7, 3, 1),
+            (BinaryOp::Modulo, -7, 3, -1),
+            (BinaryOp::Equal, 7, 3, 0),
+            (BinaryOp::NotEqual, 7, 3, 1),
+            (BinaryOp::Less, 7, 3, 0),
+            (BinaryOp::LessEqual, 3, 3, 1),
+            (BinaryOp::Greater, 7, 3, 1),
+            (BinaryOp::GreaterEqual, 2, 3, 0),
+            (BinaryOp::And, 7, 0, 0),
+            (BinaryOp::Or, 7, 0, 1),
+        ];
+        for (op, left, right, expected) in cases {
+            assert_eq!(eval_binary(&op, left, right), Ok(expected), "{:?}", op);
+        }
+    }
+
+    fn const_eval(input: &str) -> Option<i64> {
+        // wrapped in a declaration, which is all the parser accepts
+        let tokens = lexer::lex(&format!("let v: int = {};", input)).unwrap();
+        match &parser::parse(tokens).unwrap().remove(0).node {
+            Statement::Let { value, .. } => try_const_eval(value),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_try_const_eval() {
+        assert_eq!(const_eval("2 * (3 + 4)"), Some(14));
+        assert_eq!(const_eval("x + 1"), None);
+        assert_eq!(const_eval("-abs(-3) < 0 && true"), Some(1));
+        assert_eq!(const_eval("1 / 0"), None);
+        assert_eq!(const_eval("9223372036854775807 + 1"), None);
+    }
+
+    #[test]
+    fn test_eval_builtin() {
+        let checked = OverflowPolicy::Checked;
+        assert_eq!(checked.eval_builtin(Builtin::Abs, &[-5]), Ok(5));
+        assert_eq!(checked.eval_builtin(Builtin::Pow, &[2, 10]), Ok(1024));
+        assert_eq!(checked.eval_builtin(Builtin::Min, &[3, -7]), Ok(-7));
+        assert_eq!(checked.eval_builtin(Builtin::Max, &[3, -7]), Ok(3));
+        assert_eq!(
+            checked.eval_builtin(Builtin::Abs, &[i64::MIN]),
+            Err(ArithError::Overflow)
+        );
+        assert_eq!(
+            checked.eval_builtin(Builtin::Pow, &[2, 63]),
+            Err(ArithError::Overflow)
+        );
+        assert_eq!(
+            checked.eval_builtin(Builtin::Pow, &[2, -1]),
+            Err(ArithError::NegativeExponent)
+        );
+        assert_eq!(
+            OverflowPolicy::Saturating.eval_builtin(Builtin::Pow, &[2, 63]),
+            Ok(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_pow_exponent_beyond_u32() {
+        let huge = u32::MAX as i64 + 2;
+        for policy in [
+            OverflowPolicy::Checked,
+            OverflowPolicy::Wrapping,
+            OverflowPolicy::Saturating,
+        ] {
+            assert_eq!(policy.eval_builtin(Builtin::Pow, &[1, huge]), Ok(1));
+            assert_eq!(policy.eval_builtin(Builtin::Pow, &[0, huge]), Ok(0));
+            assert_eq!(policy.eval_builtin(Builtin::Pow, &[-1, huge]), Ok(-1));
+            assert_eq!(policy.eval_builtin(Builtin::Pow, &[-1, huge + 1]), Ok(1));
+            assert_eq!(policy.eval_builtin(Builtin::Pow, &[1, -3]), Ok(1));
+        }
+
+        let pow =
+            |policy: OverflowPolicy, base: i64| policy.eval_builtin(Builtin::Pow, &[base, huge]);
+        assert_eq!(pow(OverflowPolicy::Checked, 2), Err(ArithError::Overflow));
+        assert_eq!(pow(OverflowPolicy::Saturating, 2), Ok(i64::MAX));
+        assert_eq!(pow(OverflowPolicy::Saturating, -2), Ok(i64::MIN));
+        // an even base wraps to zero, and 3^(2^32 + 1) is 3 times 3
+        // squared 32 times
+        assert_eq!(pow(OverflowPolicy::Wrapping, 2), Ok(0));
+        let squared = (0..32).fold(3i64, |value, _| value.wrapping_mul(value));
+        assert_eq!(
+            pow(OverflowPolicy::Wrapping, 3),
+            Ok(squared.wrapping_mul(3))
+        );
+        assert_eq!(wrapping_pow(-7, 29), (-7i64).wrapping_pow(29));
+    }
+
+    #[test]
+    fn test_eval_binary_errors() {
+        assert_eq!(
+            eval_binary(&BinaryOp::Add, i64::MAX, 1),
+            Err(ArithError::Overflow)
+        );
+        assert_eq!(
+            eval_binary(&BinaryOp::Subtract, i64::MIN, 1),
+            Err(ArithError::Overflow)
+        );
+        assert_eq!(
+            eval_binary(&BinaryOp::Multiply, i64::MAX, 2),
+            Err(ArithError::Overflow)
+        );
+        assert_eq!(
+            eval_binary(&BinaryOp::Divide, i64::MIN, -1),
+            Err(ArithError::Overflow)
+        );
+        assert_eq!(
+            eval_binary(&BinaryOp::Divide, 1, 0),
+            Err(ArithError::DivisionByZero)
+        );
+        assert_eq!(
+            eval_binary(&BinaryOp::Modulo, 1, 0),
+            Err(ArithError::DivisionByZero)
+        );
+    }
+}
@@ -1,36 +1,709 @@
-#[derive(Debug, Clone)]
+use crate::span::Spanned;
+use crate::typecheck::TypeError;
+use std::fmt;
+
+// Expressions compare and hash structurally, so they can key memoization maps
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Expr {
-    Integer(i64),
+    Integer(i64, Radix),
+    Boolean(bool),
     Variable(String),
     Binary {
         op: BinaryOp,
-        left: Box<Expr>,
-        right: Box<Expr>,
+        left: Box<Spanned<Expr>>,
+        right: Box<Spanned<Expr>>,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Spanned<Expr>>,
+    },
+    // The callee is usually a Variable naming a function, but chained calls
+    // like `f(x)(y)` call the result of another call
+    Call {
+        callee: Box<Spanned<Expr>>,
+        args: Vec<Spanned<Expr>>,
+    },
+    // `(a, b)` outside of a call, where no tuple is expected: each runs in
+    // turn and the last gives the value. Commas bind loosest of all, so
+    // `(x = 1, x + 1 < y)` sequences the whole of each side.
+    Seq(Vec<Spanned<Expr>>),
+    // `(x = value)`, which gives the value assigned; only written in
+    // parentheses, as a statement can't tell it from an assignment
+    Assign {
+        target: String,
+        value: Box<Spanned<Expr>>,
+    },
+    // `(a, b)` where a tuple is expected: as the value of a declaration with
+    // a tuple type, like a destructuring `let`, or of a `return` from a
+    // function declared to return one. Anywhere else the same parentheses
+    // are a Seq.
+    Tuple(Vec<Spanned<Expr>>),
+    // `name: value` among a call's arguments, passing the value to the
+    // parameter called `name`
+    Named {
+        name: String,
+        value: Box<Spanned<Expr>>,
     },
 }
 
-#[derive(Debug, Clone)]
+// Infix with every compound subexpression parenthesized, so the grouping
+// the parser chose is always visible: `a + b * c` prints as `(a + (b * c))`
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Integer(value, Radix::Decimal) => write!(f, "{}", value),
+            Expr::Integer(value, Radix::Hex) => write!(f, "{:#X}", value),
+            Expr::Boolean(value) => write!(f, "{}", value),
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::Binary { op, left, right } => write!(f, "({} {} {})", left, op, right),
+            Expr::Unary { op, operand } => {
+                let op = match op {
+                    UnaryOp::Negate => "-",
+                    UnaryOp::Not => "!",
+                };
+                write!(f, "({}{})", op, operand)
+            }
+            Expr::Call { callee, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", callee, args.join(", "))
+            }
+            Expr::Seq(exprs) | Expr::Tuple(exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(|expr| expr.to_string()).collect();
+                write!(f, "({})", exprs.join(", "))
+            }
+            Expr::Assign { target, value } => write!(f, "({} = {})", target, value),
+            Expr::Named { name, value } => write!(f, "{}: {}", name, value),
+        }
+    }
+}
+
+// How an integer literal was written, so output can keep it that way
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Radix {
+    Decimal,
+    Hex,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum BinaryOp {
     Add,
+    // `+%` and `+|` wrap and saturate on overflow, whatever the policy
+    WrappingAdd,
+    SaturatingAdd,
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::WrappingAdd => "+%",
+            BinaryOp::SaturatingAdd => "+|",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Modulo => "%",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Negate,
+    Not,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Type {
     Int,
+    Bool,
+    Void,
+    // `(int, bool)`, two or more ints and bools. A function can return one,
+    // and a destructuring `let` take it apart again; no variable holds one.
+    Tuple(Vec<Type>),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Type::Int => "int",
+            Type::Bool => "bool",
+            Type::Void => "void",
+            Type::Tuple(elements) => {
+                let elements: Vec<String> = elements.iter().map(|typ| typ.to_string()).collect();
+                return write!(f, "({})", elements.join(", "));
+            }
+        };
+        write!(f, "{}", name)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
     Let {
         name: String,
         typ: Type,
-        value: Expr,
+        value: Spanned<Expr>,
+    },
+    // `let (x, y): (int, int) = value;`, binding each name to an element of
+    // the tuple `typ`
+    Destructure {
+        names: Vec<String>,
+        typ: Type,
+        value: Spanned<Expr>,
+    },
+    Const {
+        name: String,
+        typ: Type,
+        value: Spanned<Expr>,
     },
     Assignment {
         target: String,
-        value: Expr,
+        value: Spanned<Expr>,
+    },
+    If {
+        condition: Spanned<Expr>,
+        then_branch: Vec<Spanned<Statement>>,
+        else_branch: Option<Vec<Spanned<Statement>>>,
+    },
+    While {
+        label: Option<String>,
+        condition: Spanned<Expr>,
+        body: Vec<Spanned<Statement>>,
     },
+    // Both carry an optional label naming the loop they apply to
+    Break(Option<String>),
+    Continue(Option<String>),
+    Function {
+        name: String,
+        params: Vec<(String, Type)>,
+        // None when the `-> type` is left out, for the checker to infer
+        return_type: Option<Type>,
+        body: Vec<Spanned<Statement>>,
+    },
+    Return(Option<Spanned<Expr>>),
+    // An expression evaluated for its effect, like a call; the value is dropped
+    Expression(Spanned<Expr>),
+    // An expression ending the program without a `;`, whose value is the
+    // program's result
+    Result(Spanned<Expr>),
+    // `import "path";`, replaced by the imported file's items before checking
+    Import(String),
+}
+
+impl Expr {
+    // The type of the expression when it follows from the expression alone.
+    // Variables and calls depend on declarations elsewhere, so give None.
+    fn local_type(&self) -> Option<Type> {
+        match self {
+            Expr::Integer(..) => Some(Type::Int),
+            Expr::Boolean(_) => Some(Type::Bool),
+            Expr::Variable(_) | Expr::Call { .. } => None,
+            Expr::Binary { op, .. } => match op {
+                BinaryOp::Add
+                | BinaryOp::WrappingAdd
+                | BinaryOp::SaturatingAdd
+                | BinaryOp::Subtract
+                | BinaryOp::Multiply
+                | BinaryOp::Divide
+                | BinaryOp::Modulo => Some(Type::Int),
+                _ => Some(Type::Bool),
+            },
+            Expr::Unary { op, .. } => match op {
+                UnaryOp::Negate => Some(Type::Int),
+                UnaryOp::Not => Some(Type::Bool),
+            },
+            Expr::Seq(exprs) => exprs.last()?.local_type(),
+            Expr::Tuple(exprs) => exprs
+                .iter()
+                .map(|expr| expr.local_type())
+                .collect::<Option<Vec<Type>>>()
+                .map(Type::Tuple),
+            Expr::Assign { value, .. } | Expr::Named { value, .. } => value.local_type(),
+        }
+    }
+}
+
+impl Statement {
+    // Builds a `let`, rejecting a value whose type is evident and differs from
+    // `typ`. This is no substitute for running the type checker afterward.
+    pub fn new_let(name: &str, typ: Type, value: Spanned<Expr>) -> Result<Statement, TypeError> {
+        if let Some(found) = value.local_type() {
+            if found != typ {
+                return Err(TypeError::mismatched_initializer(name, typ, found, &value));
+            }
+        }
+        Ok(Statement::Let {
+            name: name.to_string(),
+            typ,
+            value,
+        })
+    }
+}
+
+// Whether calling a function gives a value. One whose return type was left
+// out gives a value when any of its own `return`s has one, which the checker
+// makes sure they all agree on.
+pub fn returns_value(return_type: Option<Type>, body: &[Spanned<Statement>]) -> bool {
+    match return_type {
+        Some(typ) => typ != Type::Void,
+        None => body.iter().any(|stmt| match &stmt.node {
+            Statement::Return(value) => value.is_some(),
+            Statement::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                returns_value(None, then_branch)
+                    || else_branch
+                        .as_ref()
+                        .is_some_and(|branch| returns_value(None, branch))
+            }
+            Statement::While { body, .. } => returns_value(None, body),
+            _ => false,
+        }),
+    }
+}
+
+// Puts `values`, one for each of a call's `args` in the order written, in the
+// order of the callee's `params`. A call passes all of its arguments by
+// position, which leaves them as they are, or names every one of them,
+// giving each parameter exactly once.
+pub fn positional<T>(
+    callee: &str,
+    args: &[Spanned<Expr>],
+    values: Vec<T>,
+    params: &[&str],
+) -> Result<Vec<T>, String> {
+    let names: Vec<Option<&str>> = args
+        .iter()
+        .map(|arg| match &arg.node {
+            Expr::Named { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    if names.iter().all(Option::is_none) {
+        return Ok(values);
+    }
+    if names.iter().any(Option::is_none) {
+        return Err(format!(
+            "Call to `{}` mixes positional and named arguments",
+            callee
+        ));
+    }
+    let mut slots: Vec<Option<T>> = params.iter().map(|_| None).collect();
+    for (name, value) in names.into_iter().flatten().zip(values) {
+        let Some(index) = params.iter().position(|param| *param == name) else {
+            return Err(format!("Function `{}` has no parameter `{}`", callee, name));
+        };
+        if slots[index].replace(value).is_some() {
+            return Err(format!(
+                "Parameter `{}` of `{}` is given more than once",
+                name, callee
+            ));
+        }
+    }
+    slots
+        .into_iter()
+        .zip(params)
+        .map(|(slot, param)| {
+            slot.ok_or_else(|| format!("Call to `{}` doesn't give parameter `{}`", callee, param))
+        })
+        .collect()
+}
+
+// Renders statements back as source, one statement per line with each nested
+// block indented by four spaces. The output parses back to the same statements.
+pub fn pretty_print(statements: &[Spanned<Statement>]) -> String {
+    let mut out = String::new();
+    pretty_block(statements, 0, &mut out);
+    out
+}
+
+// Conditions need parentheses however simple they are, but Display already
+// wraps binary and unary expressions in a pair
+fn parenthesized(condition: &Expr) -> String {
+    match condition {
+        Expr::Binary { .. } | Expr::Unary { .. } => condition.to_string(),
+        _ => format!("({})", condition),
+    }
+}
+
+fn pretty_block(statements: &[Spanned<Statement>], depth: usize, out: &mut String) {
+    for stmt in statements {
+        pretty_statement(stmt, depth, out);
+    }
+}
+
+fn pretty_statement(stmt: &Statement, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    let line = match stmt {
+        Statement::Let { name, typ, value } => format!("let {}: {} = {};", name, typ, value),
+        Statement::Destructure { names, typ, value } => {
+            format!("let ({}): {} = {};", names.join(", "), typ, value)
+        }
+        Statement::Const { name, typ, value } => format!("const {}: {} = {};", name, typ, value),
+        Statement::Assignment { target, value } => format!("{} = {};", target, value),
+        Statement::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            out.push_str(&format!("{}if {} {{\n", indent, parenthesized(condition)));
+            pretty_block(then_branch, depth + 1, out);
+            if let Some(else_branch) = else_branch {
+                out.push_str(&format!("{}}} else {{\n", indent));
+                pretty_block(else_branch, depth + 1, out);
+            }
+            "}".to_string()
+        }
+        Statement::While {
+            label,
+            condition,
+            body,
+        } => {
+            let label = label
+                .as_ref()
+                .map_or(String::new(), |label| format!("{}: ", label));
+            out.push_str(&format!(
+                "{}{}while {} {{\n",
+                indent,
+                label,
+                parenthesized(condition)
+            ));
+            pretty_block(body, depth + 1, out);
+            "}".to_string()
+        }
+        Statement::Function {
+            name,
+            params,
+            return_type,
+            body,
+        } => {
+            let params: Vec<String> = params
+                .iter()
+                .map(|(name, typ)| format!("{}: {}", name, typ))
+                .collect();
+            let return_type = return_type
+                .as_ref()
+                .map_or(String::new(), |typ| format!(" -> {}", typ));
+            out.push_str(&format!(
+                "{}fn {}({}){} {{\n",
+                indent,
+                name,
+                params.join(", "),
+                return_type
+            ));
+            pretty_block(body, depth + 1, out);
+            "}".to_string()
+        }
+        Statement::Return(Some(value)) => format!("return {};", value),
+        Statement::Return(None) => "return;".to_string(),
+        Statement::Break(Some(label)) => format!("break {};", label),
+        Statement::Break(None) => "break;".to_string(),
+        Statement::Continue(Some(label)) => format!("continue {};", label),
+        Statement::Continue(None) => "continue;".to_string(),
+        Statement::Expression(expr) => format!("{};", expr),
+        Statement::Result(expr) => expr.to_string(),
+        Statement::Import(path) => format!("import {:?};", path),
+    };
+    out.push_str(&format!("{}{}\n", indent, line));
+}
+
+// Renders statements as an indented tree with each node numbered in visit order
+pub fn dump_ast(statements: &[Spanned<Statement>]) -> String {
+    let mut dumper = AstDumper {
+        out: String::new(),
+        next_id: 0,
+    };
+    for stmt in statements {
+        dumper.statement(stmt, 0);
+    }
+    dumper.out
+}
+
+struct AstDumper {
+    out: String,
+    next_id: usize,
+}
+
+impl AstDumper {
+    fn line(&mut self, label: &str, depth: usize) {
+        self.out.push_str(&format!(
+            "{}#{} {}\n",
+            "  ".repeat(depth),
+            self.next_id,
+            label
+        ));
+        self.next_id += 1;
+    }
+
+    fn statement(&mut self, stmt: &Statement, depth: usize) {
+        match stmt {
+            Statement::Let { name, value, .. } => {
+                self.line(&format!("Let {}", name), depth);
+                self.expr(value, depth + 1);
+            }
+            Statement::Destructure { names, value, .. } => {
+                self.line(&format!("Destructure {}", names.join(", ")), depth);
+                self.expr(value, depth + 1);
+            }
+            Statement::Const { name, value, .. } => {
+                self.line(&format!("Const {}", name), depth);
+                self.expr(value, depth + 1);
+            }
+            Statement::Assignment { target, value } => {
+                self.line(&format!("Assignment {}", target), depth);
+                self.expr(value, depth + 1);
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.line("If", depth);
+                self.expr(condition, depth + 1);
+                self.block("Then", then_branch, depth + 1);
+                if let Some(else_branch) = else_branch {
+                    self.block("Else", else_branch, depth + 1);
+                }
+            }
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => {
+                match label {
+                    Some(label) => self.line(&format!("While {}", label), depth),
+                    None => self.line("While", depth),
+                }
+                self.expr(condition, depth + 1);
+                self.block("Body", body, depth + 1);
+            }
+            Statement::Function {
+                name, params, body, ..
+            } => {
+                let params: Vec<&str> = params.iter().map(|(name, _)| name.as_str()).collect();
+                self.line(&format!("Function {}({})", name, params.join(", ")), depth);
+                self.block("Body", body, depth + 1);
+            }
+            Statement::Return(value) => {
+                self.line("Return", depth);
+                if let Some(value) = value {
+                    self.expr(value, depth + 1);
+                }
+            }
+            Statement::Expression(expr) => {
+                self.line("Expression", depth);
+                self.expr(expr, depth + 1);
+            }
+            Statement::Result(expr) => {
+                self.line("Result", depth);
+                self.expr(expr, depth + 1);
+            }
+            Statement::Import(path) => self.line(&format!("Import {:?}", path), depth),
+            Statement::Break(label) | Statement::Continue(label) => {
+                let keyword = if matches!(stmt, Statement::Break(_)) {
+                    "Break"
+                } else {
+                    "Continue"
+                };
+                match label {
+                    Some(label) => self.line(&format!("{} {}", keyword, label), depth),
+                    None => self.line(keyword, depth),
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, label: &str, statements: &[Spanned<Statement>], depth: usize) {
+        self.line(label, depth);
+        for stmt in statements {
+            self.statement(stmt, depth + 1);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, depth: usize) {
+        match expr {
+            Expr::Integer(value, _) => self.line(&format!("Integer {}", value), depth),
+            Expr::Boolean(value) => self.line(&format!("Boolean {}", value), depth),
+            Expr::Variable(name) => self.line(&format!("Variable {}", name), depth),
+            Expr::Binary { op, left, right } => {
+                self.line(&format!("Binary {:?}", op), depth);
+                self.expr(left, depth + 1);
+                self.expr(right, depth + 1);
+            }
+            Expr::Unary { op, operand } => {
+                self.line(&format!("Unary {:?}", op), depth);
+                self.expr(operand, depth + 1);
+            }
+            Expr::Call { callee, args } => {
+                match &callee.node {
+                    Expr::Variable(name) => self.line(&format!("Call {}", name), depth),
+                    callee => {
+                        self.line("Call", depth);
+                        self.expr(callee, depth + 1);
+                    }
+                }
+                for arg in args {
+                    self.expr(arg, depth + 1);
+                }
+            }
+            Expr::Seq(exprs) => {
+                self.line("Seq", depth);
+                for expr in exprs {
+                    self.expr(expr, depth + 1);
+                }
+            }
+            Expr::Tuple(exprs) => {
+                self.line("Tuple", depth);
+                for expr in exprs {
+                    self.expr(expr, depth + 1);
+                }
+            }
+            Expr::Assign { target, value } => {
+                self.line(&format!("Assign {}", target), depth);
+                self.expr(value, depth + 1);
+            }
+            Expr::Named { name, value } => {
+                self.line(&format!("Named {}", name), depth);
+                self.expr(value, depth + 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash_of(expr: &Expr) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        expr.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn parse_expr(input: &str) -> Spanned<Expr> {
+        let tokens = lexer::lex(&format!("let v: int = {};", input)).unwrap();
+        match parser::parse(tokens).unwrap().remove(0).node {
+            Statement::Let { value, .. } => value,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_expr_structural_hash() {
+        let first = parse_expr("a + b * 2");
+        let second = parse_expr("a + (b * 2)");
+        let different = parse_expr("(a + b) * 2");
+        assert_eq!(first, second);
+        assert_eq!(hash_of(&first), hash_of(&second));
+        assert_ne!(first, different);
+        assert_ne!(hash_of(&first), hash_of(&different));
+    }
+
+    #[test]
+    fn test_new_let() {
+        let err = Statement::new_let("x", Type::Int, parse_expr("true")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expected int, found bool in initializer `true` at line 1, column 14"));
+
+        assert!(Statement::new_let("b", Type::Bool, parse_expr("1 < 2")).is_ok());
+        // not knowable without the declaration of `y`
+        assert!(Statement::new_let("x", Type::Int, parse_expr("y")).is_ok());
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        let tokens = lexer::lex("let z: int = x * y / 2;").unwrap();
+        let stmts = parser::parse(tokens).unwrap();
+        assert_eq!(pretty_print(&stmts), "let z: int = ((x * y) / 2);\n");
+    }
+
+    #[test]
+    fn test_pretty_print_nested_blocks() {
+        let tokens = lexer::lex(
+            "fn f(n: int) -> int { outer: while (n > 0) { if (!done) { break outer; } else { n = n - 1; } } return -n; }",
+        )
+        .unwrap();
+        let stmts = parser::parse(tokens).unwrap();
+        assert_eq!(
+            pretty_print(&stmts),
+            "fn f(n: int) -> int {
+    outer: while (n > 0) {
+        if (!done) {
+            break outer;
+        } else {
+            n = (n - 1);
+        }
+    }
+    return (-n);
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_pretty_print_round_trip() {
+        let source = "let c: bool = true; let n: int = 0xFF;
+             if (c) { n = 1; } else if (false) { n = 2; }
+             outer: while (c) { while (true) { break outer; } continue; }
+             fn f(a: int) -> int { return -a; }
+             fn g(a: int) { return a; }
+             f(n);";
+        let stmts = parser::parse(lexer::lex(source).unwrap()).unwrap();
+        let printed = pretty_print(&stmts);
+        assert!(printed.contains("if (c) {"));
+        assert!(printed.contains("outer: while (c) {"));
+        assert!(printed.contains("fn g(a: int) {"));
+        // hex literals stay hex
+        assert!(printed.contains("let n: int = 0xFF;"));
+        let reparsed = parser::parse(lexer::lex(&printed).unwrap()).unwrap();
+        assert_eq!(reparsed, stmts);
+    }
+
+    #[test]
+    fn test_dump_ast() {
+        let tokens = lexer::lex("let x: int = 1 + y; x = 2;").unwrap();
+        let stmts = parser::parse(tokens).unwrap();
+        let dump = dump_ast(&stmts);
+        assert_eq!(
+            dump,
+            "#0 Let x\n  #1 Binary Add\n    #2 Integer 1\n    #3 Variable y\n#4 Assignment x\n  #5 Integer 2\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_ast_if() {
+        let tokens = lexer::lex("if (c) { x = 1; } else { x = 2; }").unwrap();
+        let stmts = parser::parse(tokens).unwrap();
+        assert_eq!(
+            dump_ast(&stmts),
+            "#0 If\n  #1 Variable c\n  #2 Then\n    #3 Assignment x\n      #4 Integer 1\n  #5 Else\n    #6 Assignment x\n      #7 Integer 2\n"
+        );
+    }
 }
@@ -0,0 +1,287 @@
+use crate::arith::{ArithError, OverflowPolicy};
+use crate::ast::BinaryOp;
+use crate::intern::{Interner, Symbol};
+use crate::ir::{Instruction, Program};
+use std::collections::HashMap;
+use std::{error::Error, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    PushConst(i64),
+    // Push the value in a slot, or pop the top of the stack into one
+    Load(usize),
+    Store(usize),
+    // Pop the right operand, then the left, and push the result
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug)]
+pub struct BytecodeError {
+    message: String,
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bytecode error: {}", self.message)
+    }
+}
+
+impl Error for BytecodeError {}
+
+// The size of the VM's ints, which can be narrower than the IR's 64 bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntWidth {
+    W32,
+    #[default]
+    W64,
+}
+
+impl IntWidth {
+    pub fn bits(self) -> u32 {
+        match self {
+            IntWidth::W32 => 32,
+            IntWidth::W64 => 64,
+        }
+    }
+
+    fn fits(self, value: i64) -> bool {
+        match self {
+            IntWidth::W32 => i32::try_from(value).is_ok(),
+            IntWidth::W64 => true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BytecodeOptions {
+    pub width: IntWidth,
+}
+
+// Flattens straight-line IR into stack operations. Every value the program
+// defines gets its own slot, numbered in the order the values are defined.
+pub fn compile(program: &Program) -> Result<Vec<Op>, BytecodeError> {
+    compile_with_options(program, &BytecodeOptions::default())
+}
+
+// Like compile, for a VM whose ints are `options.width` wide. The IR folds
+// at 64 bits, so a constant it leaves that doesn't fit is an error.
+pub fn compile_with_options(
+    program: &Program,
+    options: &BytecodeOptions,
+) -> Result<Vec<Op>, BytecodeError> {
+    let mut slots: HashMap<Symbol, usize> = HashMap::new();
+    let mut ops = Vec::new();
+    let load = |operand: Symbol, slots: &HashMap<Symbol, usize>| {
+        load(operand, slots, &program.symbols, options.width)
+    };
+
+    for inst in &program.instructions {
+        match inst {
+            Instruction::Constant { value, .. } => ops.push(constant(*value, options.width)?),
+            Instruction::Copy { source, .. } => ops.push(load(*source, &slots)?),
+            Instruction::Binary {
+                op, left, right, ..
+            } => {
+                let op = match op {
+                    BinaryOp::Add => Op::Add,
+                    BinaryOp::Subtract => Op::Sub,
+                    BinaryOp::Multiply => Op::Mul,
+                    BinaryOp::Divide => Op::Div,
+                    op => {
+                        return Err(BytecodeError {
+                            message: format!("`{}` has no bytecode equivalent", op),
+                        })
+                    }
+                };
+                ops.push(load(*left, &slots)?);
+                ops.push(load(*right, &slots)?);
+                ops.push(op);
+            }
+            inst => {
+                return Err(BytecodeError {
+                    message: format!(
+                        "`{}` has no bytecode equivalent",
+                        inst.display(&program.symbols)
+                    ),
+                })
+            }
+        }
+        // only value-defining instructions get this far
+        let result = inst.result().expect("instruction defines a value");
+        let slot = slots.len();
+        slots.insert(result, slot);
+        ops.push(Op::Store(slot));
+    }
+    Ok(ops)
+}
+
+fn constant(value: i64, width: IntWidth) -> Result<Op, BytecodeError> {
+    if !width.fits(value) {
+        return Err(BytecodeError {
+            message: format!(
+                "constant {} does not fit in a {}-bit int",
+                value,
+                width.bits()
+            ),
+        });
+    }
+    Ok(Op::PushConst(value))
+}
+
+// An operand is either an integer literal or a value defined earlier
+fn load(
+    operand: Symbol,
+    slots: &HashMap<Symbol, usize>,
+    symbols: &Interner,
+    width: IntWidth,
+) -> Result<Op, BytecodeError> {
+    let name = symbols.resolve(operand);
+    if let Ok(value) = name.parse::<i64>() {
+        return constant(value, width);
+    }
+    match slots.get(&operand) {
+        Some(&slot) => Ok(Op::Load(slot)),
+        None => Err(BytecodeError {
+            message: format!("`{}` is used before it is defined", name),
+        }),
+    }
+}
+
+// Runs the ops and returns the final contents of every slot. Arithmetic
+// follows the default overflow policy, the same one constant folding uses.
+pub fn run(ops: &[Op]) -> Result<Vec<i64>, BytecodeError> {
+    run_with_options(ops, &BytecodeOptions::default())
+}
+
+// Like run, with results that don't fit `options.width` overflowing
+pub fn run_with_options(ops: &[Op], options: &BytecodeOptions) -> Result<Vec<i64>, BytecodeError> {
+    let slot_count = ops
+        .iter()
+        .filter_map(|op| match op {
+            Op::Load(slot) | Op::Store(slot) => Some(slot + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let mut slots = vec![0; slot_count];
+    let mut stack = Vec::new();
+    let underflow = || BytecodeError {
+        message: "stack underflow".to_string(),
+    };
+
+    for op in ops {
+        match op {
+            Op::PushConst(value) => stack.push(*value),
+            Op::Load(slot) => stack.push(slots[*slot]),
+            Op::Store(slot) => slots[*slot] = stack.pop().ok_or_else(underflow)?,
+            Op::Add | Op::Sub | Op::Mul | Op::Div => {
+                let right = stack.pop().ok_or_else(underflow)?;
+                let left = stack.pop().ok_or_else(underflow)?;
+                let binary = match op {
+                    Op::Add => BinaryOp::Add,
+                    Op::Sub => BinaryOp::Subtract,
+                    Op::Mul => BinaryOp::Multiply,
+                    _ => BinaryOp::Divide,
+                };
+                let value = OverflowPolicy::default()
+                    .eval(&binary, left, right)
+                    .and_then(|value| {
+                        if options.width.fits(value) {
+                            Ok(value)
+                        } else {
+                            Err(ArithError::Overflow)
+                        }
+                    })
+                    .map_err(|err| BytecodeError {
+                        message: format!("{} computing {} {} {}", err, left, binary, right),
+                    })?;
+                stack.push(value);
+            }
+        }
+    }
+    Ok(slots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, lexer, parser};
+
+    fn compile_source(input: &str) -> Result<Vec<Op>, BytecodeError> {
+        let tokens = lexer::lex(input).unwrap();
+        compile(&ir::lower(parser::parse(tokens).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_compile_and_run() {
+        let ops = compile_source("let x: int = 2 + 3 * 4;").unwrap();
+        assert_eq!(
+            ops,
+            [
+                Op::PushConst(3),
+                Op::PushConst(4),
+                Op::Mul,
+                Op::Store(0),
+                Op::PushConst(2),
+                Op::Load(0),
+                Op::Add,
+                Op::Store(1),
+            ]
+        );
+        // x.1 is the last value defined
+        assert_eq!(run(&ops).unwrap(), [12, 14]);
+    }
+
+    #[test]
+    fn test_unsupported_instruction() {
+        let err = compile_source("let x: int = 7 % 2;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bytecode error: `%` has no bytecode equivalent"
+        );
+    }
+
+    #[test]
+    fn test_32_bit_width() {
+        let options = BytecodeOptions {
+            width: IntWidth::W32,
+        };
+        let program = |input: &str| ir::lower(parser::parse(lexer::lex(input).unwrap()).unwrap());
+        let ops = compile_with_options(&program("let x: int = 2147483647;").unwrap(), &options);
+        assert_eq!(ops.unwrap(), [Op::PushConst(2147483647), Op::Store(0)]);
+
+        // folding is 64 bits wide, so it leaves `x` one past the end of an i32
+        let mut lowered = program("let x: int = 2147483647 + 1;").unwrap();
+        ir::run_pass(&mut lowered, "fold").unwrap();
+        let err = compile_with_options(&lowered, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bytecode error: constant 2147483648 does not fit in a 32-bit int"
+        );
+        let err = compile_with_options(&program("let x: int = 5 - 4294967296;").unwrap(), &options);
+        assert!(err.unwrap_err().to_string().contains("constant 4294967296"));
+        assert!(compile(&program("let x: int = 4294967296;").unwrap()).is_ok());
+
+        let ops = [
+            Op::PushConst(65536),
+            Op::PushConst(65536),
+            Op::Mul,
+            Op::Store(0),
+        ];
+        let err = run_with_options(&ops, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bytecode error: Arithmetic overflow computing 65536 * 65536"
+        );
+        assert_eq!(run(&ops).unwrap(), [4294967296]);
+    }
+
+    #[test]
+    fn test_run_division_by_zero() {
+        let ops = [Op::PushConst(1), Op::PushConst(0), Op::Div, Op::Store(0)];
+        assert!(run(&ops).is_err());
+    }
+}
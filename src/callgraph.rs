@@ -0,0 +1,205 @@
+use crate::ast::{Expr, Statement};
+use crate::span::Spanned;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    // caller -> functions it calls directly; every declared function has an
+    // entry, even when it calls nothing
+    pub edges: BTreeMap<String, BTreeSet<String>>,
+    // functions called from top-level code, outside any function
+    pub roots: BTreeSet<String>,
+}
+
+impl CallGraph {
+    pub fn calls(&self, caller: &str, callee: &str) -> bool {
+        self.edges
+            .get(caller)
+            .is_some_and(|callees| callees.contains(callee))
+    }
+
+    // Every function reachable through one or more calls from `name`
+    pub fn reachable_from(&self, name: &str) -> BTreeSet<String> {
+        let mut reached = BTreeSet::new();
+        let mut pending: Vec<&String> = self.edges.get(name).into_iter().flatten().collect();
+        while let Some(next) = pending.pop() {
+            if reached.insert(next.clone()) {
+                pending.extend(self.edges.get(next).into_iter().flatten());
+            }
+        }
+        reached
+    }
+
+    // True when `name` can end up calling itself, directly or through others
+    pub fn is_recursive(&self, name: &str) -> bool {
+        self.reachable_from(name).contains(name)
+    }
+}
+
+pub fn build_call_graph(statements: &[Spanned<Statement>]) -> CallGraph {
+    let mut graph = CallGraph::default();
+    let mut roots = BTreeSet::new();
+    collect_statements(statements, &mut graph, &mut roots);
+    graph.roots = roots;
+    graph
+}
+
+// Removes top-level functions that can't be reached from `main` or from
+// top-level code, returning a warning naming each one removed
+pub fn eliminate_dead_functions(statements: &mut Vec<Spanned<Statement>>) -> Vec<String> {
+    let graph = build_call_graph(statements);
+    let mut live: BTreeSet<String> = BTreeSet::new();
+    for entry in graph.roots.iter().map(String::as_str).chain(["main"]) {
+        if graph.edges.contains_key(entry) {
+            live.insert(entry.to_string());
+        }
+        live.extend(graph.reachable_from(entry));
+    }
+
+    let mut warnings = Vec::new();
+    statements.retain(|stmt| match &stmt.node {
+        Statement::Function { name, .. } if !live.contains(name) => {
+            warnings.push(format!("Removed unused function `{}`", name));
+            false
+        }
+        _ => true,
+    });
+    warnings
+}
+
+// Records calls made by `statements` into `callees`, and registers any
+// nested function declarations as graph nodes of their own
+fn collect_statements(
+    statements: &[Spanned<Statement>],
+    graph: &mut CallGraph,
+    callees: &mut BTreeSet<String>,
+) {
+    for stmt in statements {
+        match &stmt.node {
+            Statement::Let { value, .. }
+            | Statement::Destructure { value, .. }
+            | Statement::Const { value, .. }
+            | Statement::Assignment { value, .. } => collect_expr(value, callees),
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                collect_expr(condition, callees);
+                collect_statements(then_branch, graph, callees);
+                if let Some(else_branch) = else_branch {
+                    collect_statements(else_branch, graph, callees);
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                collect_expr(condition, callees);
+                collect_statements(body, graph, callees);
+            }
+            Statement::Function { name, body, .. } => {
+                let mut own = BTreeSet::new();
+                collect_statements(body, graph, &mut own);
+                graph.edges.entry(name.clone()).or_default().extend(own);
+            }
+            Statement::Return(value) => {
+                if let Some(value) = value {
+                    collect_expr(value, callees);
+                }
+            }
+            Statement::Expression(expr) | Statement::Result(expr) => collect_expr(expr, callees),
+            Statement::Break(_) | Statement::Continue(_) | Statement::Import(_) => {}
+        }
+    }
+}
+
+fn collect_expr(expr: &Expr, callees: &mut BTreeSet<String>) {
+    match expr {
+        Expr::Integer(..) | Expr::Boolean(_) | Expr::Variable(_) => {}
+        Expr::Binary { left, right, .. } => {
+            collect_expr(left, callees);
+            collect_expr(right, callees);
+        }
+        Expr::Unary { operand, .. } => collect_expr(operand, callees),
+        Expr::Call { callee, args } => {
+            match &callee.node {
+                Expr::Variable(name) => {
+                    callees.insert(name.clone());
+                }
+                callee => collect_expr(callee, callees),
+            }
+            for arg in args {
+                collect_expr(arg, callees);
+            }
+        }
+        Expr::Seq(exprs) | Expr::Tuple(exprs) => {
+            for expr in exprs {
+                collect_expr(expr, callees);
+            }
+        }
+        Expr::Assign { value, .. } | Expr::Named { value, .. } => collect_expr(value, callees),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    fn graph_of(input: &str) -> CallGraph {
+        let tokens = lexer::lex(input).unwrap();
+        build_call_graph(&parser::parse(tokens).unwrap())
+    }
+
+    #[test]
+    fn test_call_edge() {
+        let graph = graph_of(
+            "fn helper() -> int { return 1; }
+             fn main() -> int { return helper() + 1; }",
+        );
+        assert!(graph.calls("main", "helper"));
+        assert!(!graph.calls("helper", "main"));
+        assert!(graph.edges["helper"].is_empty());
+        assert!(!graph.is_recursive("main"));
+    }
+
+    #[test]
+    fn test_recursive_cycle() {
+        let graph = graph_of(
+            "fn countdown(n: int) -> int {
+                 if (n == 0) { return 0; }
+                 return countdown(n - 1);
+             }",
+        );
+        assert!(graph.calls("countdown", "countdown"));
+        assert!(graph.is_recursive("countdown"));
+    }
+
+    #[test]
+    fn test_eliminate_dead_functions() {
+        let tokens = lexer::lex(
+            "fn helper() -> int { return 1; }
+             fn unused() -> int { return helper(); }
+             fn main() -> int { return helper(); }",
+        )
+        .unwrap();
+        let mut statements = parser::parse(tokens).unwrap();
+        let warnings = eliminate_dead_functions(&mut statements);
+        assert_eq!(warnings, ["Removed unused function `unused`"]);
+
+        let remaining: Vec<&str> = statements
+            .iter()
+            .filter_map(|stmt| match &stmt.node {
+                Statement::Function { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(remaining, ["helper", "main"]);
+    }
+
+    #[test]
+    fn test_top_level_calls_are_roots() {
+        let graph = graph_of("fn f() -> int { return 1; } let x: int = f();");
+        assert!(graph.roots.contains("f"));
+    }
+}
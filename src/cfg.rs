@@ -0,0 +1,135 @@
+use crate::intern::Symbol;
+use crate::ir::{Instruction, Program};
+use std::collections::HashMap;
+
+// A run of instructions that control only enters at the top and leaves at
+// the bottom. A block that starts at a label keeps it as its first
+// instruction; `successors` are ids of other blocks in the same Cfg.
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub id: usize,
+    pub instructions: Vec<Instruction>,
+    pub successors: Vec<usize>,
+}
+
+// Blocks in program order, with the entry block first
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    // (from, to) edges going to a block no later in program order. Lowering
+    // puts a loop's header before its body, so these are the loops' back edges.
+    pub fn back_edges(&self) -> Vec<(usize, usize)> {
+        self.blocks
+            .iter()
+            .flat_map(|block| {
+                block
+                    .successors
+                    .iter()
+                    .filter(|&&to| to <= block.id)
+                    .map(|&to| (block.id, to))
+            })
+            .collect()
+    }
+}
+
+// A new block starts at every label and after every jump or branch. A
+// branch has two successors, the target and the block after it, while a
+// jump only goes to its target and an exit goes nowhere; any other block
+// falls through to the next.
+pub fn build_cfg(program: &Program) -> Cfg {
+    let mut blocks: Vec<Vec<Instruction>> = vec![Vec::new()];
+    for inst in &program.instructions {
+        let current = blocks.last_mut().expect("there is always a block");
+        if let Instruction::Label(_) = inst {
+            if !current.is_empty() {
+                blocks.push(Vec::new());
+            }
+        }
+        blocks.last_mut().unwrap().push(inst.clone());
+        if matches!(
+            inst,
+            Instruction::Jump(_) | Instruction::BranchIfZero { .. }
+        ) {
+            blocks.push(Vec::new());
+        }
+    }
+    // a program ending in a jump leaves an empty block behind it
+    if blocks.len() > 1 && blocks.last().is_some_and(Vec::is_empty) {
+        blocks.pop();
+    }
+
+    let labels: HashMap<Symbol, usize> = blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(id, block)| match block.first() {
+            Some(Instruction::Label(label)) => Some((*label, id)),
+            _ => None,
+        })
+        .collect();
+    let count = blocks.len();
+    let blocks = blocks
+        .into_iter()
+        .enumerate()
+        .map(|(id, instructions)| {
+            let next = (id + 1 < count).then_some(id + 1);
+            let successors = match instructions.last() {
+                Some(Instruction::Jump(target)) => vec![labels[target]],
+                Some(Instruction::Exit(_)) => Vec::new(),
+                Some(Instruction::BranchIfZero { target, .. }) => {
+                    let mut successors = vec![labels[target]];
+                    successors.extend(next.filter(|next| *next != labels[target]));
+                    successors
+                }
+                _ => next.into_iter().collect(),
+            };
+            BasicBlock {
+                id,
+                instructions,
+                successors,
+            }
+        })
+        .collect();
+    Cfg { blocks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, lexer, parser};
+
+    fn cfg_of(source: &str) -> Cfg {
+        let tokens = lexer::lex(source).unwrap();
+        build_cfg(&ir::lower(parser::parse(tokens).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_cfg_while_loop() {
+        let cfg = cfg_of("let i: int = 0; while (i < 3) { i = i + 1; } let j: int = i;");
+        // entry, the header, the body and the block after the loop
+        assert_eq!(cfg.blocks.len(), 4);
+        assert_eq!(cfg.blocks[0].successors, [1]);
+        assert_eq!(cfg.blocks[1].successors, [3, 2]);
+        assert_eq!(cfg.blocks[2].successors, [1]);
+        assert!(cfg.blocks[3].successors.is_empty());
+        assert_eq!(cfg.back_edges(), [(2, 1)]);
+    }
+
+    #[test]
+    fn test_cfg_if_else() {
+        let cfg = cfg_of("let c: bool = true; let x: int = 0; if (c) { x = 1; } else { x = 2; }");
+        // entry, then, else and endif
+        assert_eq!(cfg.blocks.len(), 4);
+        assert_eq!(cfg.blocks[0].successors, [2, 1]);
+        assert_eq!(cfg.blocks[1].successors, [3]);
+        assert_eq!(cfg.blocks[2].successors, [3]);
+        assert!(cfg.back_edges().is_empty());
+
+        // straight-line code is a single block
+        let cfg = cfg_of("let a: int = 1; let b: int = a + 1;");
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!(cfg.blocks[0].instructions.len(), 2);
+    }
+}
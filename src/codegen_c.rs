@@ -0,0 +1,593 @@
+use crate::arith::Builtin;
+use crate::ast::BinaryOp;
+use crate::intern::{Interner, Symbol};
+use crate::ir::{Instruction, Program, ENTRY_BLOCK};
+use crate::span::Span;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Default)]
+pub struct EmitOptions {
+    // render constants written as hex literals in hex, as in the source
+    pub hex_constants: bool,
+}
+
+// Translates the program into a standalone C file whose `main` runs it and
+// prints the final value of each top-level variable still in the program,
+// returning the program's exit code if it has one
+pub fn emit(program: &Program) -> String {
+    emit_with_options(program, &EmitOptions::default())
+}
+
+pub fn emit_with_options(program: &Program, options: &EmitOptions) -> String {
+    emit_with_source_map(program, options).0
+}
+
+// Also returns a source map, with an entry for each instruction emitted as a
+// line of C: the 1-based line it is on, and the span of the source it was
+// lowered from when it has one
+pub fn emit_with_source_map(
+    program: &Program,
+    options: &EmitOptions,
+) -> (String, Vec<(usize, Option<Span>)>) {
+    let symbols = &program.symbols;
+    // every defined value but labels, which also count as results
+    let defined: Vec<Symbol> = program
+        .instructions
+        .iter()
+        .filter(|inst| !matches!(inst, Instruction::Label(_)))
+        .filter_map(Instruction::result)
+        .collect();
+    let printed: Vec<(&str, Symbol)> = program
+        .final_values
+        .iter()
+        .filter(|(_, value)| defined.contains(value))
+        .map(|(name, &value)| (name.as_str(), value))
+        .collect();
+    // anything else would be set but never used, which `-Wall` warns about
+    let read = read_values(program, printed.iter().map(|&(_, value)| value));
+    let values: Vec<Symbol> = defined
+        .into_iter()
+        .filter(|value| read.contains(value))
+        .collect();
+
+    // only these get a C label, so `-Wall` has no unused ones to warn about
+    let targets: HashSet<Symbol> = program
+        .instructions
+        .iter()
+        .filter_map(|inst| match inst {
+            Instruction::Jump(target) | Instruction::BranchIfZero { target, .. } => Some(*target),
+            _ => None,
+        })
+        .collect();
+
+    // C has no phi, so each source becomes a copy at the end of the block it
+    // comes from: predecessor label -> (phi result, value)
+    let mut phi_copies: HashMap<Symbol, Vec<(Symbol, Symbol)>> = HashMap::new();
+    for inst in &program.instructions {
+        if let Instruction::Phi { result, sources } = inst {
+            if !read.contains(result) {
+                continue;
+            }
+            for &(value, block) in sources {
+                phi_copies.entry(block).or_default().push((*result, value));
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut source_map = Vec::new();
+    out.push_str("#include <inttypes.h>\n#include <stdio.h>\n\n");
+    if uses_builtin(program, Builtin::Pow) {
+        out.push_str(POW_HELPER);
+    }
+    if uses_op(program, BinaryOp::SaturatingAdd) {
+        out.push_str(SATURATING_ADD_HELPER);
+    }
+    out.push_str("int main(void) {\n");
+    for &value in &values {
+        out.push_str(&format!("    int64_t {} = 0;\n", c_name(symbols, value)));
+    }
+    if !values.is_empty() {
+        out.push('\n');
+    }
+
+    // a program with no labels never interns the entry block's name
+    let mut block = symbols.get(ENTRY_BLOCK);
+    let mut block_left = false;
+    let mut exit_code = "0".to_string();
+    for inst in &program.instructions {
+        if matches!(
+            inst,
+            Instruction::Label(_) | Instruction::Jump(_) | Instruction::BranchIfZero { .. }
+        ) && !block_left
+        {
+            block_left = true;
+            let copies = block.and_then(|block| phi_copies.get(&block));
+            emit_phi_copies(symbols, copies.map_or(&[], Vec::as_slice), &mut out);
+        }
+        if inst.result().is_some_and(|result| !read.contains(&result))
+            && !matches!(inst, Instruction::Label(_))
+        {
+            continue;
+        }
+        let line = match inst {
+            Instruction::Constant { result, value, .. } => {
+                let value = if options.hex_constants && program.hex_constants.contains(result) {
+                    format!("{:#X}", value)
+                } else {
+                    literal(*value)
+                };
+                format!("{} = {};", c_name(symbols, *result), value)
+            }
+            Instruction::Binary {
+                result,
+                op,
+                left,
+                right,
+                ..
+            } => {
+                let (left, right) = (operand(symbols, *left), operand(symbols, *right));
+                let value = match op {
+                    // unsigned arithmetic wraps instead of being undefined
+                    BinaryOp::WrappingAdd => {
+                        format!("(int64_t)((uint64_t){} + (uint64_t){})", left, right)
+                    }
+                    BinaryOp::SaturatingAdd => {
+                        format!("crucible_saturating_add({}, {})", left, right)
+                    }
+                    op => format!("{} {} {}", left, op, right),
+                };
+                format!("{} = {};", c_name(symbols, *result), value)
+            }
+            Instruction::Copy { result, source, .. } => {
+                format!(
+                    "{} = {};",
+                    c_name(symbols, *result),
+                    operand(symbols, *source)
+                )
+            }
+            Instruction::Call {
+                result,
+                builtin,
+                args,
+                ..
+            } => {
+                let args: Vec<String> = args.iter().map(|&arg| operand(symbols, arg)).collect();
+                let call = match (builtin, &args[..]) {
+                    (Builtin::Abs, [value]) => format!("{0} < 0 ? -{0} : {0}", value),
+                    (Builtin::Pow, [base, exponent]) => {
+                        format!("crucible_pow({}, {})", base, exponent)
+                    }
+                    _ => unreachable!("lowering checks builtin arity, and selects min and max"),
+                };
+                format!("{} = {};", c_name(symbols, *result), call)
+            }
+            Instruction::Select {
+                result,
+                cond,
+                then_val,
+                else_val,
+                ..
+            } => format!(
+                "{} = {} ? {} : {};",
+                c_name(symbols, *result),
+                operand(symbols, *cond),
+                operand(symbols, *then_val),
+                operand(symbols, *else_val)
+            ),
+            // copied in at the end of each predecessor instead
+            Instruction::Phi { .. } => continue,
+            // the empty statement lets a label end the function body
+            Instruction::Label(name) => {
+                block = Some(*name);
+                block_left = false;
+                if targets.contains(name) {
+                    out.push_str(&format!("{}:;\n", c_name(symbols, *name)));
+                }
+                continue;
+            }
+            Instruction::Jump(target) => format!("goto {};", c_name(symbols, *target)),
+            // returned once the variables are printed
+            Instruction::Exit(value) => {
+                exit_code = format!("(int){}", operand(symbols, *value));
+                continue;
+            }
+            Instruction::BranchIfZero { cond, target } => {
+                format!(
+                    "if ({} == 0) goto {};",
+                    operand(symbols, *cond),
+                    c_name(symbols, *target)
+                )
+            }
+        };
+        source_map.push((out.matches('\n').count() + 1, inst.span()));
+        out.push_str(&format!("    {}\n", line));
+    }
+
+    if !printed.is_empty() {
+        out.push('\n');
+    }
+    for (name, value) in printed {
+        out.push_str(&format!(
+            "    printf(\"{} = %\" PRId64 \"\\n\", {});\n",
+            name,
+            c_name(symbols, value)
+        ));
+    }
+    out.push_str(&format!("    return {};\n}}\n", exit_code));
+    (out, source_map)
+}
+
+// The values the program's output depends on: those printed at the end, the
+// exit code, branch conditions, and whatever the values read are computed
+// from, found by sweeping until nothing new turns up
+fn read_values(program: &Program, printed: impl Iterator<Item = Symbol>) -> HashSet<Symbol> {
+    let mut read: HashSet<Symbol> = printed.collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for inst in &program.instructions {
+            let needed = match inst.result() {
+                Some(result) => read.contains(&result),
+                None => true,
+            };
+            if needed {
+                for operand in inst.operands() {
+                    changed |= read.insert(operand);
+                }
+            }
+        }
+    }
+    read
+}
+
+// Phis all take their values as control leaves the block, so when one reads
+// what another is assigned, as when a loop swaps two variables, the values
+// are staged in temporaries first. A phi whose value comes back unchanged
+// needs no copy at all.
+fn emit_phi_copies(symbols: &Interner, copies: &[(Symbol, Symbol)], out: &mut String) {
+    let copies: Vec<(Symbol, Symbol)> = copies
+        .iter()
+        .copied()
+        .filter(|(result, value)| result != value)
+        .collect();
+    let overlapping = copies
+        .iter()
+        .any(|(_, value)| copies.iter().any(|(result, _)| result == value));
+    if !overlapping {
+        for &(result, value) in &copies {
+            out.push_str(&format!(
+                "    {} = {};\n",
+                c_name(symbols, result),
+                operand(symbols, value)
+            ));
+        }
+        return;
+    }
+    out.push_str("    {\n");
+    for (index, &(_, value)) in copies.iter().enumerate() {
+        out.push_str(&format!(
+            "        int64_t phi_{} = {};\n",
+            index,
+            operand(symbols, value)
+        ));
+    }
+    for (index, &(result, _)) in copies.iter().enumerate() {
+        out.push_str(&format!(
+            "        {} = phi_{};\n",
+            c_name(symbols, result),
+            index
+        ));
+    }
+    out.push_str("    }\n");
+}
+
+// Exponents are never negative here; constant folding rejects those
+const POW_HELPER: &str = "static int64_t crucible_pow(int64_t base, int64_t exponent) {
+    int64_t result = 1;
+    while (exponent-- > 0) {
+        result *= base;
+    }
+    return result;
+}
+
+";
+
+const SATURATING_ADD_HELPER: &str = "static int64_t crucible_saturating_add(int64_t a, int64_t b) {
+    if (b > 0 && a > INT64_MAX - b) {
+        return INT64_MAX;
+    }
+    if (b < 0 && a < INT64_MIN - b) {
+        return INT64_MIN;
+    }
+    return a + b;
+}
+
+";
+
+fn uses_op(program: &Program, op: BinaryOp) -> bool {
+    program
+        .instructions
+        .iter()
+        .any(|inst| matches!(inst, Instruction::Binary { op: used, .. } if *used == op))
+}
+
+fn uses_builtin(program: &Program, builtin: Builtin) -> bool {
+    program
+        .instructions
+        .iter()
+        .any(|inst| matches!(inst, Instruction::Call { builtin: used, .. } if *used == builtin))
+}
+
+// `x.1` becomes `x_1`. Versions are all digits, so the last `_` still tells
+// the base from the version and no two names collide.
+fn c_name(symbols: &Interner, name: Symbol) -> String {
+    symbols.resolve(name).replace('.', "_")
+}
+
+// Operands are either value names or integer literals. Negative literals
+// are parenthesized so `x - -1` can't run together into `x --1`.
+fn operand(symbols: &Interner, operand: Symbol) -> String {
+    match symbols.resolve(operand).parse::<i64>() {
+        Ok(value) if value < 0 => format!("({})", literal(value)),
+        Ok(value) => literal(value),
+        Err(_) => c_name(symbols, operand),
+    }
+}
+
+// `-9223372036854775808` isn't a valid C literal, since the negation applies
+// to a literal that doesn't fit in int64_t
+fn literal(value: i64) -> String {
+    if value == i64::MIN {
+        "INT64_MIN".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit_two_instructions() {
+        let mut program = Program::new();
+        let mut name = |text: &str| program.intern(text);
+        let instructions = vec![
+            Instruction::Constant {
+                result: name("x.1"),
+                value: 3,
+                span: None,
+            },
+            Instruction::Binary {
+                result: name("y.1"),
+                op: BinaryOp::Multiply,
+                left: name("x.1"),
+                right: name("2"),
+                span: None,
+            },
+            Instruction::Constant {
+                result: name("y.2"),
+                value: 0,
+                span: None,
+            },
+        ];
+        let y = name("y.1");
+        program.instructions = instructions;
+        program.final_values.insert("y".to_string(), y);
+        let c = emit(&program);
+        assert!(c.contains("int64_t x_1 = 0;\n"));
+        assert!(c.contains("int64_t y_1 = 0;\n"));
+        assert!(c.contains("    x_1 = 3;\n    y_1 = x_1 * 2;\n"));
+        assert!(c.contains(r#"printf("y = %" PRId64 "\n", y_1);"#));
+        assert!(!c.contains("crucible_pow"));
+        // nothing reads y.2, so `cc -Wall` would call it set but not used
+        assert!(!c.contains("y_2"));
+    }
+
+    #[test]
+    fn test_emit_phi_as_copies() {
+        let mut program = Program::new();
+        let mut name = |text: &str| program.intern(text);
+        let instructions = vec![
+            Instruction::BranchIfZero {
+                cond: name("c.1"),
+                target: name("else.1"),
+            },
+            Instruction::Label(name("then.1")),
+            Instruction::Jump(name("endif.1")),
+            Instruction::Label(name("else.1")),
+            Instruction::Label(name("endif.1")),
+            Instruction::Phi {
+                result: name("x.3"),
+                sources: vec![(name("x.1"), name("then.1")), (name("x.2"), name("else.1"))],
+            },
+        ];
+        let x = name("x.3");
+        program.instructions = instructions;
+        program.final_values.insert("x".to_string(), x);
+        let c = emit(&program);
+        assert!(c.contains("goto else_1;\n    x_3 = x_1;\n    goto endif_1;\n"));
+        assert!(c.contains("else_1:;\n    x_3 = x_2;\nendif_1:;\n"));
+    }
+
+    #[test]
+    fn test_emit_swapping_phis() {
+        let mut program = Program::new();
+        let mut name = |text: &str| program.intern(text);
+        // a loop whose a and b come back around as each other's old values
+        let instructions = vec![
+            Instruction::Constant {
+                result: name("a.1"),
+                value: 1,
+                span: None,
+            },
+            Instruction::Constant {
+                result: name("b.1"),
+                value: 2,
+                span: None,
+            },
+            Instruction::Label(name("while.1")),
+            Instruction::Phi {
+                result: name("a.2"),
+                sources: vec![(name("a.1"), name("entry")), (name("b.2"), name("do.1"))],
+            },
+            Instruction::Phi {
+                result: name("b.2"),
+                sources: vec![(name("b.1"), name("entry")), (name("a.2"), name("do.1"))],
+            },
+            Instruction::Label(name("do.1")),
+            Instruction::Jump(name("while.1")),
+        ];
+        let (a, b) = (name("a.2"), name("b.2"));
+        program.instructions = instructions;
+        program.final_values.insert("a".to_string(), a);
+        program.final_values.insert("b".to_string(), b);
+        let c = emit(&program);
+        assert!(c.contains("    a_2 = a_1;\n    b_2 = b_1;\n"));
+        assert!(c.contains(
+            "    {
+        int64_t phi_0 = b_2;
+        int64_t phi_1 = a_2;
+        a_2 = phi_0;
+        b_2 = phi_1;
+    }
+    goto while_1;
+"
+        ));
+    }
+
+    #[test]
+    fn test_emit_hex_constants() {
+        let tokens = crate::lexer::lex("let mask: int = 0xFF00; let low: int = 255;").unwrap();
+        let program = crate::ir::lower(crate::parser::parse(tokens).unwrap()).unwrap();
+        let options = EmitOptions {
+            hex_constants: true,
+        };
+        let c = emit_with_options(&program, &options);
+        assert!(c.contains("    mask_1 = 0xFF00;\n    low_1 = 255;\n"));
+        assert!(emit(&program).contains("    mask_1 = 65280;\n"));
+    }
+
+    #[test]
+    fn test_source_map() {
+        let source = "let a: int = 1;\nlet b: int = a * 2;\nif (b > 1) { b = 0; }";
+        let tokens = crate::lexer::lex(source).unwrap();
+        let program = crate::ir::lower(crate::parser::parse(tokens).unwrap()).unwrap();
+        let (c, source_map) = emit_with_source_map(&program, &EmitOptions::default());
+        let lines: Vec<&str> = c.lines().collect();
+        let entries: Vec<(&str, Option<&str>)> = source_map
+            .iter()
+            .map(|&(line, span)| {
+                let span = span.map(|span| &source[span.start..span.end]);
+                (lines[line - 1].trim(), span)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("a_1 = 1;", Some("1")),
+                ("b_1 = a_1 * 2;", Some("a * 2")),
+                ("bin_1 = b_1 > 1;", Some("b > 1")),
+                ("if (bin_1 == 0) goto endif_1;", None),
+                ("b_2 = 0;", Some("0")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_exit_code() {
+        let tokens = crate::lexer::lex("fn main() -> int { return 42; }").unwrap();
+        let program = crate::ir::lower(crate::parser::parse(tokens).unwrap()).unwrap();
+        let c = emit(&program);
+        assert!(c.ends_with("    return (int)42;\n}\n"));
+    }
+
+    // Builds the C with warnings as errors and runs it, returning what it
+    // printed and its exit code, or None when there's no `cc` to build with
+    fn build_and_run(test: &str, c: &str) -> Option<(String, i32)> {
+        use std::process::Command;
+        let dir = std::env::temp_dir();
+        let base = format!("crucible-c-{}-{}", test, std::process::id());
+        let (source, binary) = (dir.join(format!("{}.c", base)), dir.join(base));
+        std::fs::write(&source, c).unwrap();
+        let built = Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .output();
+        std::fs::remove_file(&source).unwrap();
+        let built = built.ok()?;
+        assert!(
+            built.status.success(),
+            "{}\n{}",
+            String::from_utf8_lossy(&built.stderr),
+            c
+        );
+        let ran = Command::new(&binary).output().unwrap();
+        std::fs::remove_file(&binary).unwrap();
+        let printed = String::from_utf8(ran.stdout).unwrap();
+        Some((printed, ran.status.code().unwrap()))
+    }
+
+    #[test]
+    fn test_emitted_c_builds_without_warnings() {
+        let programs = [
+            "let c: bool = true; let x: int = 0;
+             if (c) { x = 1; } else { x = 2; }
+             let y: int = x + 1;",
+            "let x: int = 1; if (x > 0) { let x: int = 5; x = x + 1; } let y: int = x;",
+            "let i: int = 0; let s: int = 0;
+             while (i < 5) {
+                 i = i + 1;
+                 if (i == 2) { continue; }
+                 if (i == 4) { break; }
+                 s = s + i;
+             }",
+            "let big: int = 9223372036854775807;
+             let p: int = pow(3, 4) + abs(0 - 5);
+             let sat: int = big +| 1; let wrapped: int = big +% 1;",
+            "let i: int = 0; let low: int = 9; let high: int = 0;
+             while (i < 4) { low = min(low, i * 3); high = max(high, i * 3); i = i + 1; }",
+            "let total: int = 0;
+             fn add(n: int) -> void { total = total + n; }
+             fn main() -> int {
+                 let i: int = 0;
+                 while (i < 5) { add(i); i = i + 1; }
+                 if (total > 5) { return total; }
+                 return 0;
+             }",
+        ];
+        for (index, source) in programs.iter().enumerate() {
+            let parse =
+                |source: &str| crate::parser::parse(crate::lexer::lex(source).unwrap()).unwrap();
+            let ast = parse(source);
+            // the variables as main leaves them, when there is one
+            let (env, code) = match crate::interp::run(&ast) {
+                Ok(code) => {
+                    let ran = format!("{} let exit_code: int = main();", source);
+                    (crate::interp::eval(&parse(&ran)).unwrap(), code)
+                }
+                Err(_) => (crate::interp::eval(&ast).unwrap(), 0),
+            };
+            let lowered = crate::ir::lower(ast).unwrap();
+            let optimized = crate::driver::compile(source).unwrap();
+            for (program, all_printed) in [(lowered, true), (optimized, false)] {
+                let Some((printed, exit_code)) = build_and_run(&index.to_string(), &emit(&program))
+                else {
+                    eprintln!("skipping: no cc to build the generated C with");
+                    return;
+                };
+                if all_printed {
+                    assert_eq!(printed.lines().count(), program.final_values.len());
+                }
+                // whatever survives optimizing has the value the interpreter gives it
+                for line in printed.lines() {
+                    let (name, value) = line.split_once(" = ").unwrap();
+                    assert_eq!(value.parse::<i64>().unwrap(), env[name], "{}", source);
+                }
+                assert_eq!(i64::from(exit_code), code, "{}", source);
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use crate::arith::OverflowPolicy;
+use crate::ast::{positional, Expr, Radix, Statement, Type};
+use crate::interp;
+use crate::span::Spanned;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug)]
+pub struct ConstEvalOptions {
+    pub policy: OverflowPolicy,
+    // how many expressions a single call may evaluate before it's left for
+    // the program to make at runtime
+    pub step_limit: u64,
+}
+
+impl Default for ConstEvalOptions {
+    fn default() -> Self {
+        ConstEvalOptions {
+            policy: OverflowPolicy::default(),
+            step_limit: 1000,
+        }
+    }
+}
+
+// Replaces each call of a top-level function with literal arguments by the
+// value it returns, found by running the call in the interpreter. A function
+// that reads or assigns a top-level variable can't run on its own, so only
+// pure ones are folded; so are only those declared `-> int` or `-> bool`,
+// which say what kind of literal to leave. Returns whether anything changed.
+pub fn const_eval(statements: &mut [Spanned<Statement>]) -> bool {
+    const_eval_with_options(statements, &ConstEvalOptions::default())
+}
+
+pub fn const_eval_with_options(
+    statements: &mut [Spanned<Statement>],
+    options: &ConstEvalOptions,
+) -> bool {
+    // the calls run against the program as it was
+    let program = statements.to_vec();
+    let returns = program
+        .iter()
+        .filter_map(|stmt| match &stmt.node {
+            Statement::Function {
+                name,
+                params,
+                return_type: Some(typ @ (Type::Int | Type::Bool)),
+                ..
+            } => {
+                let params = params.iter().map(|(param, _)| param.as_str()).collect();
+                Some((name.clone(), (typ.clone(), params)))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut folder = Folder {
+        program: &program,
+        returns,
+        shadowed: HashSet::new(),
+        options,
+        changed: false,
+    };
+    folder.block(statements, true);
+    folder.changed
+}
+
+struct Folder<'a> {
+    program: &'a [Spanned<Statement>],
+    // the top-level functions that can be folded, with their return types
+    // and parameter names
+    returns: HashMap<String, (Type, Vec<&'a str>)>,
+    // names a nested function declared in an enclosing block hides the
+    // top-level function of
+    shadowed: HashSet<String>,
+    options: &'a ConstEvalOptions,
+    changed: bool,
+}
+
+impl Folder<'_> {
+    fn block(&mut self, statements: &mut [Spanned<Statement>], top_level: bool) {
+        let mut hidden = Vec::new();
+        for stmt in statements.iter() {
+            if let Statement::Function { name, .. } = &stmt.node {
+                if !top_level && self.shadowed.insert(name.clone()) {
+                    hidden.push(name.clone());
+                }
+            }
+        }
+        for stmt in statements.iter_mut() {
+            self.statement(&mut stmt.node);
+        }
+        for name in hidden {
+            self.shadowed.remove(&name);
+        }
+    }
+
+    fn statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::Let { value, .. }
+            | Statement::Destructure { value, .. }
+            | Statement::Const { value, .. }
+            | Statement::Assignment { value, .. }
+            | Statement::Return(Some(value))
+            | Statement::Expression(value)
+            | Statement::Result(value) => self.expr(value),
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.block(then_branch, false);
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch, false);
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                self.expr(condition);
+                self.block(body, false);
+            }
+            Statement::Function { body, .. } => self.block(body, false),
+            Statement::Return(None)
+            | Statement::Break(_)
+            | Statement::Continue(_)
+            | Statement::Import(_) => {}
+        }
+    }
+
+    // Arguments are folded first, so a call whose arguments are themselves
+    // foldable calls folds too
+    fn expr(&mut self, expr: &mut Spanned<Expr>) {
+        let folded = match &mut expr.node {
+            Expr::Binary { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+                None
+            }
+            Expr::Unary { operand, .. } => {
+                self.expr(operand);
+                None
+            }
+            Expr::Call { callee, args } => {
+                args.iter_mut().for_each(|arg| self.expr(arg));
+                self.call(callee, args)
+            }
+            Expr::Seq(exprs) | Expr::Tuple(exprs) => {
+                exprs.iter_mut().for_each(|expr| self.expr(expr));
+                None
+            }
+            Expr::Assign { value, .. } | Expr::Named { value, .. } => {
+                self.expr(value);
+                None
+            }
+            Expr::Integer(..) | Expr::Boolean(_) | Expr::Variable(_) => None,
+        };
+        if let Some(folded) = folded {
+            expr.node = folded;
+            self.changed = true;
+        }
+    }
+
+    fn call(&self, callee: &Spanned<Expr>, args: &[Spanned<Expr>]) -> Option<Expr> {
+        let Expr::Variable(name) = &callee.node else {
+            return None;
+        };
+        if self.shadowed.contains(name) {
+            return None;
+        }
+        let (typ, params) = self.returns.get(name)?;
+        let values = args
+            .iter()
+            .map(|arg| match &arg.node {
+                Expr::Named { value, .. } => &value.node,
+                arg => arg,
+            })
+            .map(|arg| match *arg {
+                Expr::Integer(value, _) => Some(value),
+                Expr::Boolean(value) => Some(value as i64),
+                _ => None,
+            })
+            .collect::<Option<Vec<i64>>>()?;
+        let args = positional(name, args, values, params).ok()?;
+        let options = self.options;
+        let value =
+            interp::call_bounded(self.program, name, args, options.policy, options.step_limit)
+                .ok()?;
+        Some(match *typ {
+            Type::Bool => Expr::Boolean(value != 0),
+            _ => Expr::Integer(value, Radix::Decimal),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    fn fold_source(input: &str, options: &ConstEvalOptions) -> Vec<Spanned<Statement>> {
+        let tokens = lexer::lex(input).unwrap();
+        let mut statements = parser::parse(tokens).unwrap();
+        const_eval_with_options(&mut statements, options);
+        statements
+    }
+
+    // The initializer of the top-level `let` at `index`, as text
+    fn initializer(statements: &[Spanned<Statement>], index: usize) -> String {
+        match &statements[index].node {
+            Statement::Let { value, .. } => value.to_string(),
+            stmt => panic!("expected a let, got {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn test_fold_pure_calls() {
+        let statements = fold_source(
+            "fn double(n: int) -> int { return n * 2; }
+             fn even(n: int) -> bool { return n % 2 == 0; }
+             let a: int = double(5);
+             let b: int = double(double(1)) + 1;
+             let c: bool = even(3);
+             fn minus(a: int, b: int) -> int { return a - b; }
+             let d: int = minus(b: 1, a: 10);",
+            &ConstEvalOptions::default(),
+        );
+        assert_eq!(initializer(&statements, 2), "10");
+        assert_eq!(initializer(&statements, 3), "(4 + 1)");
+        assert_eq!(initializer(&statements, 4), "false");
+        assert_eq!(initializer(&statements, 6), "9");
+    }
+
+    #[test]
+    fn test_calls_left_alone() {
+        let statements = fold_source(
+            "let total: int = 1;
+             fn add_total(n: int) -> int { return n + total; }
+             fn spin(n: int) -> int { while (n > 0) { n = n - 1; } return n; }
+             fn double(n: int) -> int { return n * 2; }
+             let a: int = add_total(1);
+             let b: int = spin(1000000);
+             let c: int = double(total);
+             let d: int = spin(3);",
+            &ConstEvalOptions::default(),
+        );
+        // reads a top-level variable, runs past the step limit, or isn't
+        // given a literal
+        assert_eq!(initializer(&statements, 4), "add_total(1)");
+        assert_eq!(initializer(&statements, 5), "spin(1000000)");
+        assert_eq!(initializer(&statements, 6), "double(total)");
+        assert_eq!(initializer(&statements, 7), "0");
+
+        let options = ConstEvalOptions {
+            step_limit: 5,
+            ..ConstEvalOptions::default()
+        };
+        let statements = fold_source(
+            "fn spin(n: int) -> int { while (n > 0) { n = n - 1; } return n; }
+             let d: int = spin(3);",
+            &options,
+        );
+        assert_eq!(initializer(&statements, 1), "spin(3)");
+
+        // recursion too deep to run safely in the compiler
+        let statements = fold_source(
+            "fn down(n: int) -> int { if (n == 0) { return 0; } return down(n - 1); }
+             let a: int = down(10);
+             let b: int = down(100);",
+            &ConstEvalOptions::default(),
+        );
+        assert_eq!(initializer(&statements, 1), "0");
+        assert_eq!(initializer(&statements, 2), "down(100)");
+    }
+
+    #[test]
+    fn test_nested_function_hides_top_level_one() {
+        let mut statements = fold_source(
+            "fn f() -> int { return 1; }
+             fn g() -> int { fn f() -> int { return 2; } return f(); }",
+            &ConstEvalOptions::default(),
+        );
+        let Statement::Function { body, .. } = &mut statements[1].node else {
+            unreachable!();
+        };
+        assert!(
+            matches!(&body[1].node, Statement::Return(Some(value)) if value.to_string() == "f()")
+        );
+    }
+}
@@ -0,0 +1,794 @@
+use crate::arith::OverflowPolicy;
+use crate::ast::{Statement, Type};
+use crate::const_eval::{self, ConstEvalOptions};
+use crate::ir::{self, Program};
+use crate::span::Spanned;
+use crate::{callgraph, interp, lexer, parser, preprocess, typecheck};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+#[derive(Debug)]
+pub struct ImportError {
+    message: String,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Import error: {}", self.message)
+    }
+}
+
+impl Error for ImportError {}
+
+#[derive(Debug)]
+pub struct EntryPointError {
+    message: String,
+}
+
+impl fmt::Display for EntryPointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Entry point error: {}", self.message)
+    }
+}
+
+impl Error for EntryPointError {}
+
+// Runs the whole pipeline, from source text to optimized IR. Warnings from
+// every stage are collected in the returned program. Imports are resolved
+// relative to the working directory.
+pub fn compile(source: &str) -> Result<Program, Box<dyn Error>> {
+    compile_with_options(source, &CompileOptions::default())
+}
+
+#[derive(Debug, Default)]
+pub struct CompileOptions {
+    // defined for `#if` guards in the source and everything it imports
+    pub flags: HashSet<String>,
+    // how constant folding treats arithmetic that overflows
+    pub overflow_policy: OverflowPolicy,
+}
+
+pub fn compile_with_options(
+    source: &str,
+    options: &CompileOptions,
+) -> Result<Program, Box<dyn Error>> {
+    let flags = &options.flags;
+    let ast = parse_source(source, flags)?;
+    let ast = resolve_imports(ast, Path::new("."), flags, &mut Imports::default())?;
+    compile_statements(ast, options)
+}
+
+// Like compile, for the program in the file at `path`; its imports are
+// resolved relative to the directory it's in
+pub fn compile_file(path: &Path) -> Result<Program, Box<dyn Error>> {
+    compile_file_with_options(path, &CompileOptions::default())
+}
+
+pub fn compile_file_with_options(
+    path: &Path,
+    options: &CompileOptions,
+) -> Result<Program, Box<dyn Error>> {
+    let ast = load_file(path, &options.flags, &mut Imports::default())?;
+    compile_statements(ast, options)
+}
+
+// Runs the pipeline up to lowering, leaving the IR unoptimized
+pub fn lower_with_options(
+    source: &str,
+    options: &CompileOptions,
+) -> Result<Program, Box<dyn Error>> {
+    let flags = &options.flags;
+    let ast = parse_source(source, flags)?;
+    let ast = resolve_imports(ast, Path::new("."), flags, &mut Imports::default())?;
+    lower_statements(ast, options)
+}
+
+// Like lower_with_options, for the program in the file at `path`
+pub fn lower_file_with_options(
+    path: &Path,
+    options: &CompileOptions,
+) -> Result<Program, Box<dyn Error>> {
+    let ast = load_file(path, &options.flags, &mut Imports::default())?;
+    lower_statements(ast, options)
+}
+
+fn parse_source(
+    source: &str,
+    flags: &HashSet<String>,
+) -> Result<Vec<Spanned<Statement>>, Box<dyn Error>> {
+    let source = preprocess::preprocess(source, flags)?;
+    let tokens = lexer::lex(&source)?;
+    parser::parse(tokens)
+}
+
+fn compile_statements(
+    ast: Vec<Spanned<Statement>>,
+    options: &CompileOptions,
+) -> Result<Program, Box<dyn Error>> {
+    let mut program = lower_statements(ast, options)?;
+    ir::optimize(&mut program, options.overflow_policy, false)?;
+    Ok(program)
+}
+
+fn lower_statements(
+    mut ast: Vec<Spanned<Statement>>,
+    options: &CompileOptions,
+) -> Result<Program, Box<dyn Error>> {
+    let mut warnings = typecheck::check(&ast)?;
+    entry_point(&ast)?;
+    // a function whose every call folds is then as good as unused
+    let const_eval_options = ConstEvalOptions {
+        policy: options.overflow_policy,
+        ..ConstEvalOptions::default()
+    };
+    const_eval::const_eval_with_options(&mut ast, &const_eval_options);
+    warnings.extend(callgraph::eliminate_dead_functions(&mut ast));
+    let mut program = ir::lower(ast)?;
+    program.warnings.splice(0..0, warnings);
+    Ok(program)
+}
+
+// Whether the program has a top-level `main`, which is where execution
+// starts. One that exists has to be declared as `fn main() -> int`.
+fn entry_point(ast: &[Spanned<Statement>]) -> Result<bool, EntryPointError> {
+    let main = ast.iter().find_map(|stmt| match &stmt.node {
+        Statement::Function {
+            name,
+            params,
+            return_type,
+            ..
+        } if name == "main" => Some((params, return_type)),
+        _ => None,
+    });
+    match main {
+        None => Ok(false),
+        Some((params, Some(Type::Int))) if params.is_empty() => Ok(true),
+        Some((params, return_type)) => {
+            let params: Vec<String> = params
+                .iter()
+                .map(|(name, typ)| format!("{}: {}", name, typ))
+                .collect();
+            let return_type = return_type
+                .as_ref()
+                .map_or(String::new(), |typ| format!(" -> {}", typ));
+            Err(EntryPointError {
+                message: format!(
+                    "`main` must be declared as `fn main() -> int`, not `fn main({}){}`",
+                    params.join(", "),
+                    return_type
+                ),
+            })
+        }
+    }
+}
+
+// Runs the program in the interpreter, starting at its `main` once the
+// top-level statements have run, and returns the exit code `main` returns
+pub fn run(source: &str, options: &CompileOptions) -> Result<i64, Box<dyn Error>> {
+    let ast = parse_source(source, &options.flags)?;
+    let ast = resolve_imports(ast, Path::new("."), &options.flags, &mut Imports::default())?;
+    run_statements(&ast, options)
+}
+
+// Like run, for the program in the file at `path`
+pub fn run_file(path: &Path, options: &CompileOptions) -> Result<i64, Box<dyn Error>> {
+    let ast = load_file(path, &options.flags, &mut Imports::default())?;
+    run_statements(&ast, options)
+}
+
+fn run_statements(
+    ast: &[Spanned<Statement>],
+    options: &CompileOptions,
+) -> Result<i64, Box<dyn Error>> {
+    typecheck::check(ast)?;
+    if !entry_point(ast)? {
+        return Err(Box::new(EntryPointError {
+            message: "there is no `main` function to run; declare `fn main() -> int`".to_string(),
+        }));
+    }
+    Ok(interp::run_with_policy(ast, options.overflow_policy)?)
+}
+
+// Runs just the top-level statements in the interpreter, returning the value
+// of the expression that ends the program without a `;`, if there is one
+pub fn eval(source: &str, options: &CompileOptions) -> Result<Option<i64>, Box<dyn Error>> {
+    let ast = parse_source(source, &options.flags)?;
+    let ast = resolve_imports(ast, Path::new("."), &options.flags, &mut Imports::default())?;
+    typecheck::check(&ast)?;
+    Ok(interp::eval_result_with_policy(
+        &ast,
+        options.overflow_policy,
+    )?)
+}
+
+// Like eval, for the program in the file at `path`
+pub fn eval_file(path: &Path, options: &CompileOptions) -> Result<Option<i64>, Box<dyn Error>> {
+    let ast = load_file(path, &options.flags, &mut Imports::default())?;
+    typecheck::check(&ast)?;
+    Ok(interp::eval_result_with_policy(
+        &ast,
+        options.overflow_policy,
+    )?)
+}
+
+#[derive(Default)]
+struct Imports {
+    // files being loaded, outermost first; meeting one again is a cycle
+    stack: Vec<PathBuf>,
+    // files already merged in, which later imports of them skip
+    loaded: HashSet<PathBuf>,
+}
+
+fn load_file(
+    path: &Path,
+    flags: &HashSet<String>,
+    imports: &mut Imports,
+) -> Result<Vec<Spanned<Statement>>, Box<dyn Error>> {
+    let path = fs::canonicalize(path)
+        .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    let source = fs::read_to_string(&path)
+        .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    let ast = parse_source(&source, flags)?;
+
+    imports.stack.push(path.clone());
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let ast = resolve_imports(ast, dir, flags, imports);
+    imports.stack.pop();
+    imports.loaded.insert(path);
+    ast
+}
+
+// Replaces each top-level import with the items of the file it names
+fn resolve_imports(
+    ast: Vec<Spanned<Statement>>,
+    dir: &Path,
+    flags: &HashSet<String>,
+    imports: &mut Imports,
+) -> Result<Vec<Spanned<Statement>>, Box<dyn Error>> {
+    let mut resolved = Vec::new();
+    for stmt in ast {
+        let Statement::Import(name) = stmt.node else {
+            resolved.push(stmt);
+            continue;
+        };
+        let path = dir.join(&name);
+        if let Ok(canonical) = fs::canonicalize(&path) {
+            if let Some(start) = imports.stack.iter().position(|file| *file == canonical) {
+                let cycle: Vec<String> = imports.stack[start..]
+                    .iter()
+                    .chain([&canonical])
+                    .map(|file| file_name(file))
+                    .collect();
+                return Err(Box::new(ImportError {
+                    message: format!("import cycle: {}", cycle.join(" -> ")),
+                }));
+            }
+            if imports.loaded.contains(&canonical) {
+                continue;
+            }
+        }
+        resolved.extend(load_file(&path, flags, imports)?);
+    }
+    Ok(resolved)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+// Like compile, but reuses the program from an earlier call with the same
+// source. Errors aren't cached, so failing sources are recompiled each time.
+pub fn compile_cached(
+    source: &str,
+    cache: &mut HashMap<u64, Program>,
+) -> Result<Program, Box<dyn Error>> {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    let key = hasher.finish();
+
+    if let Some(program) = cache.get(&key) {
+        return Ok(program.clone());
+    }
+    let program = compile(source)?;
+    cache.insert(key, program.clone());
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::ArithError;
+    use crate::intern::Symbol;
+    use crate::ir::Instruction;
+
+    #[test]
+    fn test_compile_cached() {
+        let source = "let x: int = 1; let y: int = x + 1;";
+        let mut cache = HashMap::new();
+        compile_cached(source, &mut cache).unwrap();
+        assert_eq!(cache.len(), 1);
+
+        // mark the cached entry so a hit is distinguishable from a recompile
+        cache
+            .values_mut()
+            .next()
+            .unwrap()
+            .warnings
+            .push("cached".into());
+        let program = compile_cached(source, &mut cache).unwrap();
+        assert_eq!(program.warnings, ["cached"]);
+
+        let program = compile_cached("let x: int = 2;", &mut cache).unwrap();
+        assert!(program.warnings.is_empty());
+        assert_eq!(cache.len(), 2);
+    }
+
+    // A fresh directory under the system temp dir holding the given files
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crucible-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_compile_file_with_import() {
+        let dir = write_files(
+            "import",
+            &[
+                ("lib.crucible", "const BASE: int = 40;"),
+                (
+                    "main.crucible",
+                    r#"import "lib.crucible"; let answer: int = BASE + 2;"#,
+                ),
+            ],
+        );
+        // `BASE` would be undeclared if the import hadn't been merged in
+        let program = compile_file(&dir.join("main.crucible")).unwrap();
+        assert!(program
+            .to_string()
+            .lines()
+            .any(|line| line == "BASE.1 = 40"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compile_with_flags() {
+        let source = "let x: int = 1;\n#if DEBUG\nlet checked: int = x + 1;\n#endif\n";
+        // the optimizer can drop the unused definition, but lowering still
+        // records the variable
+        let defines_checked = |program: &Program| program.variables.contains_key("checked");
+        let options = CompileOptions {
+            flags: HashSet::from(["DEBUG".to_string()]),
+            ..CompileOptions::default()
+        };
+        assert!(defines_checked(
+            &compile_with_options(source, &options).unwrap()
+        ));
+        assert!(!defines_checked(&compile(source).unwrap()));
+    }
+
+    #[test]
+    fn test_compile_short_circuit() {
+        // the division never runs, as in the interpreter
+        let source = "let x: int = 0; let ok: bool = x != 0 && 1 / x > 0;";
+        assert!(compile(source).is_ok());
+        let ast = parser::parse(lexer::lex(source).unwrap()).unwrap();
+        assert_eq!(crate::interp::eval(&ast).unwrap()["ok"], 0);
+    }
+
+    #[test]
+    fn test_compile_functions() {
+        // functions that survive dead function elimination are inlined into
+        // their callers and optimized along with them
+        let program = compile(
+            "fn double(n: int) -> int { return n * 2; }
+             fn unused() -> int { return 0; }
+             let n: int = 21;
+             let x: int = double(n);",
+        )
+        .unwrap();
+        assert_eq!(program.warnings, ["Removed unused function `unused`"]);
+        // a call with literal arguments is evaluated before lowering instead,
+        // which leaves nothing calling the function
+        let program =
+            compile("fn double(n: int) -> int { return n * 2; } let x: int = double(21);").unwrap();
+        assert_eq!(program.warnings, ["Removed unused function `double`"]);
+        let program = compile("fn unused() -> int { return 0; return 1; }").unwrap();
+        assert_eq!(
+            program.warnings,
+            [
+                "Unreachable statement at line 1, column 32",
+                "Removed unused function `unused`"
+            ]
+        );
+        // folding sees through the call to the division it makes
+        let err = compile("fn half(n: int) -> int { return 100 / n; } let x: int = half(0);")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Constant folding error: Division by zero computing x.1"
+        );
+
+        assert!(compile("fn main() -> int { return 42; }").is_ok());
+        // a function without a return type gives the type its returns have
+        let source = "fn inc(a: int) { return a + 1; } fn main() -> int { return inc(41); }";
+        assert_eq!(run(source, &CompileOptions::default()).unwrap(), 42);
+        let program = compile(source).unwrap();
+        assert_eq!(
+            program.instructions.last(),
+            Some(&Instruction::Exit(program.symbols.get("42").unwrap()))
+        );
+        let err = compile("fn f(n: int) -> int { return f(n); } let x: int = f(1);").unwrap_err();
+        assert!(err.to_string().contains("recursive function `f`"));
+    }
+
+    #[test]
+    fn test_compile_rejects_typeof() {
+        // the checker answers the query, but there's nothing to compile
+        let err = compile("let a: int = 1; typeof(a < 2);").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("`typeof` is answered by the type checker"));
+    }
+
+    #[test]
+    fn test_run_main() {
+        let options = CompileOptions::default();
+        assert_eq!(
+            run("fn main() -> int { return 42; }", &options).unwrap(),
+            42
+        );
+
+        // compiling runs `main` too, with its value ending the program
+        let program = compile("fn main() -> int { return 6 * 7; }").unwrap();
+        assert_eq!(
+            program
+                .instructions
+                .last()
+                .unwrap()
+                .display(&program.symbols)
+                .to_string(),
+            "exit 42"
+        );
+
+        let err = run("let x: int = 42;", &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entry point error: there is no `main` function to run; declare `fn main() -> int`"
+        );
+        for source in [
+            "fn main(code: int) -> int { return code; }",
+            "fn main() -> bool { return true; }",
+        ] {
+            let message = compile(source).unwrap_err().to_string();
+            assert!(
+                message.starts_with(
+                    "Entry point error: `main` must be declared as `fn main() -> int`"
+                ),
+                "{}",
+                message
+            );
+        }
+        let err = run("fn main() -> void { return; }", &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entry point error: `main` must be declared as `fn main() -> int`, not `fn main() -> void`"
+        );
+    }
+
+    #[test]
+    fn test_eval_result() {
+        let options = CompileOptions::default();
+        let source = "let x: int = 5;\nx + 1";
+        assert_eq!(eval(source, &options).unwrap(), Some(6));
+        // the program still compiles, with the result computed and dropped
+        compile(source).unwrap();
+
+        let err = eval("fn f() { } f()", &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Type error: The program's result `f()` has no value"
+        );
+    }
+
+    #[test]
+    fn test_compile_with_overflow_policy() {
+        let source = "let x: int = 9223372036854775807 + 1; let y: int = x - 1;";
+        // left to runtime with a warning when overflow is an error
+        let program = compile(source).unwrap();
+        assert_eq!(
+            program.warnings,
+            ["Arithmetic overflow computing x.1; not folded"]
+        );
+
+        let options = CompileOptions {
+            overflow_policy: OverflowPolicy::Wrapping,
+            ..CompileOptions::default()
+        };
+        let program = compile_with_options(source, &options).unwrap();
+        assert!(program.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_import_cycle() {
+        let dir = write_files(
+            "import-cycle",
+            &[
+                ("a.crucible", r#"import "b.crucible"; const A: int = 1;"#),
+                ("b.crucible", r#"import "a.crucible"; const B: int = 2;"#),
+            ],
+        );
+        let err = compile_file(&dir.join("a.crucible")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Import error: import cycle: a.crucible -> b.crucible -> a.crucible"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // xorshift64, so every run checks the same programs
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % bound
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len() as u64) as usize]
+        }
+    }
+
+    fn random_int(rng: &mut Rng, vars: &[String], depth: u32) -> String {
+        if depth == 0 || rng.below(3) == 0 {
+            return match rng.below(6) {
+                0 | 1 if !vars.is_empty() => vars[rng.below(vars.len() as u64) as usize].clone(),
+                // values that make overflow and division by zero likely
+                2 => rng
+                    .pick(&["0", "9223372036854775807", "(-9223372036854775807 - 1)"])
+                    .to_string(),
+                _ => match rng.below(21) as i64 - 10 {
+                    value if value < 0 => format!("({})", value),
+                    value => value.to_string(),
+                },
+            };
+        }
+        match rng.below(16) {
+            0 | 1 => return format!("(-{})", random_int(rng, vars, depth - 1)),
+            2 => {
+                let builtin = rng.pick(&["min", "max"]);
+                let left = random_int(rng, vars, depth - 1);
+                return format!(
+                    "{}({}, {})",
+                    builtin,
+                    left,
+                    random_int(rng, vars, depth - 1)
+                );
+            }
+            _ => {}
+        }
+        let op = rng.pick(&["+", "-", "*", "/", "%", "+%", "+|", "+", "-", "*"]);
+        let left = random_int(rng, vars, depth - 1);
+        format!("({} {} {})", left, op, random_int(rng, vars, depth - 1))
+    }
+
+    fn random_bool(rng: &mut Rng, vars: &[String], depth: u32) -> String {
+        if depth > 0 && rng.below(3) == 0 {
+            let left = random_bool(rng, vars, depth - 1);
+            let right = random_bool(rng, vars, depth - 1);
+            return match rng.below(3) {
+                0 => format!("!{}", left),
+                1 => format!("({} && {})", left, right),
+                _ => format!("({} || {})", left, right),
+            };
+        }
+        let op = rng.pick(&["==", "!=", "<", "<=", ">", ">="]);
+        let left = random_int(rng, vars, 1);
+        format!("({} {} {})", left, op, random_int(rng, vars, 1))
+    }
+
+    // Top-level lets, with ifs and counted loops assigning to them, and a
+    // `main` whose result depends on every variable
+    fn random_program(rng: &mut Rng) -> String {
+        let mut vars: Vec<String> = Vec::new();
+        let mut source = String::new();
+        // loop counters are read but never assigned, so every loop ends
+        let assignment = |rng: &mut Rng, vars: &[String]| {
+            let targets: Vec<&String> = vars.iter().filter(|var| var.starts_with('v')).collect();
+            let target = targets[rng.below(targets.len() as u64) as usize];
+            format!("{} = {};", target, random_int(rng, vars, 2))
+        };
+        for index in 0..3 + rng.below(5) {
+            match rng.below(5) {
+                0 if index > 0 => {
+                    let condition = random_bool(rng, &vars, 2);
+                    let then = assignment(rng, &vars);
+                    let otherwise = assignment(rng, &vars);
+                    source.push_str(&format!(
+                        "if ({}) {{ {} }} else {{ {} }}\n",
+                        condition, then, otherwise
+                    ));
+                }
+                1 if index > 0 => {
+                    let counter = format!("i{}", index);
+                    source.push_str(&format!("let {}: int = 0;\n", counter));
+                    vars.push(counter.clone());
+                    let body = assignment(rng, &vars);
+                    source.push_str(&format!(
+                        "while ({} < {}) {{ {} {} = {} + 1; }}\n",
+                        counter,
+                        rng.below(4),
+                        body,
+                        counter,
+                        counter
+                    ));
+                }
+                _ => {
+                    let value = random_int(rng, &vars, 3);
+                    let name = format!("v{}", index);
+                    source.push_str(&format!("let {}: int = {};\n", name, value));
+                    vars.push(name);
+                }
+            }
+        }
+        source.push_str(&format!(
+            "fn main() -> int {{ return {}; }}\n",
+            vars.join(" +% ")
+        ));
+        source
+    }
+
+    // Runs IR directly, returning every value it computed and the code it
+    // exited with. Each block's phis take their values together on the way in,
+    // folding may have left constants between them.
+    fn run_ir(program: &Program) -> Result<(HashMap<Symbol, i64>, Option<i64>), ArithError> {
+        let labels: HashMap<Symbol, usize> = program
+            .instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, inst)| match inst {
+                Instruction::Label(label) => Some((*label, index)),
+                _ => None,
+            })
+            .collect();
+        let policy = OverflowPolicy::default();
+        let mut values: HashMap<Symbol, i64> = HashMap::new();
+        let value = |values: &HashMap<Symbol, i64>, symbol: Symbol| {
+            let name = program.name(symbol);
+            name.parse().unwrap_or_else(|_| {
+                *values
+                    .get(&symbol)
+                    .unwrap_or_else(|| panic!("{} is unset", name))
+            })
+        };
+        let mut block = program.symbols.get(ir::ENTRY_BLOCK);
+        let mut pc = 0;
+        let mut steps = 0;
+        while let Some(inst) = program.instructions.get(pc) {
+            steps += 1;
+            assert!(steps < 100_000, "IR runs forever:\n{}", program);
+            pc += 1;
+            match inst {
+                Instruction::Constant { result, value, .. } => {
+                    values.insert(*result, *value);
+                }
+                Instruction::Binary {
+                    result,
+                    op,
+                    left,
+                    right,
+                    ..
+                } => {
+                    let computed =
+                        policy.eval(op, value(&values, *left), value(&values, *right))?;
+                    values.insert(*result, computed);
+                }
+                Instruction::Copy { result, source, .. } => {
+                    values.insert(*result, value(&values, *source));
+                }
+                Instruction::Call {
+                    result,
+                    builtin,
+                    args,
+                    ..
+                } => {
+                    let args: Vec<i64> = args.iter().map(|arg| value(&values, *arg)).collect();
+                    values.insert(*result, policy.eval_builtin(*builtin, &args)?);
+                }
+                Instruction::Select {
+                    result,
+                    cond,
+                    then_val,
+                    else_val,
+                    ..
+                } => {
+                    let source = if value(&values, *cond) != 0 {
+                        then_val
+                    } else {
+                        else_val
+                    };
+                    values.insert(*result, value(&values, *source));
+                }
+                Instruction::Label(label) => {
+                    let from = block.replace(*label);
+                    let mut incoming = Vec::new();
+                    let body = program.instructions[pc..]
+                        .iter()
+                        .take_while(|inst| !matches!(inst, Instruction::Label(_)));
+                    for inst in body {
+                        if let Instruction::Phi { result, sources } = inst {
+                            let source = sources
+                                .iter()
+                                .find(|(_, block)| Some(*block) == from)
+                                .unwrap_or_else(|| {
+                                    panic!("phi for {} has no source", program.name(*result))
+                                });
+                            incoming.push((*result, value(&values, source.0)));
+                        }
+                    }
+                    values.extend(incoming);
+                }
+                Instruction::Phi { .. } => {}
+                Instruction::Jump(target) => pc = labels[target],
+                Instruction::BranchIfZero { cond, target } => {
+                    if value(&values, *cond) == 0 {
+                        pc = labels[target];
+                    }
+                }
+                Instruction::Exit(code) => {
+                    return Ok((values.clone(), Some(value(&values, *code))))
+                }
+            }
+        }
+        Ok((values, None))
+    }
+
+    #[test]
+    fn test_optimized_ir_matches_interpreter() {
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        for _ in 0..1000 {
+            let source = random_program(&mut rng);
+            let ast = parser::parse(lexer::lex(&source).unwrap()).unwrap();
+            typecheck::check(&ast).unwrap();
+            let expected = interp::run(&ast);
+            let env = interp::eval(&ast);
+            let lowered = ir::lower(ast).unwrap();
+            // programs the interpreter rejects are only checked for not
+            // crashing: the compiler just warns about overflow, and simplify
+            // may drop a multiplication by zero that overflows
+            let (Ok(env), Ok(code)) = (env, expected) else {
+                let _ = compile(&source);
+                continue;
+            };
+            // lowering alone keeps every variable, each with the value the
+            // interpreter gives it
+            let (values, _) = run_ir(&lowered).unwrap();
+            for (name, value) in &lowered.final_values {
+                assert_eq!(values[value], env[name], "`{}` in\n{}", name, source);
+            }
+
+            // folding rejects a division it proves is by zero wherever it
+            // may run, which includes loops the interpreter never enters
+            let program = match compile(&source) {
+                Ok(program) => program,
+                Err(err) if err.to_string().contains("Division by zero") => continue,
+                Err(err) => panic!("{} in\n{}", err, source),
+            };
+            let (_, exit) = run_ir(&program).unwrap();
+            assert_eq!(exit, Some(code), "{}\n{}", source, program);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+// An interned name. Copying and comparing one is as cheap as a u32; the text
+// lives in the Interner of the program that created it, and only means
+// something together with that table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+// Each program keeps its own table, so names are freed along with it and
+// looking one up needs no lock
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    names: Vec<String>,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.symbols.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    // The symbol for `name`, if it was ever interned here
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    // Panics on a symbol from another table that is out of this one's range
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut interner = Interner::new();
+        let first = interner.intern("x.1");
+        assert_eq!(first, interner.intern("x.1"));
+        assert_ne!(first, interner.intern("x.2"));
+        assert_eq!(interner.resolve(first), "x.1");
+        assert_eq!(interner.get("x.2"), Some(interner.intern("x.2")));
+        assert_eq!(interner.get("y.1"), None);
+
+        // tables are independent: the same name can get a different symbol
+        let mut other = Interner::new();
+        other.intern("y.1");
+        assert_ne!(other.intern("x.1"), first);
+    }
+}
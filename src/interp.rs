@@ -0,0 +1,731 @@
+use crate::arith::{ArithError, Builtin, OverflowPolicy};
+use crate::ast::{positional, returns_value, BinaryOp, Expr, Statement, Type, UnaryOp};
+use crate::span::Spanned;
+use std::collections::HashMap;
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    message: String,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Runtime error: {}", self.message)
+    }
+}
+
+impl Error for RuntimeError {}
+
+fn error<T>(message: String) -> Result<T, RuntimeError> {
+    Err(RuntimeError { message })
+}
+
+impl From<ArithError> for RuntimeError {
+    fn from(err: ArithError) -> Self {
+        RuntimeError {
+            message: err.to_string(),
+        }
+    }
+}
+
+// How a block finished: normally, by a `break`/`continue` that an
+// enclosing loop still has to handle, or by returning from a function
+enum Flow {
+    Normal,
+    Break(Option<String>),
+    Continue(Option<String>),
+    // the returned value, or each element of a returned tuple
+    Return(Vec<i64>),
+}
+
+// Runs the top-level statements directly, returning the final value of every
+// top-level variable. Booleans are 0 and 1, as in the IR, and arithmetic follows the
+// default overflow policy. A function only runs when it's called.
+pub fn eval(statements: &[Spanned<Statement>]) -> Result<HashMap<String, i64>, RuntimeError> {
+    eval_with_policy(statements, OverflowPolicy::default())
+}
+
+// Like eval, with arithmetic following `policy`
+pub fn eval_with_policy(
+    statements: &[Spanned<Statement>],
+    policy: OverflowPolicy,
+) -> Result<HashMap<String, i64>, RuntimeError> {
+    let mut interpreter = Interpreter::new(policy);
+    interpreter.exec_top_level(statements)?;
+    Ok(interpreter.scopes.swap_remove(0))
+}
+
+#[derive(Debug, Default)]
+pub struct EvalOptions {
+    pub policy: OverflowPolicy,
+    // count the operations that run, at the cost of doing so for every one
+    pub profile: bool,
+}
+
+// How many times each kind of operation ran. A `%` counts as a divide, and
+// every `if`, `while` and short-circuiting `&&` or `||` test as a branch.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub adds: u64,
+    pub multiplies: u64,
+    pub divides: u64,
+    pub branches: u64,
+}
+
+// Like eval, also returning the operation counts when `options.profile` is set
+pub fn eval_with_options(
+    statements: &[Spanned<Statement>],
+    options: &EvalOptions,
+) -> Result<(HashMap<String, i64>, Option<Profile>), RuntimeError> {
+    let mut interpreter = Interpreter::new(options.policy);
+    interpreter.profile = options.profile.then(Profile::default);
+    interpreter.exec_top_level(statements)?;
+    Ok((interpreter.scopes.swap_remove(0), interpreter.profile))
+}
+
+// Runs the top-level statements, returning the value of the expression that
+// ends the program without a `;`, if it has one
+pub fn eval_result(statements: &[Spanned<Statement>]) -> Result<Option<i64>, RuntimeError> {
+    eval_result_with_policy(statements, OverflowPolicy::default())
+}
+
+// Like eval_result, with arithmetic following `policy`
+pub fn eval_result_with_policy(
+    statements: &[Spanned<Statement>],
+    policy: OverflowPolicy,
+) -> Result<Option<i64>, RuntimeError> {
+    let mut interpreter = Interpreter::new(policy);
+    interpreter.exec_top_level(statements)?;
+    Ok(interpreter.result)
+}
+
+// Runs the top-level statements and then calls `main`, returning what it
+// returns as the program's exit code
+pub fn run(statements: &[Spanned<Statement>]) -> Result<i64, RuntimeError> {
+    run_with_policy(statements, OverflowPolicy::default())
+}
+
+// Like run, with arithmetic following `policy`
+pub fn run_with_policy(
+    statements: &[Spanned<Statement>],
+    policy: OverflowPolicy,
+) -> Result<i64, RuntimeError> {
+    let mut interpreter = Interpreter::new(policy);
+    interpreter.exec_top_level(statements)?;
+    if !interpreter.functions.contains_key("main") {
+        return error("there is no `main` function to run".to_string());
+    }
+    interpreter.call("main", Vec::new())
+}
+
+// How deep calls made by call_bounded may nest. Each level takes a good
+// deal of native stack, so this stops deep recursion well before that runs
+// out, even on a small thread stack.
+const BOUNDED_CALL_DEPTH: usize = 64;
+
+// Calls the top-level function `name` without running any of the top-level
+// statements first, so the only variables it can see are its own. Gives up
+// once `step_limit` expressions have been evaluated, or calls nest more than
+// BOUNDED_CALL_DEPTH deep.
+pub fn call_bounded(
+    statements: &[Spanned<Statement>],
+    name: &str,
+    args: Vec<i64>,
+    policy: OverflowPolicy,
+    step_limit: u64,
+) -> Result<i64, RuntimeError> {
+    let mut interpreter = Interpreter::new(policy);
+    interpreter.steps_left = Some(step_limit);
+    interpreter.calls_left = Some(BOUNDED_CALL_DEPTH);
+    interpreter.register_functions(statements);
+    if !interpreter.functions.contains_key(name) {
+        return error(format!("there is no `{}` function to call", name));
+    }
+    interpreter.call(name, args)
+}
+
+// A function declared in a block being run
+#[derive(Clone, Copy)]
+struct Function<'a> {
+    params: &'a [(String, Type)],
+    returns_value: bool,
+    body: &'a [Spanned<Statement>],
+    // how many scopes were open where it was declared, which are the only
+    // ones its body can see
+    depth: usize,
+}
+
+struct Interpreter<'a> {
+    // one per enclosing block, innermost last, as in the type checker, so a
+    // `let` in a block shadows an outer variable only until the block ends
+    scopes: Vec<HashMap<String, i64>>,
+    functions: HashMap<String, Function<'a>>,
+    policy: OverflowPolicy,
+    // the value of the program's trailing expression, once it has run
+    result: Option<i64>,
+    // None unless profiling
+    profile: Option<Profile>,
+    // how many more expressions may be evaluated, and how many more calls
+    // may nest inside the current one, when those are bounded
+    steps_left: Option<u64>,
+    calls_left: Option<usize>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(policy: OverflowPolicy) -> Self {
+        Interpreter {
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            policy,
+            result: None,
+            profile: None,
+            steps_left: None,
+            calls_left: None,
+        }
+    }
+
+    fn count(&mut self, counter: fn(&mut Profile) -> &mut u64) {
+        if let Some(profile) = &mut self.profile {
+            *counter(profile) += 1;
+        }
+    }
+
+    // A condition, counted as a branch
+    fn test(&mut self, condition: &Expr) -> Result<bool, RuntimeError> {
+        self.count(|profile| &mut profile.branches);
+        Ok(self.eval_expr(condition)? != 0)
+    }
+
+    // Top-level functions stay registered afterward, for `run` to call `main`
+    fn exec_top_level(&mut self, statements: &'a [Spanned<Statement>]) -> Result<(), RuntimeError> {
+        self.register_functions(statements);
+        match self.exec_statements(statements)? {
+            Flow::Normal => Ok(()),
+            // the type checker rejects these, but unchecked ASTs can get here
+            Flow::Break(_) | Flow::Continue(_) => {
+                error("`break` or `continue` outside of a loop".to_string())
+            }
+            Flow::Return(_) => error("`return` outside of a function".to_string()),
+        }
+    }
+
+    // The value a call returns, where the type checker has made sure it isn't
+    // a tuple
+    fn call(&mut self, name: &str, args: Vec<i64>) -> Result<i64, RuntimeError> {
+        Ok(self.call_values(name, args)?[0])
+    }
+
+    // Runs the function's body with only the scopes it was declared in
+    // visible, plus one binding each parameter to its argument
+    fn call_values(&mut self, name: &str, args: Vec<i64>) -> Result<Vec<i64>, RuntimeError> {
+        let function = self.functions[name];
+        if args.len() != function.params.len() {
+            return error(format!(
+                "`{}` takes {} arguments but {} were given",
+                name,
+                function.params.len(),
+                args.len()
+            ));
+        }
+        match &mut self.calls_left {
+            Some(0) => return error("call depth limit reached".to_string()),
+            Some(calls_left) => *calls_left -= 1,
+            None => {}
+        }
+        let caller_scopes = self.scopes.split_off(function.depth);
+        let params = function.params.iter().map(|(param, _)| param.clone());
+        self.scopes.push(params.zip(args).collect());
+        let flow = self.exec_scoped(function.body);
+        self.scopes.pop();
+        self.scopes.extend(caller_scopes);
+        if let Some(calls_left) = &mut self.calls_left {
+            *calls_left += 1;
+        }
+        match flow? {
+            Flow::Return(values) => Ok(values),
+            Flow::Normal if !function.returns_value => Ok(vec![0]),
+            Flow::Normal => error(format!("`{}` ended without returning a value", name)),
+            Flow::Break(_) | Flow::Continue(_) => {
+                error("`break` or `continue` outside of a loop".to_string())
+            }
+        }
+    }
+
+    // Updates the innermost variable called `target`
+    fn assign(&mut self, target: &str, value: i64) -> Result<(), RuntimeError> {
+        let scope = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find(|scope| scope.contains_key(target));
+        let Some(scope) = scope else {
+            return error(format!("Assignment to undeclared variable `{}`", target));
+        };
+        scope.insert(target.to_string(), value);
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<i64> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    // Runs a nested block in a scope of its own
+    fn exec_scoped(&mut self, statements: &'a [Spanned<Statement>]) -> Result<Flow, RuntimeError> {
+        self.scopes.push(HashMap::new());
+        let flow = self.exec_block(statements);
+        self.scopes.pop();
+        flow
+    }
+
+    // Functions are registered up front, as in the type checker, so they can
+    // be called before their declaration, and a nested one is forgotten again
+    // when its block ends
+    fn exec_block(&mut self, statements: &'a [Spanned<Statement>]) -> Result<Flow, RuntimeError> {
+        let shadowed = self.register_functions(statements);
+        let flow = self.exec_statements(statements);
+        for (name, outer) in shadowed.into_iter().rev() {
+            match outer {
+                Some(outer) => self.functions.insert(name.clone(), outer),
+                None => self.functions.remove(name),
+            };
+        }
+        flow
+    }
+
+    // Returns each function declared in `statements` with the one of the
+    // same name it hides, if any
+    fn register_functions(
+        &mut self,
+        statements: &'a [Spanned<Statement>],
+    ) -> Vec<(&'a String, Option<Function<'a>>)> {
+        let mut shadowed = Vec::new();
+        for stmt in statements {
+            if let Statement::Function {
+                name,
+                params,
+                return_type,
+                body,
+            } = &stmt.node
+            {
+                let function = Function {
+                    params,
+                    returns_value: returns_value(return_type.clone(), body),
+                    body,
+                    depth: self.scopes.len(),
+                };
+                shadowed.push((name, self.functions.insert(name.clone(), function)));
+            }
+        }
+        shadowed
+    }
+
+    fn exec_statements(
+        &mut self,
+        statements: &'a [Spanned<Statement>],
+    ) -> Result<Flow, RuntimeError> {
+        for stmt in statements {
+            match &stmt.node {
+                Statement::Let { name, value, .. } | Statement::Const { name, value, .. } => {
+                    let value = self.eval_expr(value)?;
+                    let scope = self.scopes.last_mut().expect("a scope is always open");
+                    scope.insert(name.clone(), value);
+                }
+                Statement::Destructure { names, value, .. } => {
+                    let values = self.eval_values(value)?;
+                    if values.len() != names.len() {
+                        return error(format!(
+                            "{} values can't be destructured into {} names",
+                            values.len(),
+                            names.len()
+                        ));
+                    }
+                    let scope = self.scopes.last_mut().expect("a scope is always open");
+                    scope.extend(names.iter().cloned().zip(values));
+                }
+                // updates the innermost variable of that name
+                Statement::Assignment { target, value } => {
+                    let value = self.eval_expr(value)?;
+                    self.assign(target, value)?;
+                }
+                Statement::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                } => {
+                    let flow = if self.test(condition)? {
+                        self.exec_scoped(then_branch)?
+                    } else {
+                        match else_branch {
+                            Some(else_branch) => self.exec_scoped(else_branch)?,
+                            None => Flow::Normal,
+                        }
+                    };
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                Statement::While {
+                    label,
+                    condition,
+                    body,
+                } => {
+                    // a label-less break or continue applies to the innermost loop
+                    let is_ours = |target: &Option<String>| target.is_none() || target == label;
+                    while self.test(condition)? {
+                        match self.exec_scoped(body)? {
+                            Flow::Normal => {}
+                            Flow::Break(target) if is_ours(&target) => break,
+                            Flow::Continue(target) if is_ours(&target) => continue,
+                            flow => return Ok(flow),
+                        }
+                    }
+                }
+                Statement::Expression(expr) => {
+                    self.eval_expr(expr)?;
+                }
+                Statement::Result(expr) => self.result = Some(self.eval_expr(expr)?),
+                Statement::Break(label) => return Ok(Flow::Break(label.clone())),
+                Statement::Continue(label) => return Ok(Flow::Continue(label.clone())),
+                // registered by exec_block, and run wherever it's called
+                Statement::Function { .. } => {}
+                Statement::Return(value) => {
+                    let values = match value {
+                        Some(value) => self.eval_values(value)?,
+                        None => vec![0],
+                    };
+                    return Ok(Flow::Return(values));
+                }
+                Statement::Import(path) => return error(format!("unresolved import {:?}", path)),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn step(&mut self) -> Result<(), RuntimeError> {
+        if let Some(steps_left) = &mut self.steps_left {
+            if *steps_left == 0 {
+                return error("step limit reached".to_string());
+            }
+            *steps_left -= 1;
+        }
+        Ok(())
+    }
+
+    // The elements of a tuple, or the one value of anything else
+    fn eval_values(&mut self, expr: &Expr) -> Result<Vec<i64>, RuntimeError> {
+        match expr {
+            Expr::Tuple(exprs) => {
+                self.step()?;
+                exprs.iter().map(|expr| self.eval_expr(expr)).collect()
+            }
+            Expr::Call { callee, args } => {
+                self.step()?;
+                self.eval_call(callee, args)
+            }
+            expr => Ok(vec![self.eval_expr(expr)?]),
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> Result<i64, RuntimeError> {
+        self.step()?;
+        match expr {
+            Expr::Integer(value, _) => Ok(*value),
+            Expr::Boolean(value) => Ok(*value as i64),
+            Expr::Variable(name) => match self.lookup(name) {
+                Some(value) => Ok(value),
+                None => error(format!("Use of undeclared variable `{}`", name)),
+            },
+            // the right side only runs when the left doesn't decide the result
+            Expr::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                left,
+                right,
+            } => {
+                let left = self.test(left)?;
+                match (op, left) {
+                    (BinaryOp::And, false) => Ok(0),
+                    (BinaryOp::Or, true) => Ok(1),
+                    _ => Ok((self.eval_expr(right)? != 0) as i64),
+                }
+            }
+            Expr::Binary { op, left, right } => {
+                let (left, right) = (self.eval_expr(left)?, self.eval_expr(right)?);
+                match op {
+                    BinaryOp::Add | BinaryOp::WrappingAdd | BinaryOp::SaturatingAdd => {
+                        self.count(|profile| &mut profile.adds)
+                    }
+                    BinaryOp::Multiply => self.count(|profile| &mut profile.multiplies),
+                    BinaryOp::Divide | BinaryOp::Modulo => {
+                        self.count(|profile| &mut profile.divides)
+                    }
+                    _ => {}
+                }
+                Ok(self.policy.eval(op, left, right)?)
+            }
+            Expr::Unary { op, operand } => {
+                let operand = self.eval_expr(operand)?;
+                match op {
+                    UnaryOp::Negate => Ok(self.policy.eval(&BinaryOp::Subtract, 0, operand)?),
+                    UnaryOp::Not => Ok((operand == 0) as i64),
+                }
+            }
+            Expr::Call { callee, args } => Ok(self.eval_call(callee, args)?[0]),
+            Expr::Tuple(_) => error("a tuple has no single value".to_string()),
+            Expr::Seq(exprs) => {
+                let mut value = 0;
+                for expr in exprs {
+                    value = self.eval_expr(expr)?;
+                }
+                Ok(value)
+            }
+            Expr::Assign { target, value } => {
+                let value = self.eval_expr(value)?;
+                self.assign(target, value)?;
+                Ok(value)
+            }
+            Expr::Named { value, .. } => self.eval_expr(value),
+        }
+    }
+
+    // A function of the program's own takes precedence over a builtin of the
+    // same name, as in the type checker
+    fn eval_call(
+        &mut self,
+        callee: &Expr,
+        args: &[Spanned<Expr>],
+    ) -> Result<Vec<i64>, RuntimeError> {
+        let Expr::Variable(name) = callee else {
+            return error("only functions can be called by name".to_string());
+        };
+        let values = args
+            .iter()
+            .map(|arg| self.eval_expr(arg))
+            .collect::<Result<Vec<i64>, RuntimeError>>()?;
+        if let Some(function) = self.functions.get(name) {
+            let params: Vec<&str> = function
+                .params
+                .iter()
+                .map(|(param, _)| param.as_str())
+                .collect();
+            let args = positional(name, args, values, &params)
+                .map_err(|message| RuntimeError { message })?;
+            return self.call_values(name, args);
+        }
+        let Some(builtin) = Builtin::from_name(name) else {
+            return error(format!("Call to unknown function `{}`", name));
+        };
+        let args =
+            positional(name, args, values, &[]).map_err(|message| RuntimeError { message })?;
+        if args.len() != builtin.arity() {
+            return error(format!(
+                "`{}` takes {} arguments but {} were given",
+                builtin.name(),
+                builtin.arity(),
+                args.len()
+            ));
+        }
+        Ok(vec![self.policy.eval_builtin(builtin, &args)?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    fn eval_source(input: &str) -> Result<HashMap<String, i64>, RuntimeError> {
+        let tokens = lexer::lex(input).unwrap();
+        eval(&parser::parse(tokens).unwrap())
+    }
+
+    #[test]
+    fn test_eval_lets_and_assignments() {
+        let env = eval_source(
+            "let x: int = 2 + 3 * 4; let y: int = x - 4; x = x * 2; let done: bool = x > y;",
+        )
+        .unwrap();
+        assert_eq!(env["x"], 28);
+        assert_eq!(env["y"], 10);
+        assert_eq!(env["done"], 1);
+    }
+
+    #[test]
+    fn test_eval_loops() {
+        let env = eval_source(
+            "let i: int = 0; let total: int = 0;
+             outer: while (true) {
+                 i = i + 1;
+                 if (i % 2 == 0) { continue; }
+                 while (true) { break outer; }
+             }
+             while (i < 5) { total = total + i; i = i + 1; }",
+        )
+        .unwrap();
+        assert_eq!(env["i"], 5);
+        assert_eq!(env["total"], 1 + 2 + 3 + 4);
+    }
+
+    #[test]
+    fn test_eval_tuples() {
+        let env = eval_source(
+            "fn divmod(a: int, b: int) -> (int, int) {
+                 if (b == 0) { return (0, 0); }
+                 return (a / b, a % b);
+             }
+             fn halves(n: int) -> (int, int) { return divmod(n, 2); }
+             let (q, r): (int, int) = divmod(17, 5);
+             let (h, odd): (int, int) = halves(q + r);
+             let (a, b): (int, bool) = (r, true);",
+        )
+        .unwrap();
+        assert_eq!((env["q"], env["r"]), (3, 2));
+        assert_eq!((env["h"], env["odd"]), (2, 1));
+        assert_eq!((env["a"], env["b"]), (2, 1));
+    }
+
+    #[test]
+    fn test_eval_sequence() {
+        let env = eval_source(
+            "let a: int = 0; let b: int = (a = 1, a + 1);
+             let i: int = 0; while ((i = i + 1) < 3) {}",
+        )
+        .unwrap();
+        assert_eq!(env["a"], 1);
+        assert_eq!(env["b"], 2);
+        assert_eq!(env["i"], 3);
+    }
+
+    #[test]
+    fn test_eval_block_scopes() {
+        let env = eval_source(
+            "let x: int = 5; let c: bool = true;
+             if (c) { let x: bool = false; x = true; }
+             let y: int = x + 1;
+             if (c) { x = x * 2; }",
+        )
+        .unwrap();
+        // the inner `x` shadowed the outer one only within its block, while
+        // assigning from a block without a `let` updates the outer one
+        assert_eq!(env["y"], 6);
+        assert_eq!(env["x"], 10);
+    }
+
+    #[test]
+    fn test_eval_division_by_zero() {
+        let err = eval_source("let x: int = 0; let y: int = 1 / x;").unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: Division by zero");
+
+        // short-circuiting skips the division entirely
+        let env = eval_source("let x: int = 0; let ok: bool = x != 0 && 1 / x > 0;").unwrap();
+        assert_eq!(env["ok"], 0);
+    }
+
+    #[test]
+    fn test_eval_with_policy() {
+        let tokens = lexer::lex("let x: int = 9223372036854775807 + 1;").unwrap();
+        let ast = parser::parse(tokens).unwrap();
+        let err = eval(&ast).unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: Arithmetic overflow");
+        let env = eval_with_policy(&ast, OverflowPolicy::Wrapping).unwrap();
+        assert_eq!(env["x"], i64::MIN);
+        let env = eval_with_policy(&ast, OverflowPolicy::Saturating).unwrap();
+        assert_eq!(env["x"], i64::MAX);
+    }
+
+    #[test]
+    fn test_eval_functions() {
+        let env = eval_source(
+            "fn fact(n: int) -> int { if (n <= 1) { return 1; } return n * fact(n - 1); }
+             let calls: int = 0;
+             fn bump() -> void { calls = calls + 1; }
+             let x: int = 2;
+             fn shadowed() -> int { let x: int = 100; return x; }
+             let f: int = fact(5);
+             bump(); bump();
+             let y: int = shadowed();",
+        )
+        .unwrap();
+        assert_eq!(env["f"], 120);
+        // a function sees and updates the variables in scope where it's declared
+        assert_eq!(env["calls"], 2);
+        assert_eq!((env["x"], env["y"]), (2, 100));
+
+        // named arguments go to their parameters but run in the order written
+        let env = eval_source(
+            "let log: int = 0;
+             fn minus(a: int, b: int) -> int { return a - b; }
+             let d: int = minus(b: (log = log * 10 + 1, 1), a: (log = log * 10 + 2, 10));",
+        )
+        .unwrap();
+        assert_eq!((env["d"], env["log"]), (9, 12));
+
+        let err = eval_source("fn f() -> int { let x: int = 1; } let y: int = f();").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Runtime error: `f` ended without returning a value"
+        );
+    }
+
+    #[test]
+    fn test_run_main() {
+        let tokens = lexer::lex(
+            "fn answer() -> int { return 40; }
+             let offset: int = 2;
+             fn main() -> int { return answer() + offset; }",
+        )
+        .unwrap();
+        assert_eq!(run(&parser::parse(tokens).unwrap()).unwrap(), 42);
+
+        let tokens = lexer::lex("let x: int = 1;").unwrap();
+        let err = run(&parser::parse(tokens).unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Runtime error: there is no `main` function to run"
+        );
+    }
+
+    #[test]
+    fn test_eval_result() {
+        let tokens = lexer::lex("let x: int = 5;\nx + 1").unwrap();
+        assert_eq!(
+            eval_result(&parser::parse(tokens).unwrap()).unwrap(),
+            Some(6)
+        );
+
+        let tokens = lexer::lex("let x: int = 5;").unwrap();
+        assert_eq!(eval_result(&parser::parse(tokens).unwrap()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_profile() {
+        let tokens = lexer::lex(
+            "let i: int = 0; let total: int = 1;
+             while (i < 10) { i = i + 1; if (i % 2 == 0) { total = total * 2; } }",
+        )
+        .unwrap();
+        let statements = parser::parse(tokens).unwrap();
+        let options = EvalOptions {
+            profile: true,
+            ..EvalOptions::default()
+        };
+        let (variables, profile) = eval_with_options(&statements, &options).unwrap();
+        assert_eq!(variables["total"], 32);
+        assert_eq!(
+            profile,
+            Some(Profile {
+                adds: 10,
+                multiplies: 5,
+                divides: 10,
+                // 11 loop tests and 10 of the `if`
+                branches: 21,
+            })
+        );
+
+        let (_, profile) = eval_with_options(&statements, &EvalOptions::default()).unwrap();
+        assert_eq!(profile, None);
+    }
+}
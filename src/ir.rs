@@ -1,64 +1,600 @@
-use crate::ast::{BinaryOp, Expr, Statement};
-use std::collections::HashMap;
+use crate::arith::{eval_binary, ArithError, Builtin, OverflowPolicy};
+use crate::ast::{positional, returns_value, BinaryOp, Expr, Radix, Statement, UnaryOp};
+use crate::intern::{Interner, Symbol};
+use crate::span::{Span, Spanned};
+use crate::typecheck::TYPEOF;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::{error::Error, fmt};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+// The instructions that compute a value carry the span of the expression they
+// were lowered from, or None when a pass made them up
 pub enum Instruction {
     // Expr::Integer
     Constant {
-        result: String,
+        result: Symbol,
         value: i64,
+        span: Option<Span>,
     },
     // Expr::Binary
     Binary {
-        result: String,
+        result: Symbol,
         op: BinaryOp,
-        left: String,
-        right: String,
+        left: Symbol,
+        right: Symbol,
+        span: Option<Span>,
+    },
+    // Expr::Variable bound to a new name
+    Copy {
+        result: Symbol,
+        source: Symbol,
+        span: Option<Span>,
+    },
+    // Expr::Call of a builtin
+    Call {
+        result: Symbol,
+        builtin: Builtin,
+        args: Vec<Symbol>,
+        span: Option<Span>,
+    },
+    // `then_val` when `cond` is nonzero and `else_val` otherwise, without
+    // branching; `min` and `max` lower to one
+    Select {
+        result: Symbol,
+        cond: Symbol,
+        then_val: Symbol,
+        else_val: Symbol,
+        span: Option<Span>,
     },
+    // Control flow, from Statement::If, Statement::While, short-circuiting
+    // `&&` and `||`, and the returns of an inlined call
+    Label(Symbol),
+    Jump(Symbol),
+    BranchIfZero {
+        cond: Symbol,
+        target: Symbol,
+    },
+    // Merges the values a variable can have where control flow joins: after
+    // an if, at the top and the end of a loop, or the two ways a `&&` or `||`
+    // can finish. Each source pairs a value with the label of the block it
+    // comes from, where code before the first label belongs to ENTRY_BLOCK.
+    Phi {
+        result: Symbol,
+        sources: Vec<(Symbol, Symbol)>,
+    },
+    // Ends the program with the value `main` returned as its exit code;
+    // always the last instruction when there is one
+    Exit(Symbol),
 }
-#[derive(Debug)]
+
+pub const ENTRY_BLOCK: &str = "entry";
+
+#[derive(Debug, Clone)]
 pub struct Program {
     pub instructions: Vec<Instruction>,
-    pub variables: HashMap<String, i64>,
+    // Ordered so that anything listing variables is stable across runs
+    pub variables: BTreeMap<String, i64>,
+    pub warnings: Vec<String>,
+    // the text of every Symbol in `instructions`
+    pub symbols: Interner,
+    // the value each top-level variable is left with once the program has
+    // run, whichever version that is; optimizing may remove some of them
+    pub final_values: BTreeMap<String, Symbol>,
+    // results of the constants written as hex literals in the source
+    pub hex_constants: HashSet<Symbol>,
+    // while lowering, the value each variable name currently refers to, one
+    // map per enclosing block with the innermost last; these lag behind
+    // `variables` once a branch has been left
+    scopes: Vec<HashMap<String, Symbol>>,
+    // the loops enclosing the statement being lowered, innermost last
+    loops: Vec<Loop>,
+    // functions declared in the blocks being lowered, by name
+    functions: HashMap<String, Function>,
+    // the calls being inlined, innermost last
+    calls: Vec<Frame>,
 }
 
 impl Program {
     pub fn new() -> Self {
         Program {
             instructions: Vec::new(),
-            variables: HashMap::new(), // track number of variable versions
+            variables: BTreeMap::new(), // track number of variable versions
+            warnings: Vec::new(),
+            symbols: Interner::new(),
+            final_values: BTreeMap::new(),
+            hex_constants: HashSet::new(),
+            scopes: vec![HashMap::new()],
+            loops: Vec::new(),
+            functions: HashMap::new(),
+            calls: Vec::new(),
+        }
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        self.symbols.intern(name)
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        self.symbols.resolve(symbol)
+    }
+
+    // Appends `other`, shifting its versions past this program's so that
+    // names from the two never collide: `x.1` in `other` becomes `x.3` when
+    // this program already defines two versions of `x`.
+    // Symbols are re-interned into this program's table along the way.
+    pub fn merge(&mut self, other: Program) {
+        let mut rename = |name: &mut Symbol| {
+            let text = other.symbols.resolve(*name);
+            let renamed = text.rsplit_once('.').and_then(|(base, version)| {
+                let offset = self.variables.get(base)?;
+                let version = version.parse::<i64>().ok()?;
+                Some(format!("{}.{}", base, version + offset))
+            });
+            *name = self.symbols.intern(renamed.as_deref().unwrap_or(text));
+        };
+
+        for mut inst in other.instructions {
+            if let Some(result) = inst.result_mut() {
+                rename(result);
+            }
+            inst.for_each_operand_mut(&mut rename);
+            self.instructions.push(inst);
         }
+        for (name, mut value) in other.final_values {
+            rename(&mut value);
+            self.final_values.insert(name, value);
+        }
+        for mut value in other.hex_constants {
+            rename(&mut value);
+            self.hex_constants.insert(value);
+        }
+        for (base, count) in other.variables {
+            *self.variables.entry(base).or_insert(0) += count;
+        }
+        self.warnings.extend(other.warnings);
     }
 }
 
-fn translate_literal(value: i64, ir: &mut Program, target: Option<&str>) -> String {
-    let result = if let Some(name) = target {
-        gen_name(name, ir)
-    } else {
-        format!("{}", value)
-    };
+impl Instruction {
+    // The name the instruction defines; a label defines its own name
+    pub fn result(&self) -> Option<Symbol> {
+        match self {
+            Instruction::Constant { result, .. }
+            | Instruction::Binary { result, .. }
+            | Instruction::Copy { result, .. }
+            | Instruction::Call { result, .. }
+            | Instruction::Select { result, .. }
+            | Instruction::Phi { result, .. }
+            | Instruction::Label(result) => Some(*result),
+            Instruction::Jump(_) | Instruction::BranchIfZero { .. } | Instruction::Exit(_) => None,
+        }
+    }
 
-    if target.is_some() {
-        ir.instructions.push(Instruction::Constant {
-            result: result.clone(),
-            value,
-        });
+    pub fn result_mut(&mut self) -> Option<&mut Symbol> {
+        match self {
+            Instruction::Constant { result, .. }
+            | Instruction::Binary { result, .. }
+            | Instruction::Copy { result, .. }
+            | Instruction::Call { result, .. }
+            | Instruction::Select { result, .. }
+            | Instruction::Phi { result, .. }
+            | Instruction::Label(result) => Some(result),
+            Instruction::Jump(_) | Instruction::BranchIfZero { .. } | Instruction::Exit(_) => None,
+        }
+    }
+
+    // Where in the source the instruction comes from; control flow has no
+    // single expression of its own, so it has none
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Instruction::Constant { span, .. }
+            | Instruction::Binary { span, .. }
+            | Instruction::Copy { span, .. }
+            | Instruction::Call { span, .. }
+            | Instruction::Select { span, .. } => *span,
+            Instruction::Label(_)
+            | Instruction::Jump(_)
+            | Instruction::BranchIfZero { .. }
+            | Instruction::Phi { .. }
+            | Instruction::Exit(_) => None,
+        }
+    }
+
+    // Names and literals the instruction reads, including jump targets
+    pub fn operands(&self) -> Vec<Symbol> {
+        match self {
+            Instruction::Constant { .. } | Instruction::Label(_) => Vec::new(),
+            Instruction::Binary { left, right, .. } => vec![*left, *right],
+            Instruction::Copy { source, .. } => vec![*source],
+            Instruction::Call { args, .. } => args.clone(),
+            Instruction::Select {
+                cond,
+                then_val,
+                else_val,
+                ..
+            } => vec![*cond, *then_val, *else_val],
+            Instruction::Phi { sources, .. } => sources
+                .iter()
+                .flat_map(|&(value, block)| [value, block])
+                .collect(),
+            Instruction::Jump(target) | Instruction::Exit(target) => vec![*target],
+            Instruction::BranchIfZero { cond, target } => vec![*cond, *target],
+        }
+    }
+
+    // Visits the same operands as `operands`, in place, without collecting
+    // them first
+    pub fn for_each_operand_mut(&mut self, mut visit: impl FnMut(&mut Symbol)) {
+        match self {
+            Instruction::Constant { .. } | Instruction::Label(_) => {}
+            Instruction::Binary { left, right, .. } => {
+                visit(left);
+                visit(right);
+            }
+            Instruction::Copy { source, .. } => visit(source),
+            Instruction::Call { args, .. } => args.iter_mut().for_each(visit),
+            Instruction::Select {
+                cond,
+                then_val,
+                else_val,
+                ..
+            } => {
+                visit(cond);
+                visit(then_val);
+                visit(else_val);
+            }
+            Instruction::Phi { sources, .. } => {
+                for (value, block) in sources {
+                    visit(value);
+                    visit(block);
+                }
+            }
+            Instruction::Jump(target) | Instruction::Exit(target) => visit(target),
+            Instruction::BranchIfZero { cond, target } => {
+                visit(cond);
+                visit(target);
+            }
+        }
+    }
+}
+
+impl Instruction {
+    // Symbols only have text through the table of the program they belong to
+    pub fn display<'a>(&'a self, symbols: &'a Interner) -> DisplayInstruction<'a> {
+        DisplayInstruction {
+            inst: self,
+            symbols,
+            annotated: false,
+        }
+    }
+
+    // Like display, but marks what kind of value each operand and result is:
+    // `#5` is a literal, `%bin3` a temporary and `x@2` version 2 of the
+    // variable `x`. Labels are left as they are.
+    pub fn display_annotated<'a>(&'a self, symbols: &'a Interner) -> DisplayInstruction<'a> {
+        DisplayInstruction {
+            inst: self,
+            symbols,
+            annotated: true,
+        }
+    }
+}
+
+// Names lowering generates for intermediate values rather than variables
+const TEMPORARIES: [&str; 2] = ["bin", "call"];
+
+pub struct DisplayInstruction<'a> {
+    inst: &'a Instruction,
+    symbols: &'a Interner,
+    annotated: bool,
+}
+
+impl DisplayInstruction<'_> {
+    fn value(&self, symbol: Symbol) -> String {
+        let name = self.symbols.resolve(symbol);
+        if !self.annotated {
+            return name.to_string();
+        }
+        if name.parse::<i64>().is_ok() {
+            return format!("#{}", name);
+        }
+        match name.rsplit_once('.') {
+            Some((base, version)) if TEMPORARIES.contains(&base) => {
+                format!("%{}{}", base, version)
+            }
+            Some((base, version)) => format!("{}@{}", base, version),
+            None => name.to_string(),
+        }
+    }
+}
+
+// Three-address form, e.g. `bin.1 = x.1 + y.1`
+impl fmt::Display for DisplayInstruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = |symbol: &Symbol| self.symbols.resolve(*symbol);
+        let value = |symbol: &Symbol| self.value(*symbol);
+        match self.inst {
+            Instruction::Constant {
+                result,
+                value: constant,
+                ..
+            } => {
+                let constant = if self.annotated {
+                    format!("#{}", constant)
+                } else {
+                    constant.to_string()
+                };
+                write!(f, "{} = {}", value(result), constant)
+            }
+            Instruction::Binary {
+                result,
+                op,
+                left,
+                right,
+                ..
+            } => write!(
+                f,
+                "{} = {} {} {}",
+                value(result),
+                value(left),
+                op,
+                value(right)
+            ),
+            Instruction::Copy { result, source, .. } => {
+                write!(f, "{} = {}", value(result), value(source))
+            }
+            Instruction::Call {
+                result,
+                builtin,
+                args,
+                ..
+            } => {
+                let args: Vec<String> = args.iter().map(value).collect();
+                write!(
+                    f,
+                    "{} = {}({})",
+                    value(result),
+                    builtin.name(),
+                    args.join(", ")
+                )
+            }
+            Instruction::Select {
+                result,
+                cond,
+                then_val,
+                else_val,
+                ..
+            } => write!(
+                f,
+                "{} = select {}, {}, {}",
+                value(result),
+                value(cond),
+                value(then_val),
+                value(else_val)
+            ),
+            Instruction::Phi { result, sources } => {
+                let sources: Vec<String> = sources
+                    .iter()
+                    .map(|(source, block)| format!("[{}, {}]", value(source), name(block)))
+                    .collect();
+                write!(f, "{} = phi {}", value(result), sources.join(", "))
+            }
+            Instruction::Label(label) => write!(f, "{}:", name(label)),
+            Instruction::Jump(target) => write!(f, "jump {}", name(target)),
+            Instruction::BranchIfZero { cond, target } => {
+                write!(f, "branch_if_zero {}, {}", value(cond), name(target))
+            }
+            Instruction::Exit(code) => write!(f, "exit {}", value(code)),
+        }
+    }
+}
+
+// One instruction per line
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for inst in &self.instructions {
+            writeln!(f, "{}", inst.display(&self.symbols))?;
+        }
+        Ok(())
+    }
+}
+
+impl Program {
+    // The listing Display gives, with operands annotated by kind as in
+    // Instruction::display_annotated
+    pub fn to_annotated_string(&self) -> String {
+        self.instructions
+            .iter()
+            .map(|inst| format!("{}\n", inst.display_annotated(&self.symbols)))
+            .collect()
+    }
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct LoweringError {
+    message: String,
+}
+
+impl fmt::Display for LoweringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Lowering error: {}", self.message)
+    }
+}
+
+impl Error for LoweringError {}
+
+#[derive(Debug)]
+pub struct FoldError {
+    message: String,
+}
+
+impl fmt::Display for FoldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Constant folding error: {}", self.message)
+    }
+}
+
+impl Error for FoldError {}
+
+#[derive(Debug)]
+pub struct UnknownPass {
+    name: String,
+}
+
+impl fmt::Display for UnknownPass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown optimization pass: {}", self.name)
     }
+}
+
+impl Error for UnknownPass {}
+
+// Why run_pass failed: the name matched no pass, or the pass itself failed
+#[derive(Debug)]
+pub enum PassError {
+    UnknownPass(UnknownPass),
+    Fold(FoldError),
+}
+
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PassError::UnknownPass(err) => err.fmt(f),
+            PassError::Fold(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for PassError {}
+
+impl From<FoldError> for PassError {
+    fn from(err: FoldError) -> Self {
+        PassError::Fold(err)
+    }
+}
+
+// Operands that don't name a value are integer literals
+fn literal_value(operand: Symbol, symbols: &Interner) -> Option<i64> {
+    symbols.resolve(operand).parse().ok()
+}
+
+fn translate_literal(
+    value: i64,
+    ir: &mut Program,
+    target: Option<&str>,
+    span: Option<Span>,
+) -> Symbol {
+    let Some(name) = target else {
+        return ir.intern(&value.to_string());
+    };
+    let result = gen_name(name, ir);
+    ir.instructions.push(Instruction::Constant {
+        result,
+        value,
+        span,
+    });
     result
 }
 
-fn translate_expr(expr: &Expr, ir: &mut Program, target: Option<&str>) -> String {
-    match expr {
-        Expr::Integer(value) => translate_literal(*value, ir, target),
-        Expr::Variable(name) => format!("{}.{}", name, ir.variables.get(name).unwrap()),
+fn translate_expr(
+    expr: &Spanned<Expr>,
+    ir: &mut Program,
+    target: Option<&str>,
+) -> Result<Symbol, LoweringError> {
+    let span = Some(expr.span);
+    match &expr.node {
+        // one written in hex can be printed back that way
+        Expr::Integer(value, radix) => {
+            let result = translate_literal(*value, ir, target, span);
+            if target.is_some() && *radix == Radix::Hex {
+                ir.hex_constants.insert(result);
+            }
+            Ok(result)
+        }
+        // Booleans share the integer representation: false is 0 and true is 1,
+        // matching what folded comparisons and logical ops produce
+        Expr::Boolean(value) => Ok(translate_literal(*value as i64, ir, target, span)),
+        Expr::Variable(name) => {
+            let source = match ir.lookup(name) {
+                Some(source) => source,
+                None => {
+                    return Err(LoweringError {
+                        message: format!("Use of undeclared variable `{}`", name),
+                    })
+                }
+            };
+            match target {
+                Some(target) => {
+                    let result = gen_name(target, ir);
+                    ir.instructions.push(Instruction::Copy {
+                        result,
+                        source,
+                        span,
+                    });
+                    Ok(result)
+                }
+                None => Ok(source),
+            }
+        }
+        // The right side only runs when the left doesn't decide the result,
+        // as in the interpreter. For `&&`:
+        //     branch_if_zero left, end.N
+        //   rhs.N:
+        //     <right>
+        //   end.N:
+        //     result = phi [0, <block of left>], [right, <block of right>]
+        // `||` branches to rhs.N instead and jumps to end.N with 1. Both
+        // sides are booleans, so they are already 0 or 1.
+        Expr::Binary {
+            op: op @ (BinaryOp::And | BinaryOp::Or),
+            left,
+            right,
+        } => {
+            let left_var = translate_expr(left, ir, None)?;
+            let end = gen_name("end", ir);
+            let rhs = gen_name("rhs", ir);
+            let decided = (current_block(ir), ir.scopes.clone());
+            let (branch_target, decided_value) = match op {
+                BinaryOp::And => (end, "0"),
+                _ => (rhs, "1"),
+            };
+            ir.instructions.push(Instruction::BranchIfZero {
+                cond: left_var,
+                target: branch_target,
+            });
+            if let BinaryOp::Or = op {
+                ir.instructions.push(Instruction::Jump(end));
+            }
+            ir.instructions.push(Instruction::Label(rhs));
+            let right_var = translate_expr(right, ir, None)?;
+            let right_block = current_block(ir);
+            ir.instructions.push(Instruction::Label(end));
+            // a call on the right may have assigned variables
+            let exits = [decided.clone(), (right_block, ir.scopes.clone())];
+            join(ir, &exits)?;
+
+            let decided_value = ir.intern(decided_value);
+            let result = gen_name(target.unwrap_or("bin"), ir);
+            ir.instructions.push(Instruction::Phi {
+                result,
+                sources: vec![(decided_value, decided.0), (right_var, right_block)],
+            });
+            Ok(result)
+        }
         Expr::Binary { op, left, right } => {
-            let left_var = match left.as_ref() {
-                Expr::Integer(value) => translate_literal(*value, ir, None),
-                _ => translate_expr(&left, ir, None),
+            let left_var = match &left.node {
+                Expr::Integer(value, _) => translate_literal(*value, ir, None, None),
+                _ => translate_expr(left, ir, None)?,
             };
-            let right_var = match right.as_ref() {
-                Expr::Integer(value) => translate_literal(*value, ir, None),
-                _ => translate_expr(&right, ir, None),
+            let right_var = match &right.node {
+                Expr::Integer(value, _) => translate_literal(*value, ir, None, None),
+                _ => translate_expr(right, ir, None)?,
             };
             let result = if let Some(name) = target {
                 gen_name(name, ir)
@@ -67,118 +603,2602 @@ fn translate_expr(expr: &Expr, ir: &mut Program, target: Option<&str>) -> String
             };
 
             ir.instructions.push(Instruction::Binary {
-                result: result.clone(),
+                result,
                 op: op.clone(),
                 left: left_var,
                 right: right_var,
+                span,
+            });
+            Ok(result)
+        }
+        // Unary ops lower to an equivalent Binary instead of a dedicated
+        // instruction, so every pass that understands Binary handles them as is:
+        // negation is a subtraction from zero and `!x` is `x == 0`
+        Expr::Unary { op, operand } => {
+            let operand_var = translate_expr(operand, ir, None)?;
+            let result = if let Some(name) = target {
+                gen_name(name, ir)
+            } else {
+                gen_name("bin", ir)
+            };
+
+            let zero = ir.intern("0");
+            let (op, left, right) = match op {
+                UnaryOp::Negate => (BinaryOp::Subtract, zero, operand_var),
+                UnaryOp::Not => (BinaryOp::Equal, operand_var, zero),
+            };
+            ir.instructions.push(Instruction::Binary {
+                result,
+                op,
+                left,
+                right,
+                span,
+            });
+            Ok(result)
+        }
+        // A function of the program's own takes precedence over a builtin
+        // of the same name, as in the type checker
+        Expr::Call { callee, args } => {
+            let Expr::Variable(name) = &callee.node else {
+                return Err(LoweringError {
+                    message: "only functions can be called by name".to_string(),
+                });
+            };
+            if name == TYPEOF {
+                return Err(LoweringError {
+                    message: format!(
+                        "`{}` is answered by the type checker and has no code to lower",
+                        TYPEOF
+                    ),
+                });
+            }
+            if let Some(function) = ir.functions.get(name).cloned() {
+                return Ok(inline_call(name, function, args, target.as_slice(), ir)?[0]);
+            }
+            let Some(builtin) = Builtin::from_name(name) else {
+                return Err(LoweringError {
+                    message: format!("Call to unknown function `{}`", name),
+                });
+            };
+            if args.len() != builtin.arity() {
+                return Err(LoweringError {
+                    message: format!(
+                        "`{}` takes {} arguments but {} were given",
+                        builtin.name(),
+                        builtin.arity(),
+                        args.len()
+                    ),
+                });
+            }
+            let values = args
+                .iter()
+                .map(|arg| translate_expr(arg, ir, None))
+                .collect::<Result<Vec<Symbol>, LoweringError>>()?;
+            let args =
+                positional(name, args, values, &[]).map_err(|message| LoweringError { message })?;
+            let result = if let Some(name) = target {
+                gen_name(name, ir)
+            } else {
+                gen_name("call", ir)
+            };
+
+            // min and max pick one of their arguments, so a comparison can
+            // choose which without a call
+            let compare = match builtin {
+                Builtin::Min => Some(BinaryOp::Less),
+                Builtin::Max => Some(BinaryOp::Greater),
+                Builtin::Abs | Builtin::Pow => None,
+            };
+            if let (Some(op), &[left, right]) = (compare, &args[..]) {
+                let cond = gen_name("bin", ir);
+                ir.instructions.push(Instruction::Binary {
+                    result: cond,
+                    op,
+                    left,
+                    right,
+                    span,
+                });
+                ir.instructions.push(Instruction::Select {
+                    result,
+                    cond,
+                    then_val: left,
+                    else_val: right,
+                    span,
+                });
+                return Ok(result);
+            }
+            ir.instructions.push(Instruction::Call {
+                result,
+                builtin,
+                args,
+                span,
             });
-            result
+            Ok(result)
+        }
+        Expr::Seq(exprs) => {
+            let (last, rest) = exprs.split_last().expect("a sequence has two or more");
+            for expr in rest {
+                translate_expr(expr, ir, None)?;
+            }
+            translate_expr(last, ir, target)
+        }
+        // the variable gets a new version and the expression its value
+        Expr::Assign {
+            target: name,
+            value,
+        } => {
+            if ir.lookup(name).is_none() {
+                return Err(LoweringError {
+                    message: format!("Assignment to undeclared variable `{}`", name),
+                });
+            }
+            let value = translate_expr(value, ir, Some(name))?;
+            ir.rebind(name, value);
+            match target {
+                Some(target) => {
+                    let result = gen_name(target, ir);
+                    ir.instructions.push(Instruction::Copy {
+                        result,
+                        source: value,
+                        span,
+                    });
+                    Ok(result)
+                }
+                None => Ok(value),
+            }
         }
+        Expr::Named { value, .. } => translate_expr(value, ir, target),
+        Expr::Tuple(_) => Err(LoweringError {
+            message: "a tuple has no single value".to_string(),
+        }),
+    }
+}
+
+// The elements of a tuple, each named after its target, or the one value of
+// anything else
+fn translate_values(
+    expr: &Spanned<Expr>,
+    ir: &mut Program,
+    targets: &[&str],
+) -> Result<Vec<Symbol>, LoweringError> {
+    match &expr.node {
+        Expr::Tuple(exprs) => exprs
+            .iter()
+            .enumerate()
+            .map(|(index, expr)| translate_expr(expr, ir, targets.get(index).copied()))
+            .collect(),
+        Expr::Call { callee, args } => match &callee.node {
+            Expr::Variable(name) if ir.functions.contains_key(name) => {
+                let function = ir.functions[name].clone();
+                inline_call(name, function, args, targets, ir)
+            }
+            _ => Ok(vec![translate_expr(expr, ir, targets.first().copied())?]),
+        },
+        _ => Ok(vec![translate_expr(expr, ir, targets.first().copied())?]),
     }
 }
 
-fn gen_name(name: &str, ir: &mut Program) -> String {
+fn gen_name(name: &str, ir: &mut Program) -> Symbol {
     let counter = ir.variables.entry(name.to_string()).or_insert(0);
     *counter += 1;
-    format!("{}.{}", name, counter)
+    let counter = *counter;
+    ir.intern(&format!("{}.{}", name, counter))
 }
 
-pub fn lower(statements: Vec<Statement>) -> Program {
-    let mut ir = Program::new();
+fn lookup_in(scopes: &[HashMap<String, Symbol>], name: &str) -> Option<Symbol> {
+    scopes
+        .iter()
+        .rev()
+        .find_map(|scope| scope.get(name).copied())
+}
 
-    for stmt in statements {
-        match stmt {
-            Statement::Let { name, value, .. } => {
-                translate_expr(&value, &mut ir, Some(&name));
-            }
-            Statement::Assignment { target, value } => {
-                translate_expr(&value, &mut ir, Some(&target));
+impl Program {
+    fn lookup(&self, name: &str) -> Option<Symbol> {
+        lookup_in(&self.scopes, name)
+    }
+
+    // Points the innermost variable called `name` at a new value, returning
+    // false when there is none
+    fn rebind(&mut self, name: &str, value: Symbol) -> bool {
+        let scope = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find(|scope| scope.contains_key(name));
+        match scope {
+            Some(scope) => {
+                scope.insert(name.to_string(), value);
+                true
             }
+            None => false,
         }
     }
+}
 
-    ir
+// A program with a top-level `main` runs it after the top-level statements,
+// with the call inlined like any other and its value ending the program
+pub fn lower(statements: Vec<Spanned<Statement>>) -> Result<Program, LoweringError> {
+    let mut ir = Program::new();
+    register_functions(&statements, &mut ir);
+    lower_statements(statements, &mut ir)?;
+    if let Some(main) = ir.functions.get("main").cloned() {
+        let code = inline_call("main", main, &[], &[], &mut ir)?;
+        ir.instructions.push(Instruction::Exit(code[0]));
+    }
+    ir.final_values = ir.scopes[0]
+        .iter()
+        .map(|(name, &value)| (name.clone(), value))
+        .collect();
+    Ok(ir)
 }
 
-fn constant_folding(program: &mut Program) {
-    let mut known_constants: HashMap<String, i64> = HashMap::new();
-    let mut modified = true;
+// The bindings in effect at the end of a block, labelled with that block
+type Exit = (Symbol, Vec<HashMap<String, Symbol>>);
 
-    while modified {
-        modified = false;
-        let mut i = 0;
+// A loop being lowered. `break` and `continue` leave the block they are in,
+// so each records its exit to be merged at the target.
+#[derive(Debug, Clone)]
+struct Loop {
+    label: Option<String>,
+    header: Symbol,
+    end: Symbol,
+    // how many scopes were open at the loop itself
+    depth: usize,
+    breaks: Vec<Exit>,
+    continues: Vec<Exit>,
+}
 
-        while i < program.instructions.len() {
-            let instruction = program.instructions[i].clone();
-            match instruction {
-                Instruction::Constant { result, value } => {
-                    known_constants.insert(result.clone(), value);
-                }
-                Instruction::Binary {
-                    result,
-                    op,
-                    left,
-                    right,
-                } => {
-                    let left_val = known_constants
-                        .get(&left)
-                        .copied()
-                        .or_else(|| left.parse::<i64>().ok());
-                    let right_val = known_constants
-                        .get(&right)
-                        .copied()
-                        .or_else(|| right.parse::<i64>().ok());
-
-                    if let (Some(left_val), Some(right_val)) = (left_val, right_val) {
-                        let new_value = match op {
-                            BinaryOp::Add => left_val + right_val,
-                            BinaryOp::Subtract => left_val - right_val,
-                            BinaryOp::Multiply => left_val * right_val,
-                            BinaryOp::Divide => left_val / right_val,
-                        };
-                        program.instructions[i] = Instruction::Constant {
-                            result: result.clone(),
-                            value: new_value,
-                        };
-                        known_constants.insert(result.clone(), new_value);
-                        modified = true;
-                    }
-                }
-            }
-            i += 1;
+// A function declared in a block being lowered. Every call is inlined, so
+// the body is kept until the block ends.
+#[derive(Debug, Clone)]
+struct Function {
+    params: Vec<String>,
+    returns_value: bool,
+    body: Vec<Spanned<Statement>>,
+    // how many scopes were open where it was declared, which are the only
+    // ones its body can see
+    depth: usize,
+}
+
+// A call being inlined. Each `return` records its exit and its value, to be
+// merged at the end of the call like the breaks of a loop.
+#[derive(Debug, Clone)]
+struct Frame {
+    name: String,
+    end: Symbol,
+    depth: usize,
+    // what the returned values are named after, as for any other expression;
+    // a tuple has one name for each element
+    targets: Vec<String>,
+    // holding each element of a returned tuple
+    returns: Vec<(Exit, Vec<Symbol>)>,
+}
+
+// Lowers a call as the function's body, with each parameter bound to its
+// argument, giving the value it returns or each element of a tuple:
+//     <args>
+//     <body, where each return jumps to return.N>
+//   return.N:
+//     <phis>
+// The body can't see the caller's variables, only those in scope where the
+// function was declared, and the caller's loops don't extend into it. A
+// function calling itself would be inlined forever, so recursion is an error.
+fn inline_call(
+    name: &str,
+    function: Function,
+    args: &[Spanned<Expr>],
+    targets: &[&str],
+    ir: &mut Program,
+) -> Result<Vec<Symbol>, LoweringError> {
+    if ir.calls.iter().any(|frame| frame.name == name) {
+        return Err(LoweringError {
+            message: format!("recursive function `{}` cannot be inlined", name),
+        });
+    }
+    if args.len() != function.params.len() {
+        return Err(LoweringError {
+            message: format!(
+                "`{}` takes {} arguments but {} were given",
+                name,
+                function.params.len(),
+                args.len()
+            ),
+        });
+    }
+    let values = args
+        .iter()
+        .map(|arg| translate_expr(arg, ir, None))
+        .collect::<Result<Vec<Symbol>, LoweringError>>()?;
+    let params: Vec<&str> = function.params.iter().map(String::as_str).collect();
+    let args =
+        positional(name, args, values, &params).map_err(|message| LoweringError { message })?;
+    let end = gen_name("return", ir);
+    let depth = function.depth;
+    let caller_scopes = ir.scopes.split_off(depth);
+    let caller_loops = std::mem::take(&mut ir.loops);
+    ir.scopes
+        .push(function.params.into_iter().zip(args).collect());
+    ir.calls.push(Frame {
+        name: name.to_string(),
+        end,
+        depth,
+        targets: targets.iter().map(|target| target.to_string()).collect(),
+        returns: Vec::new(),
+    });
+
+    let falls_through = lower_scoped(function.body, ir);
+    let mut frame = ir.calls.pop().expect("pushed above");
+    if falls_through? {
+        if function.returns_value {
+            return Err(LoweringError {
+                message: format!("`{}` can end without returning a value", name),
+            });
         }
+        let zero = ir.intern("0");
+        frame.returns.push((exit_at(ir, depth), vec![zero]));
     }
-}
+    // a return at the very end of the body can fall through instead
+    if ir.instructions.last() == Some(&Instruction::Jump(end)) {
+        ir.instructions.pop();
+    }
+    ir.instructions.push(Instruction::Label(end));
+    let exits: Vec<Exit> = frame.returns.iter().map(|(exit, _)| exit.clone()).collect();
+    join(ir, &exits)?;
+    ir.scopes.extend(caller_scopes);
+    ir.loops = caller_loops;
 
-fn dead_code_elimination(program: &mut Program) {
-    let mut uses: HashMap<String, usize> = HashMap::new();
+    let mut results = Vec::new();
+    for (index, _) in frame.returns[0].1.iter().enumerate() {
+        let sources: Vec<(Symbol, Symbol)> = frame
+            .returns
+            .iter()
+            .map(|((block, _), values)| (values[index], *block))
+            .collect();
+        if sources.iter().all(|(value, _)| *value == sources[0].0) {
+            results.push(sources[0].0);
+            continue;
+        }
+        let result = gen_name(targets.get(index).copied().unwrap_or("call"), ir);
+        ir.instructions.push(Instruction::Phi { result, sources });
+        results.push(result);
+    }
+    Ok(results)
+}
 
-    for inst in &program.instructions {
-        match inst {
-            Instruction::Binary {
-                result,
-                op,
-                left,
-                right,
-            } => {
-                *uses.entry(left.clone()).or_default() += 1;
-                *uses.entry(right.clone()).or_default() += 1;
+// Continues at a join point reached from each of `exits`. A variable bound
+// to different values on the way in gets a phi merging them; the blocks the
+// exits come from have all ended, so they share the same enclosing scopes.
+fn join(ir: &mut Program, exits: &[Exit]) -> Result<(), LoweringError> {
+    let Some((_, scopes)) = exits.first() else {
+        return Ok(());
+    };
+    let mut scopes = scopes.clone();
+    for (depth, scope) in scopes.iter_mut().enumerate() {
+        // sorted, so phis come out in the same order on every run
+        let names: BTreeSet<String> = scope.keys().cloned().collect();
+        for name in names {
+            let sources: Option<Vec<(Symbol, Symbol)>> = exits
+                .iter()
+                .map(|(block, bindings)| Some((*bindings.get(depth)?.get(&name)?, *block)))
+                .collect();
+            let Some(sources) = sources else {
+                return Err(LoweringError {
+                    message: format!("`{}` has no value on every path to a join", name),
+                });
+            };
+            if sources.iter().all(|(value, _)| *value == sources[0].0) {
+                continue;
             }
-            _ => {}
+            let result = gen_name(&name, ir);
+            ir.instructions.push(Instruction::Phi { result, sources });
+            scope.insert(name, result);
         }
     }
+    ir.scopes = scopes;
+    Ok(())
+}
 
-    program.instructions.retain(|inst| match inst {
-        Instruction::Constant { result, .. } | Instruction::Binary { result, .. } => {
-            uses.get(result).copied().unwrap_or(0) > 0
-        }
-    })
+// Lowers a nested block, whose `let`s are dropped again when it ends
+fn lower_scoped(
+    statements: Vec<Spanned<Statement>>,
+    ir: &mut Program,
+) -> Result<bool, LoweringError> {
+    ir.scopes.push(HashMap::new());
+    let lowered = lower_block(statements, ir);
+    ir.scopes.pop();
+    lowered
 }
 
-pub fn optimize(program: &mut Program) {
-    println!("\nOriginal IR: {:?}", program.instructions);
-    dead_code_elimination(program);
-    println!("\nDead Code IR: {:?}", program.instructions);
-    constant_folding(program);
-    println!("\nConstant Fold IR: {:?}", program.instructions);
+// The label of the block instructions are currently appended to
+fn current_block(ir: &mut Program) -> Symbol {
+    let label = ir.instructions.iter().rev().find_map(|inst| match inst {
+        Instruction::Label(name) => Some(*name),
+        _ => None,
+    });
+    label.unwrap_or_else(|| ir.intern(ENTRY_BLOCK))
+}
+
+// Names assigned anywhere in `statements`, which a loop around them has to
+// merge at its top. Nested functions have variables of their own. Returns
+// whether any of them call a function, which may assign any variable it
+// can see.
+fn assigned_names(
+    statements: &[Spanned<Statement>],
+    functions: &HashMap<String, Function>,
+    names: &mut BTreeSet<String>,
+) -> bool {
+    let mut calls = false;
+    for stmt in statements {
+        match &stmt.node {
+            Statement::Let { value, .. }
+            | Statement::Destructure { value, .. }
+            | Statement::Const { value, .. } => {
+                calls |= assigned_in_expr(value, functions, names);
+            }
+            Statement::Assignment { target, value } => {
+                names.insert(target.clone());
+                calls |= assigned_in_expr(value, functions, names);
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                calls |= assigned_in_expr(condition, functions, names);
+                calls |= assigned_names(then_branch, functions, names);
+                if let Some(else_branch) = else_branch {
+                    calls |= assigned_names(else_branch, functions, names);
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                calls |= assigned_in_expr(condition, functions, names);
+                calls |= assigned_names(body, functions, names);
+            }
+            Statement::Return(Some(value))
+            | Statement::Expression(value)
+            | Statement::Result(value) => {
+                calls |= assigned_in_expr(value, functions, names);
+            }
+            _ => {}
+        }
+    }
+    calls
+}
+
+// Like assigned_names, for the assignments inside an expression
+fn assigned_in_expr(
+    expr: &Expr,
+    functions: &HashMap<String, Function>,
+    names: &mut BTreeSet<String>,
+) -> bool {
+    match expr {
+        Expr::Integer(..) | Expr::Boolean(_) | Expr::Variable(_) => false,
+        Expr::Binary { left, right, .. } => {
+            let left = assigned_in_expr(left, functions, names);
+            assigned_in_expr(right, functions, names) || left
+        }
+        Expr::Unary { operand, .. } => assigned_in_expr(operand, functions, names),
+        Expr::Call { args, .. } => {
+            let mut calls = calls_function(expr, functions);
+            for arg in args {
+                calls |= assigned_in_expr(arg, functions, names);
+            }
+            calls
+        }
+        Expr::Seq(exprs) | Expr::Tuple(exprs) => {
+            let mut calls = false;
+            for expr in exprs {
+                calls |= assigned_in_expr(expr, functions, names);
+            }
+            calls
+        }
+        Expr::Assign { target, value } => {
+            names.insert(target.clone());
+            assigned_in_expr(value, functions, names)
+        }
+        Expr::Named { value, .. } => assigned_in_expr(value, functions, names),
+    }
+}
+
+// Whether evaluating `expr` calls a function other than a builtin
+fn calls_function(expr: &Expr, functions: &HashMap<String, Function>) -> bool {
+    match expr {
+        Expr::Integer(..) | Expr::Boolean(_) | Expr::Variable(_) => false,
+        Expr::Binary { left, right, .. } => {
+            calls_function(left, functions) || calls_function(right, functions)
+        }
+        Expr::Unary { operand, .. } => calls_function(operand, functions),
+        Expr::Call { callee, args } => {
+            let builtin = match &callee.node {
+                Expr::Variable(name) => {
+                    !functions.contains_key(name) && Builtin::from_name(name).is_some()
+                }
+                _ => false,
+            };
+            !builtin || args.iter().any(|arg| calls_function(arg, functions))
+        }
+        Expr::Seq(exprs) | Expr::Tuple(exprs) => {
+            exprs.iter().any(|expr| calls_function(expr, functions))
+        }
+        Expr::Assign { value, .. } | Expr::Named { value, .. } => calls_function(value, functions),
+    }
+}
+
+// The exit of the current block, seen from a join `depth` scopes deep
+fn exit_at(ir: &mut Program, depth: usize) -> Exit {
+    let mut scopes = ir.scopes.clone();
+    scopes.truncate(depth);
+    (current_block(ir), scopes)
+}
+
+// The loop a `break` or `continue` with `label` applies to
+fn target_loop<'a>(
+    ir: &'a mut Program,
+    label: &Option<String>,
+) -> Result<&'a mut Loop, LoweringError> {
+    let target = ir
+        .loops
+        .iter_mut()
+        .rev()
+        .find(|target| label.is_none() || target.label == *label);
+    target.ok_or_else(|| LoweringError {
+        message: match label {
+            Some(label) => format!("no enclosing loop labelled `{}`", label),
+            None => "`break` or `continue` outside of a loop".to_string(),
+        },
+    })
+}
+
+// Returns whether control can reach the end of the block. Functions are
+// registered up front, as in the type checker, so they can be called before
+// their declaration, and a nested one is forgotten again when its block ends.
+fn lower_block(
+    statements: Vec<Spanned<Statement>>,
+    ir: &mut Program,
+) -> Result<bool, LoweringError> {
+    let shadowed = register_functions(&statements, ir);
+    let lowered = lower_statements(statements, ir);
+    for (name, outer) in shadowed.into_iter().rev() {
+        match outer {
+            Some(outer) => ir.functions.insert(name, outer),
+            None => ir.functions.remove(&name),
+        };
+    }
+    lowered
+}
+
+// Returns each function declared in `statements` with the one of the same
+// name it hides, if any
+fn register_functions(
+    statements: &[Spanned<Statement>],
+    ir: &mut Program,
+) -> Vec<(String, Option<Function>)> {
+    let mut shadowed = Vec::new();
+    for stmt in statements {
+        if let Statement::Function {
+            name,
+            params,
+            return_type,
+            body,
+        } = &stmt.node
+        {
+            let function = Function {
+                params: params.iter().map(|(name, _)| name.clone()).collect(),
+                returns_value: returns_value(return_type.clone(), body),
+                body: body.clone(),
+                depth: ir.scopes.len(),
+            };
+            shadowed.push((name.clone(), ir.functions.insert(name.clone(), function)));
+        }
+    }
+    shadowed
+}
+
+// Statements after a `break`, `continue` or `return` can't run, so they are
+// not lowered at all
+fn lower_statements(
+    statements: Vec<Spanned<Statement>>,
+    ir: &mut Program,
+) -> Result<bool, LoweringError> {
+    for stmt in statements {
+        match stmt.node {
+            // consts lower like lets, so folding sees them as known constants.
+            // A `let` shadowing an outer variable still gets a fresh version,
+            // so the outer one's value is untouched once the block ends.
+            Statement::Let { name, value, .. } | Statement::Const { name, value, .. } => {
+                let value = translate_expr(&value, ir, Some(&name))?;
+                let scope = ir.scopes.last_mut().expect("a scope is always open");
+                scope.insert(name, value);
+            }
+            Statement::Destructure { names, value, .. } => {
+                let targets: Vec<&str> = names.iter().map(String::as_str).collect();
+                let values = translate_values(&value, ir, &targets)?;
+                if values.len() != names.len() {
+                    return Err(LoweringError {
+                        message: format!(
+                            "{} values can't be destructured into {} names",
+                            values.len(),
+                            names.len()
+                        ),
+                    });
+                }
+                let scope = ir.scopes.last_mut().expect("a scope is always open");
+                scope.extend(names.into_iter().zip(values));
+            }
+            Statement::Assignment { target, value } => {
+                if ir.lookup(&target).is_none() {
+                    return Err(LoweringError {
+                        message: format!("Assignment to undeclared variable `{}`", target),
+                    });
+                }
+                let value = translate_expr(&value, ir, Some(&target))?;
+                ir.rebind(&target, value);
+            }
+            // Falls through into the then block when the condition is nonzero:
+            //     branch_if_zero cond, else.N
+            //   then.N:
+            //     <then>
+            //     jump endif.N
+            //   else.N:
+            //     <else>
+            //   endif.N:
+            //     <phis>
+            // Without an else block the branch goes straight to endif.N. Each
+            // branch starts from the versions in effect before the if, and a
+            // variable that ends up with different versions gets a phi. Each
+            // branch is a block of its own, so only variables declared before
+            // the if can be merged. A branch that ends in `break` or
+            // `continue` never reaches endif.N and takes no part in the merge.
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let cond = translate_expr(&condition, ir, None)?;
+                let end = gen_name("endif", ir);
+                let then = gen_name("then", ir);
+                let before = ir.scopes.clone();
+                let mut exits = Vec::new();
+
+                match else_branch {
+                    Some(else_branch) => {
+                        let otherwise = gen_name("else", ir);
+                        ir.instructions.push(Instruction::BranchIfZero {
+                            cond,
+                            target: otherwise,
+                        });
+                        ir.instructions.push(Instruction::Label(then));
+                        if lower_scoped(then_branch, ir)? {
+                            exits.push((current_block(ir), ir.scopes.clone()));
+                            ir.instructions.push(Instruction::Jump(end));
+                        }
+
+                        ir.instructions.push(Instruction::Label(otherwise));
+                        ir.scopes = before;
+                        if lower_scoped(else_branch, ir)? {
+                            exits.push((current_block(ir), ir.scopes.clone()));
+                        }
+                    }
+                    None => {
+                        let skipped = (current_block(ir), before);
+                        ir.instructions
+                            .push(Instruction::BranchIfZero { cond, target: end });
+                        ir.instructions.push(Instruction::Label(then));
+                        if lower_scoped(then_branch, ir)? {
+                            exits.push((current_block(ir), ir.scopes.clone()));
+                        }
+                        exits.push(skipped);
+                    }
+                }
+                ir.instructions.push(Instruction::Label(end));
+
+                join(ir, &exits)?;
+                if exits.is_empty() {
+                    return Ok(false);
+                }
+            }
+            //   while.N:
+            //     <phis>
+            //     branch_if_zero cond, endwhile.N
+            //   do.N:
+            //     <body>
+            //     jump while.N
+            //   endwhile.N:
+            //     <phis>
+            // A variable assigned in the body gets a phi at while.N before the
+            // body is lowered, so the body and the condition read the value
+            // from the current iteration. The values coming back from the end
+            // of the body and from each `continue` are filled in afterward.
+            // endwhile.N merges the condition failing with each `break`.
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => {
+                let header = gen_name("while", ir);
+                let end = gen_name("endwhile", ir);
+                let entered = current_block(ir);
+                ir.instructions.push(Instruction::Label(header));
+
+                let mut assigned = BTreeSet::new();
+                if assigned_names(&body, &ir.functions, &mut assigned)
+                    || calls_function(&condition, &ir.functions)
+                {
+                    assigned.extend(ir.scopes.iter().flat_map(|scope| scope.keys().cloned()));
+                }
+                // (index of the phi, variable, value before the loop)
+                let mut header_phis = Vec::new();
+                for name in assigned {
+                    let Some(before) = ir.lookup(&name) else {
+                        continue;
+                    };
+                    let result = gen_name(&name, ir);
+                    header_phis.push((ir.instructions.len(), name.clone(), before));
+                    ir.instructions.push(Instruction::Phi {
+                        result,
+                        sources: vec![(before, entered)],
+                    });
+                    ir.rebind(&name, result);
+                }
+
+                let cond = translate_expr(&condition, ir, None)?;
+                let depth = ir.scopes.len();
+                let finished = exit_at(ir, depth);
+                ir.instructions
+                    .push(Instruction::BranchIfZero { cond, target: end });
+                let start = gen_name("do", ir);
+                ir.instructions.push(Instruction::Label(start));
+
+                ir.loops.push(Loop {
+                    label,
+                    header,
+                    end,
+                    depth,
+                    breaks: Vec::new(),
+                    continues: Vec::new(),
+                });
+                let falls_through = lower_scoped(body, ir);
+                let mut lowered = ir.loops.pop().expect("pushed above");
+                if falls_through? {
+                    lowered.continues.push(exit_at(ir, depth));
+                    ir.instructions.push(Instruction::Jump(header));
+                }
+
+                for (index, name, before) in header_phis {
+                    let Instruction::Phi { result, sources } = &mut ir.instructions[index] else {
+                        unreachable!("header phis are only ever patched here");
+                    };
+                    let result = *result;
+                    for (block, scopes) in &lowered.continues {
+                        let value = lookup_in(scopes, &name).expect("declared before the loop");
+                        sources.push((value, *block));
+                    }
+                    // never reassigned after all, as when the only assignment
+                    // was to a variable of the same name declared in the body
+                    if sources[1..].iter().all(|&(value, _)| value == result) {
+                        ir.instructions[index] = Instruction::Copy {
+                            result,
+                            source: before,
+                            span: None,
+                        };
+                    }
+                }
+
+                ir.instructions.push(Instruction::Label(end));
+                let mut exits = vec![finished];
+                exits.extend(lowered.breaks);
+                join(ir, &exits)?;
+            }
+            Statement::Break(label) => {
+                let depth = target_loop(ir, &label)?.depth;
+                let exit = exit_at(ir, depth);
+                let target = target_loop(ir, &label)?;
+                target.breaks.push(exit);
+                let end = target.end;
+                ir.instructions.push(Instruction::Jump(end));
+                return Ok(false);
+            }
+            Statement::Continue(label) => {
+                let depth = target_loop(ir, &label)?.depth;
+                let exit = exit_at(ir, depth);
+                let target = target_loop(ir, &label)?;
+                target.continues.push(exit);
+                let header = target.header;
+                ir.instructions.push(Instruction::Jump(header));
+                return Ok(false);
+            }
+            // registered by lower_block, and lowered wherever it's called
+            Statement::Function { .. } => {}
+            Statement::Return(value) => {
+                let Some(frame) = ir.calls.last() else {
+                    return Err(LoweringError {
+                        message: "`return` outside of a function".to_string(),
+                    });
+                };
+                let (depth, targets) = (frame.depth, frame.targets.clone());
+                let targets: Vec<&str> = targets.iter().map(String::as_str).collect();
+                let values = match value {
+                    Some(value) => translate_values(&value, ir, &targets)?,
+                    None => vec![ir.intern("0")],
+                };
+                let exit = exit_at(ir, depth);
+                let frame = ir.calls.last_mut().expect("checked above");
+                frame.returns.push((exit, values));
+                let end = frame.end;
+                ir.instructions.push(Instruction::Jump(end));
+                return Ok(false);
+            }
+            // the value is unused, so DCE removes whatever computes it; only
+            // the interpreter gives the program a result
+            Statement::Expression(expr) | Statement::Result(expr) => {
+                translate_expr(&expr, ir, None)?;
+            }
+            Statement::Import(path) => {
+                return Err(LoweringError {
+                    message: format!("unresolved import {:?}", path),
+                });
+            }
+        }
+    }
+    Ok(true)
+}
+
+// Labels that a jump or branch further down can go back to
+fn back_edge_targets(instructions: &[Instruction]) -> HashSet<Symbol> {
+    let mut seen = HashSet::new();
+    let mut targets = HashSet::new();
+    for inst in instructions {
+        match inst {
+            Instruction::Label(label) => {
+                seen.insert(*label);
+            }
+            Instruction::Jump(target) | Instruction::BranchIfZero { target, .. }
+                if seen.contains(target) =>
+            {
+                targets.insert(*target);
+            }
+            _ => {}
+        }
+    }
+    targets
+}
+
+// Blocks that a branch on a known condition can never reach are skipped, so
+// code the program never runs, like the right side of a short-circuited
+// `&&`, can't fail to fold. Reachability is worked out in the same forward
+// sweep, so the targets of back edges are assumed reachable.
+fn constant_folding(program: &mut Program, policy: OverflowPolicy) -> Result<bool, FoldError> {
+    let entry = program.intern(ENTRY_BLOCK);
+    let mut known_constants: HashMap<Symbol, i64> = HashMap::new();
+    // the literal symbol for each value substituted so far, so each distinct
+    // value is formatted and interned once rather than at every use
+    let mut literals: HashMap<i64, Symbol> = HashMap::new();
+    let mut overflowed: HashSet<Symbol> = HashSet::new();
+    let mut changed = false;
+    let mut modified = true;
+
+    while modified {
+        modified = false;
+        let back_targets = back_edge_targets(&program.instructions);
+        // the block being swept, whether it can run at all, and whether
+        // control can fall off its last instruction so far into the next one
+        let (mut block, mut live, mut falls_through) = (entry, true, true);
+        // (from, to) pairs of blocks control can pass between
+        let mut edges: HashSet<(Symbol, Symbol)> = HashSet::new();
+        let mut seen: HashSet<Symbol> = HashSet::from([entry]);
+
+        for i in 0..program.instructions.len() {
+            if let Instruction::Label(label) = program.instructions[i] {
+                if live && falls_through {
+                    edges.insert((block, label));
+                }
+                live = back_targets.contains(&label) || edges.iter().any(|&(_, to)| to == label);
+                (block, falls_through) = (label, true);
+                seen.insert(label);
+                continue;
+            }
+            if !live {
+                continue;
+            }
+            // Uses of known values become literals, so even an instruction
+            // that can't fold no longer depends on the one defining them
+            let Program {
+                instructions,
+                symbols,
+                ..
+            } = program;
+            instructions[i].for_each_operand_mut(|operand| {
+                let Some(&value) = known_constants.get(operand) else {
+                    return;
+                };
+                *operand = *literals
+                    .entry(value)
+                    .or_insert_with(|| symbols.intern(&value.to_string()));
+                modified = true;
+                changed = true;
+            });
+            let symbols = &program.symbols;
+            let constant = |operand: Symbol| {
+                known_constants
+                    .get(&operand)
+                    .copied()
+                    .or_else(|| literal_value(operand, symbols))
+            };
+            // Borrowed rather than cloned, so a `Call` or `Phi` that doesn't
+            // fold no longer copies its operand list on every pass
+            let (result, evaluated) = match &program.instructions[i] {
+                Instruction::Constant { result, value, .. } => {
+                    known_constants.insert(*result, *value);
+                    continue;
+                }
+                Instruction::Binary {
+                    result,
+                    op,
+                    left,
+                    right,
+                    ..
+                } => match (constant(*left), constant(*right)) {
+                    (Some(left), Some(right)) => (result, policy.eval(op, left, right)),
+                    _ => continue,
+                },
+                Instruction::Copy { result, source, .. } => match constant(*source) {
+                    Some(value) => (result, Ok(value)),
+                    None => continue,
+                },
+                Instruction::Call {
+                    result,
+                    builtin,
+                    args,
+                    ..
+                } => {
+                    let values: Option<Vec<i64>> = args.iter().map(|arg| constant(*arg)).collect();
+                    match values {
+                        Some(values) => (result, policy.eval_builtin(*builtin, &values)),
+                        None => continue,
+                    }
+                }
+                // A known condition picks one operand, which is a constant or
+                // else copied
+                Instruction::Select {
+                    result,
+                    cond,
+                    then_val,
+                    else_val,
+                    ..
+                } => {
+                    let Some(cond) = constant(*cond) else {
+                        continue;
+                    };
+                    let source = if cond != 0 { *then_val } else { *else_val };
+                    match constant(source) {
+                        Some(value) => (result, Ok(value)),
+                        None => {
+                            let result = *result;
+                            let span = program.instructions[i].span();
+                            program.instructions[i] = Instruction::Copy {
+                                result,
+                                source,
+                                span,
+                            };
+                            modified = true;
+                            changed = true;
+                            continue;
+                        }
+                    }
+                }
+                // Whichever way control arrived, the value is the same. A
+                // source from a block further down arrives along a back edge,
+                // which may or may not be taken.
+                Instruction::Phi { result, sources } => {
+                    let values: Option<Vec<i64>> = sources
+                        .iter()
+                        .filter(|(_, from)| !seen.contains(from) || edges.contains(&(*from, block)))
+                        .map(|(value, _)| constant(*value))
+                        .collect();
+                    match values.as_deref() {
+                        Some([first, rest @ ..]) if rest.iter().all(|value| value == first) => {
+                            (result, Ok(*first))
+                        }
+                        _ => continue,
+                    }
+                }
+                Instruction::Jump(target) => {
+                    edges.insert((block, *target));
+                    falls_through = false;
+                    continue;
+                }
+                Instruction::BranchIfZero { cond, target } => {
+                    let cond = constant(*cond);
+                    if cond.is_none() || cond == Some(0) {
+                        edges.insert((block, *target));
+                    }
+                    falls_through = cond != Some(0);
+                    continue;
+                }
+                Instruction::Exit(_) => {
+                    falls_through = false;
+                    continue;
+                }
+                Instruction::Label(_) => continue,
+            };
+
+            let result = *result;
+            let value = match evaluated {
+                Ok(value) => value,
+                // Overflow under the Checked policy leaves the computation
+                // to runtime, so results don't depend on the build profile
+                Err(ArithError::Overflow) => {
+                    if overflowed.insert(result) {
+                        program.warnings.push(format!(
+                            "Arithmetic overflow computing {}; not folded",
+                            program.name(result)
+                        ));
+                    }
+                    continue;
+                }
+                Err(err) => {
+                    return Err(FoldError {
+                        message: format!("{} computing {}", err, program.name(result)),
+                    })
+                }
+            };
+            known_constants.insert(result, value);
+            // the constant stands for the same source as what it replaces
+            let span = program.instructions[i].span();
+            program.instructions[i] = Instruction::Constant {
+                result,
+                value,
+                span,
+            };
+            modified = true;
+            changed = true;
+        }
+    }
+    Ok(changed)
+}
+
+// Regroups constant operands across chains of the same associative op, so
+// `(a + 2) + 3` becomes `a + 5` even though `a` is unknown. Constants are only
+// combined when doing so cannot overflow.
+fn reassociation(program: &mut Program) -> bool {
+    let mut known_constants: HashMap<Symbol, i64> = HashMap::new();
+    // result -> (op, non-constant operand, constant operand)
+    let mut partials: HashMap<Symbol, (BinaryOp, Symbol, i64)> = HashMap::new();
+    let mut changed = false;
+
+    for inst in program.instructions.iter_mut() {
+        match inst {
+            Instruction::Constant { result, value, .. } => {
+                known_constants.insert(*result, *value);
+            }
+            Instruction::Binary {
+                result,
+                op,
+                left,
+                right,
+                ..
+            } => {
+                if !matches!(op, BinaryOp::Add | BinaryOp::Multiply) {
+                    continue;
+                }
+                let constant = |operand: Symbol| {
+                    known_constants
+                        .get(&operand)
+                        .copied()
+                        .or_else(|| literal_value(operand, &program.symbols))
+                };
+                let (operand, value) = match (constant(*left), constant(*right)) {
+                    (None, Some(value)) => (*left, value),
+                    (Some(value), None) => (*right, value),
+                    _ => continue,
+                };
+
+                let inner = partials
+                    .get(&operand)
+                    .filter(|(inner_op, _, _)| inner_op == op);
+                // Constants of the same sign move the value the same way, so
+                // the combined op overflows exactly when the two did. `+ 5`
+                // then `+ -3` can't become `+ 2`, which hides the first
+                // overflowing; nor can a multiplication by zero hide one.
+                let same_sign = |inner_value: i64| match op {
+                    BinaryOp::Add => inner_value.signum() * value.signum() >= 0,
+                    _ => inner_value.signum() * value.signum() > 0,
+                };
+                if let Some((_, inner_operand, inner_value)) = inner {
+                    let combined = eval_binary(op, *inner_value, value)
+                        .ok()
+                        .filter(|_| same_sign(*inner_value));
+                    if let Some(combined) = combined {
+                        let inner_operand = *inner_operand;
+                        *left = inner_operand;
+                        *right = program.symbols.intern(&combined.to_string());
+                        partials.insert(*result, (op.clone(), inner_operand, combined));
+                        changed = true;
+                        continue;
+                    }
+                }
+                partials.insert(*result, (op.clone(), operand, value));
+            }
+            Instruction::Copy { .. }
+            | Instruction::Call { .. }
+            | Instruction::Select { .. }
+            | Instruction::Phi { .. }
+            | Instruction::Label(_)
+            | Instruction::Jump(_)
+            | Instruction::BranchIfZero { .. }
+            | Instruction::Exit(_) => {}
+        }
+    }
+    changed
+}
+
+// Rewrites Binary instructions with an identity operand: `x + 0`, `x - 0`
+// and `x * 1` become a copy of `x`, and `x * 0` becomes the constant 0
+fn simplify_identities(program: &mut Program) -> bool {
+    let mut known_constants: HashMap<Symbol, i64> = HashMap::new();
+    let mut changed = false;
+
+    for inst in program.instructions.iter_mut() {
+        let replacement = match inst {
+            Instruction::Constant { result, value, .. } => {
+                known_constants.insert(*result, *value);
+                continue;
+            }
+            Instruction::Copy { .. }
+            | Instruction::Call { .. }
+            | Instruction::Select { .. }
+            | Instruction::Phi { .. }
+            | Instruction::Label(_)
+            | Instruction::Jump(_)
+            | Instruction::BranchIfZero { .. }
+            | Instruction::Exit(_) => continue,
+            Instruction::Binary {
+                result,
+                op,
+                left,
+                right,
+                span,
+            } => {
+                let constant = |operand: Symbol| {
+                    known_constants
+                        .get(&operand)
+                        .copied()
+                        .or_else(|| literal_value(operand, &program.symbols))
+                };
+                let copy = |source: &Symbol| Instruction::Copy {
+                    result: *result,
+                    source: *source,
+                    span: *span,
+                };
+                match (op, constant(*left), constant(*right)) {
+                    (BinaryOp::Multiply, Some(0), _) | (BinaryOp::Multiply, _, Some(0)) => {
+                        Instruction::Constant {
+                            result: *result,
+                            value: 0,
+                            span: *span,
+                        }
+                    }
+                    (BinaryOp::Add | BinaryOp::Subtract, _, Some(0))
+                    | (BinaryOp::Multiply, _, Some(1)) => copy(left),
+                    (BinaryOp::Add, Some(0), _) | (BinaryOp::Multiply, Some(1), _) => copy(right),
+                    _ => continue,
+                }
+            }
+        };
+        if let Instruction::Constant { result, value, .. } = &replacement {
+            known_constants.insert(*result, *value);
+        }
+        *inst = replacement;
+        changed = true;
+    }
+    changed
+}
+
+// A local rewrite: `rewrite` sees `window` consecutive instructions and
+// returns their replacement when the rule applies
+struct PeepholeRule {
+    window: usize,
+    rewrite: fn(&[Instruction], &Interner) -> Option<Vec<Instruction>>,
+}
+
+const PEEPHOLE_RULES: &[PeepholeRule] = &[
+    PeepholeRule {
+        window: 1,
+        rewrite: subtract_self,
+    },
+    PeepholeRule {
+        window: 1,
+        rewrite: fold_literal_operands,
+    },
+];
+
+// `x - x` is 0, whatever `x` is
+fn subtract_self(window: &[Instruction], _: &Interner) -> Option<Vec<Instruction>> {
+    match window {
+        [Instruction::Binary {
+            result,
+            op: BinaryOp::Subtract,
+            left,
+            right,
+            span,
+        }] if left == right => Some(vec![Instruction::Constant {
+            result: *result,
+            value: 0,
+            span: *span,
+        }]),
+        _ => None,
+    }
+}
+
+// A Binary of two literals, left alone when it would overflow or divide by
+// zero so that folding reports it
+fn fold_literal_operands(window: &[Instruction], symbols: &Interner) -> Option<Vec<Instruction>> {
+    match window {
+        [Instruction::Binary {
+            result,
+            op,
+            left,
+            right,
+            span,
+        }] => {
+            let (left, right) = (
+                literal_value(*left, symbols)?,
+                literal_value(*right, symbols)?,
+            );
+            let value = eval_binary(op, left, right).ok()?;
+            Some(vec![Instruction::Constant {
+                result: *result,
+                value,
+                span: *span,
+            }])
+        }
+        _ => None,
+    }
+}
+
+fn peephole(program: &mut Program) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < program.instructions.len() {
+        for rule in PEEPHOLE_RULES {
+            let Some(window) = program.instructions.get(i..i + rule.window) else {
+                continue;
+            };
+            if let Some(replacement) = (rule.rewrite)(window, &program.symbols) {
+                program.instructions.splice(i..i + rule.window, replacement);
+                changed = true;
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+// Replaces a Binary that repeats an earlier computation with a copy of the
+// earlier result. Versioned names are only ever assigned once, so matching
+// operand names always mean matching values. An earlier computation on
+// another branch may not have run, though, so nothing is reused past a label.
+fn common_subexpression_elimination(program: &mut Program) -> bool {
+    let mut computed: HashMap<(BinaryOp, Symbol, Symbol), Symbol> = HashMap::new();
+    let mut changed = false;
+
+    for inst in program.instructions.iter_mut() {
+        if let Instruction::Label(_) = inst {
+            computed.clear();
+        }
+        if let Instruction::Binary {
+            result,
+            op,
+            left,
+            right,
+            span,
+        } = inst
+        {
+            let key = (op.clone(), *left, *right);
+            match computed.get(&key) {
+                Some(first) => {
+                    *inst = Instruction::Copy {
+                        result: *result,
+                        source: *first,
+                        span: *span,
+                    };
+                    changed = true;
+                }
+                None => {
+                    computed.insert(key, *result);
+                }
+            }
+        }
+    }
+    changed
+}
+
+// Rewrites uses of a copied name to the value it was copied from. The copies
+// themselves stay in place and are left for DCE to remove once unused.
+fn copy_propagation(program: &mut Program) -> bool {
+    // copy result -> original name, already resolved through chains of copies
+    let mut aliases: HashMap<Symbol, Symbol> = HashMap::new();
+    let mut changed = false;
+
+    for inst in program.instructions.iter_mut() {
+        inst.for_each_operand_mut(|operand| {
+            if let Some(original) = aliases.get(operand) {
+                *operand = *original;
+                changed = true;
+            }
+        });
+        if let Instruction::Copy { result, source, .. } = inst {
+            aliases.insert(*result, *source);
+        }
+    }
+    changed
+}
+
+fn dead_code_elimination(program: &mut Program) -> bool {
+    let mut uses: HashMap<Symbol, usize> = HashMap::new();
+
+    for inst in &program.instructions {
+        for operand in inst.operands() {
+            *uses.entry(operand).or_default() += 1;
+        }
+    }
+
+    let before = program.instructions.len();
+    // jumps and branches define nothing, and always stay. So do labels, even
+    // unused ones: dropping one would merge its block into the one before,
+    // which no longer says where control can't reach.
+    let symbols = &program.symbols;
+    program.instructions.retain(|inst| match inst.result() {
+        Some(_) if matches!(inst, Instruction::Label(_)) => true,
+        Some(_) if may_divide_by_zero(inst, symbols) => true,
+        Some(result) => uses.get(&result).copied().unwrap_or(0) > 0,
+        None => true,
+    });
+    program.instructions.len() != before
+}
+
+// Collapses an if/else whose arms only copy values or set constants, so
+// `if (c) { x = a; } else { x = b; }` becomes `x = select c, a, b` and
+// nothing branches. Neither arm can fail, so both run unconditionally.
+fn select_diamonds(program: &mut Program) -> bool {
+    let mut changed = false;
+    while let Some((branch, jump, join, phis_end)) = find_diamond(&program.instructions) {
+        let instructions = &program.instructions;
+        let (Instruction::BranchIfZero { cond, .. }, Instruction::Label(then_label)) =
+            (&instructions[branch], &instructions[branch + 1])
+        else {
+            unreachable!("find_diamond starts at a branch into a label");
+        };
+        let (cond, then_label) = (*cond, *then_label);
+        let Instruction::Label(else_label) = instructions[jump + 1] else {
+            unreachable!("find_diamond checks the else arm's label");
+        };
+        let Instruction::Label(join_label) = instructions[join] else {
+            unreachable!("find_diamond checks the join's label");
+        };
+        let block = instructions[..branch]
+            .iter()
+            .rev()
+            .find_map(|inst| match inst {
+                Instruction::Label(label) => Some(*label),
+                _ => None,
+            });
+
+        let mut replacement: Vec<Instruction> = instructions[branch + 2..jump].to_vec();
+        replacement.extend_from_slice(&instructions[jump + 2..join]);
+        for phi in &instructions[join + 1..phis_end] {
+            let Instruction::Phi { result, sources } = phi else {
+                unreachable!("find_diamond checks the join's phis");
+            };
+            let from = |arm: Symbol| sources.iter().find(|(_, from)| *from == arm).unwrap().0;
+            replacement.push(Instruction::Select {
+                result: *result,
+                cond,
+                then_val: from(then_label),
+                else_val: from(else_label),
+                span: None,
+            });
+        }
+        program.instructions.splice(branch..phis_end, replacement);
+        let block = block.unwrap_or_else(|| program.intern(ENTRY_BLOCK));
+        // what followed the join now belongs to the branch's block
+        for inst in program.instructions.iter_mut() {
+            if let Instruction::Phi { sources, .. } = inst {
+                for (_, from) in sources.iter_mut().filter(|(_, from)| *from == join_label) {
+                    *from = block;
+                }
+            }
+        }
+        changed = true;
+    }
+    changed
+}
+
+// The positions of the branch, the jump out of the then arm, the join's
+// label and the end of its phis, for the first diamond nothing else jumps
+// into
+fn find_diamond(instructions: &[Instruction]) -> Option<(usize, usize, usize, usize)> {
+    // where the copies and constants starting at `start` end
+    let arm_end = |start: usize| {
+        let pure = |inst: &&Instruction| {
+            matches!(
+                inst,
+                Instruction::Constant { .. } | Instruction::Copy { .. }
+            )
+        };
+        start + instructions[start..].iter().take_while(pure).count()
+    };
+    for (branch, inst) in instructions.iter().enumerate() {
+        let Instruction::BranchIfZero {
+            target: else_label, ..
+        } = inst
+        else {
+            continue;
+        };
+        let Some(Instruction::Label(then_label)) = instructions.get(branch + 1) else {
+            continue;
+        };
+        let jump = arm_end(branch + 2);
+        let Some(Instruction::Jump(join_label)) = instructions.get(jump) else {
+            continue;
+        };
+        if instructions.get(jump + 1) != Some(&Instruction::Label(*else_label)) {
+            continue;
+        }
+        let join = arm_end(jump + 2);
+        if instructions.get(join) != Some(&Instruction::Label(*join_label)) {
+            continue;
+        }
+        let phis_end = join
+            + 1
+            + instructions[join + 1..]
+                .iter()
+                .take_while(|inst| matches!(inst, Instruction::Phi { .. }))
+                .count();
+
+        let arms = [*then_label, *else_label];
+        let phis_merge_arms = instructions[join + 1..phis_end].iter().all(|inst| {
+            matches!(inst, Instruction::Phi { sources, .. }
+                if sources.len() == 2
+                    && arms.iter().all(|arm| sources.iter().any(|(_, from)| from == arm)))
+        });
+        let entered_elsewhere = instructions
+            .iter()
+            .enumerate()
+            .any(|(index, inst)| match inst {
+                Instruction::Jump(target) | Instruction::BranchIfZero { target, .. } => {
+                    index != branch
+                        && index != jump
+                        && (arms.contains(target) || target == join_label)
+                }
+                Instruction::Phi { sources, .. } if !(join + 1..phis_end).contains(&index) => {
+                    sources.iter().any(|(_, from)| arms.contains(from))
+                }
+                _ => false,
+            });
+        if phis_merge_arms && !entered_elsewhere {
+            return Some((branch, jump, join, phis_end));
+        }
+    }
+    None
+}
+
+// A division whose divisor isn't a nonzero literal can fail, so removing it
+// just because its result is unused would change whether the program
+// reports an error. Folding runs after DCE and rejects the ones it can prove
+// divide by zero.
+fn may_divide_by_zero(inst: &Instruction, symbols: &Interner) -> bool {
+    match inst {
+        Instruction::Binary {
+            op: BinaryOp::Divide | BinaryOp::Modulo,
+            right,
+            ..
+        } => !matches!(literal_value(*right, symbols), Some(divisor) if divisor != 0),
+        _ => false,
+    }
+}
+
+// Lines prefixed with `-` were removed and `+` added. Instructions are matched
+// up by result name, which is unique within a program, so an instruction that
+// was rewritten in place shows as a removal followed by its replacement.
+// Jumps and branches have no result and only match an identical instruction.
+// The two programs may have separate symbol tables, so instructions are
+// compared by their text.
+pub fn diff_ir(before: &Program, after: &Program) -> String {
+    let text = |program: &Program, inst: &Instruction| inst.display(&program.symbols).to_string();
+    let find = |program: &Program, inst: &Instruction, from: &Program| {
+        let result = inst.result().map(|result| from.name(result));
+        let inst = text(from, inst);
+        program.instructions.iter().position(|other| match result {
+            Some(result) => other.result().map(|other| program.name(other)) == Some(result),
+            None => text(program, other) == inst,
+        })
+    };
+    let mut out = String::new();
+
+    for inst in &before.instructions {
+        let removed = text(before, inst);
+        match find(after, inst, before) {
+            None => out.push_str(&format!("- {}\n", removed)),
+            Some(index) => {
+                let added = text(after, &after.instructions[index]);
+                if added != removed {
+                    out.push_str(&format!("- {}\n", removed));
+                    out.push_str(&format!("+ {}\n", added));
+                }
+            }
+        }
+    }
+    for inst in &after.instructions {
+        if find(before, inst, after).is_none() {
+            out.push_str(&format!("+ {}\n", text(after, inst)));
+        }
+    }
+    out
+}
+
+// Runs a single optimization pass by name, returning whether it changed the program.
+pub fn run_pass(program: &mut Program, name: &str) -> Result<bool, PassError> {
+    match name {
+        "copyprop" => Ok(copy_propagation(program)),
+        "cse" => Ok(common_subexpression_elimination(program)),
+        "dce" => Ok(dead_code_elimination(program)),
+        "fold" => Ok(constant_folding(program, OverflowPolicy::default())?),
+        "peephole" => Ok(peephole(program)),
+        "reassoc" => Ok(reassociation(program)),
+        "select" => Ok(select_diamonds(program)),
+        "simplify" => Ok(simplify_identities(program)),
+        _ => Err(PassError::UnknownPass(UnknownPass {
+            name: name.to_string(),
+        })),
+    }
+}
+
+// Runs every pass once, in order. With `record` set, a copy of the program
+// is taken after each pass, so a front end can step through the changes.
+pub fn optimize(
+    program: &mut Program,
+    policy: OverflowPolicy,
+    record: bool,
+) -> Result<Vec<Program>, FoldError> {
+    let mut snapshots = Vec::new();
+    let mut snapshot = |program: &Program| {
+        if record {
+            snapshots.push(program.clone());
+        }
+    };
+    reassociation(program);
+    snapshot(program);
+    simplify_identities(program);
+    snapshot(program);
+    peephole(program);
+    snapshot(program);
+    common_subexpression_elimination(program);
+    snapshot(program);
+    copy_propagation(program);
+    snapshot(program);
+    dead_code_elimination(program);
+    snapshot(program);
+    select_diamonds(program);
+    snapshot(program);
+    constant_folding(program, policy)?;
+    snapshot(program);
+    Ok(snapshots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    fn lower_source(input: &str) -> Program {
+        let tokens = lexer::lex(input).unwrap();
+        lower(parser::parse(tokens).unwrap()).unwrap()
+    }
+
+    // The instruction at `index`, as text
+    fn line(program: &Program, index: usize) -> String {
+        program.instructions[index]
+            .display(&program.symbols)
+            .to_string()
+    }
+
+    fn has_line(program: &Program, line: &str) -> bool {
+        program.to_string().lines().any(|other| other == line)
+    }
+
+    #[test]
+    fn test_run_single_pass() {
+        let mut program = lower_source("let x: int = 3; let unused: int = 0; let y: int = x + 1;");
+        assert!(run_pass(&mut program, "fold").unwrap());
+
+        // y.1 was folded, but the unused constant survives since DCE never ran
+        assert!(has_line(&program, "y.1 = 4"));
+        assert!(has_line(&program, "unused.1 = 0"));
+        assert_eq!(program.instructions.len(), 3);
+    }
+
+    #[test]
+    fn test_fold_const() {
+        let mut program = lower_source("const PI: int = 3; let r: int = PI * 2;");
+        run_pass(&mut program, "fold").unwrap();
+        assert!(has_line(&program, "r.1 = 6"));
+    }
+
+    #[test]
+    fn test_lower_assignment_to_undeclared_variable() {
+        let tokens = lexer::lex("y = 5;").unwrap();
+        let err = lower(parser::parse(tokens).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("undeclared variable `y`"));
+    }
+
+    #[test]
+    fn test_reassociate_constants() {
+        // a + 2 + 3 where `a` is unknown
+        let mut program = Program::new();
+        let mut name = |text: &str| program.intern(text);
+        let instructions = vec![
+            Instruction::Binary {
+                result: name("bin.1"),
+                op: BinaryOp::Add,
+                left: name("a.1"),
+                right: name("2"),
+                span: None,
+            },
+            Instruction::Binary {
+                result: name("x.1"),
+                op: BinaryOp::Add,
+                left: name("bin.1"),
+                right: name("3"),
+                span: None,
+            },
+        ];
+        program.instructions = instructions;
+        assert!(run_pass(&mut program, "reassoc").unwrap());
+        assert_eq!(line(&program, 1), "x.1 = a.1 + 5");
+    }
+
+    #[test]
+    fn test_reassociate_skips_overflow() {
+        let mut program = Program::new();
+        let mut name = |text: &str| program.intern(text);
+        let instructions = vec![
+            Instruction::Binary {
+                result: name("bin.1"),
+                op: BinaryOp::Multiply,
+                left: name("a.1"),
+                right: name(&i64::MAX.to_string()),
+                span: None,
+            },
+            Instruction::Binary {
+                result: name("x.1"),
+                op: BinaryOp::Multiply,
+                left: name("2"),
+                right: name("bin.1"),
+                span: None,
+            },
+        ];
+        program.instructions = instructions;
+        assert!(!run_pass(&mut program, "reassoc").unwrap());
+    }
+
+    #[test]
+    fn test_reassociate_keeps_overflow() {
+        // `a + 5` can overflow even though `a + 2` wouldn't, and `a * 2` even
+        // though `a * 0` can't
+        for (op, first, second) in [
+            (BinaryOp::Add, "5", "-3"),
+            (BinaryOp::Add, "-5", "3"),
+            (BinaryOp::Multiply, "2", "0"),
+            (BinaryOp::Multiply, "2", "-3"),
+        ] {
+            let mut program = Program::new();
+            let mut name = |text: &str| program.intern(text);
+            let instructions = vec![
+                Instruction::Binary {
+                    result: name("bin.1"),
+                    op: op.clone(),
+                    left: name("a.1"),
+                    right: name(first),
+                    span: None,
+                },
+                Instruction::Binary {
+                    result: name("x.1"),
+                    op: op.clone(),
+                    left: name("bin.1"),
+                    right: name(second),
+                    span: None,
+                },
+            ];
+            program.instructions = instructions;
+            assert!(
+                !run_pass(&mut program, "reassoc").unwrap(),
+                "{} {}",
+                first,
+                second
+            );
+        }
+    }
+
+    fn overflowing_add() -> Program {
+        let mut program = Program::new();
+        let mut name = |text: &str| program.intern(text);
+        let instructions = vec![Instruction::Binary {
+            result: name("x.1"),
+            op: BinaryOp::Add,
+            left: name(&i64::MAX.to_string()),
+            right: name("1"),
+            span: None,
+        }];
+        program.instructions = instructions;
+        program
+    }
+
+    #[test]
+    fn test_fold_overflow_policies() {
+        let mut program = overflowing_add();
+        constant_folding(&mut program, OverflowPolicy::Checked).unwrap();
+        assert!(matches!(
+            program.instructions[0],
+            Instruction::Binary { .. }
+        ));
+        assert_eq!(program.warnings.len(), 1);
+
+        let mut program = overflowing_add();
+        constant_folding(&mut program, OverflowPolicy::Wrapping).unwrap();
+        assert!(matches!(
+            program.instructions[0],
+            Instruction::Constant {
+                value: i64::MIN,
+                ..
+            }
+        ));
+
+        let mut program = overflowing_add();
+        constant_folding(&mut program, OverflowPolicy::Saturating).unwrap();
+        assert!(matches!(
+            program.instructions[0],
+            Instruction::Constant {
+                value: i64::MAX,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_variable_listing_is_deterministic() {
+        let source = "let c: int = 1; let a: int = 2; let b: int = a + c; a = 3;";
+        let first = format!("{:?}", lower_source(source).variables);
+        let second = format!("{:?}", lower_source(source).variables);
+        assert_eq!(first, second);
+        assert_eq!(first, r#"{"a": 2, "b": 1, "c": 1}"#);
+    }
+
+    #[test]
+    fn test_lower_negation() {
+        let mut program = lower_source("let a: int = 3; let x: int = -a;");
+        assert_eq!(line(&program, 1), "x.1 = 0 - a.1");
+        run_pass(&mut program, "fold").unwrap();
+        assert!(matches!(
+            &program.instructions[1],
+            Instruction::Constant { value: -3, .. }
+        ));
+    }
+
+    #[test]
+    fn test_lower_not() {
+        let mut program = lower_source("let a: int = 0; let x: int = !a;");
+        assert_eq!(line(&program, 1), "x.1 = a.1 == 0");
+        run_pass(&mut program, "fold").unwrap();
+        assert!(matches!(
+            &program.instructions[1],
+            Instruction::Constant { value: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_lower_short_circuit() {
+        let mut program = lower_source("let x: bool = true && false;");
+        assert_eq!(
+            program.to_string(),
+            "branch_if_zero 1, end.1\nrhs.1:\nend.1:\nx.1 = phi [0, entry], [0, rhs.1]\n"
+        );
+        run_pass(&mut program, "fold").unwrap();
+        assert!(has_line(&program, "x.1 = 0"));
+
+        let program = lower_source("let a: bool = false; let x: bool = a || true;");
+        assert_eq!(
+            program.to_string(),
+            "a.1 = 0
+branch_if_zero a.1, rhs.1
+jump end.1
+rhs.1:
+end.1:
+x.1 = phi [1, entry], [1, rhs.1]
+"
+        );
+    }
+
+    #[test]
+    fn test_short_circuit_skips_right_side() {
+        // the same program the interpreter runs without dividing
+        for (source, value) in [
+            ("let x: int = 0; let ok: bool = x != 0 && 1 / x > 0;", 0),
+            ("let x: int = 0; let ok: bool = x == 0 || 1 / x > 0;", 1),
+        ] {
+            let mut program = lower_source(source);
+            run_pass(&mut program, "fold").unwrap();
+            assert!(
+                has_line(&program, &format!("ok.1 = {}", value)),
+                "{}",
+                source
+            );
+            optimize(&mut program, OverflowPolicy::default(), false).unwrap();
+        }
+
+        // once the left side doesn't decide it, the division is reported
+        let mut program = lower_source("let x: int = 0; let ok: bool = x == 0 && 1 / x > 0;");
+        let err = optimize(&mut program, OverflowPolicy::default(), false).unwrap_err();
+        assert!(err.to_string().contains("Division by zero"));
+    }
+
+    #[test]
+    fn test_lower_undeclared_variable() {
+        let tokens = lexer::lex("let y: int = undefined + 1;").unwrap();
+        let err = lower(parser::parse(tokens).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("`undefined`"));
+    }
+
+    #[test]
+    fn test_fold_skips_overflowing_multiply() {
+        let mut program = lower_source("let x: int = 9223372036854775807 * 2; let y: int = 2 * 3;");
+        assert!(run_pass(&mut program, "fold").unwrap());
+        assert_eq!(line(&program, 0), "x.1 = 9223372036854775807 * 2");
+        assert!(matches!(
+            &program.instructions[1],
+            Instruction::Constant { value: 6, .. }
+        ));
+        assert!(program.warnings[0].contains("overflow computing x.1"));
+    }
+
+    #[test]
+    fn test_fold_modulo() {
+        let mut program = lower_source("let x: int = 7 % 3;");
+        run_pass(&mut program, "fold").unwrap();
+        assert!(matches!(
+            &program.instructions[0],
+            Instruction::Constant { value: 1, .. }
+        ));
+
+        let mut program = lower_source("let x: int = 7 % 0;");
+        let err = run_pass(&mut program, "fold").unwrap_err();
+        assert!(err.to_string().contains("Division by zero"));
+    }
+
+    fn folded_value(input: &str) -> Option<i64> {
+        let mut program = lower_source(input);
+        run_pass(&mut program, "fold").unwrap();
+        program.instructions.iter().find_map(|inst| match inst {
+            Instruction::Constant { result, value, .. } if program.name(*result) == "x.1" => {
+                Some(*value)
+            }
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_fold_propagates_through_variables() {
+        let mut program = lower_source("let x: int = 2; let y: int = x + 3; let z: int = y * 2;");
+        run_pass(&mut program, "fold").unwrap();
+        assert!(has_line(&program, "z.1 = 10"));
+        assert!(program
+            .instructions
+            .iter()
+            .all(|inst| inst.operands().is_empty()));
+
+        // an instruction that can't fold still has its known operands replaced
+        let mut program = lower_source("let x: int = 9223372036854775807; let y: int = x + 1;");
+        run_pass(&mut program, "fold").unwrap();
+        assert_eq!(line(&program, 1), "y.1 = 9223372036854775807 + 1");
+    }
+
+    #[test]
+    fn test_fold_leaves_unfoldable_instructions() {
+        // `a` overflows, so which branch runs isn't known until runtime
+        let mut program = lower_source(
+            "let a: int = 9223372036854775807 + 1; let x: int = 0;
+             if (a > 0) { x = pow(2, 3); } else { x = abs(-4); }
+             let z: int = x + 1;",
+        );
+        run_pass(&mut program, "fold").unwrap();
+        let listing = program.to_string();
+        assert!(listing.contains("x.2 = 8"));
+        assert!(listing.contains("x.3 = 4"));
+        // the phi's sources are constants, but different ones
+        assert!(listing.contains("x.4 = phi [8, then.1], [4, else.1]"));
+        assert!(listing.contains("z.1 = x.4 + 1"));
+    }
+
+    #[test]
+    fn test_fold_bool_equality() {
+        // booleans are 0 and 1 in the IR, so integer equality covers them
+        assert_eq!(folded_value("let x: bool = true == false;"), Some(0));
+        assert_eq!(
+            folded_value("let a: bool = true; let x: bool = a != false;"),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_fold_explicit_overflow_operators() {
+        // both fold under the default Checked policy, where `+` would not
+        assert_eq!(
+            folded_value("let x: int = 9223372036854775807 +% 1;"),
+            Some(i64::MIN)
+        );
+        assert_eq!(
+            folded_value("let x: int = 9223372036854775807 +| 1;"),
+            Some(i64::MAX)
+        );
+        assert_eq!(
+            folded_value("let x: int = (-9223372036854775807 - 1) +| -1;"),
+            Some(i64::MIN)
+        );
+        assert_eq!(folded_value("let x: int = 9223372036854775807 + 1;"), None);
+    }
+
+    #[test]
+    fn test_fold_division_by_zero() {
+        let mut program = lower_source("let x: int = 1 / 0;");
+        for policy in [
+            OverflowPolicy::Checked,
+            OverflowPolicy::Wrapping,
+            OverflowPolicy::Saturating,
+        ] {
+            let err = constant_folding(&mut program, policy).unwrap_err();
+            assert!(err.to_string().contains("Division by zero"));
+        }
+    }
+
+    #[test]
+    fn test_copy_propagation() {
+        let mut program =
+            lower_source("let x: int = 1; let y: int = x; let z: int = y; let w: int = z + 2;");
+        assert_eq!(line(&program, 2), "z.1 = y.1");
+        let before = program.instructions.len();
+
+        assert!(run_pass(&mut program, "copyprop").unwrap());
+        assert_eq!(line(&program, 3), "w.1 = x.1 + 2");
+
+        run_pass(&mut program, "dce").unwrap();
+        assert!(!program
+            .instructions
+            .iter()
+            .any(|inst| matches!(inst, Instruction::Copy { .. })));
+        assert!(program.instructions.len() < before);
+    }
+
+    #[test]
+    fn test_common_subexpression_elimination() {
+        let mut program = lower_source(
+            "let a: int = 1; let b: int = 2; let x: int = a + b; let y: int = a + b; let z: int = x * y;",
+        );
+        assert!(run_pass(&mut program, "cse").unwrap());
+        assert_eq!(line(&program, 3), "y.1 = x.1");
+
+        run_pass(&mut program, "copyprop").unwrap();
+        run_pass(&mut program, "dce").unwrap();
+        let additions = program
+            .instructions
+            .iter()
+            .filter(|inst| {
+                matches!(
+                    inst,
+                    Instruction::Binary {
+                        op: BinaryOp::Add,
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(additions, 1);
+    }
+
+    fn simplified(source: &str) -> Program {
+        let mut program = lower_source(source);
+        assert!(run_pass(&mut program, "simplify").unwrap());
+        program
+    }
+
+    #[test]
+    fn test_simplify_identities() {
+        for source in [
+            "let a: int = 5; let x: int = a + 0;",
+            "let a: int = 5; let x: int = 0 + a;",
+            "let a: int = 5; let x: int = a - 0;",
+            "let a: int = 5; let x: int = a * 1;",
+            "let a: int = 5; let x: int = 1 * a;",
+        ] {
+            let program = simplified(source);
+            assert_eq!(line(&program, 1), "x.1 = a.1", "{}", source);
+        }
+
+        for source in [
+            "let a: int = 5; let x: int = a * 0;",
+            "let a: int = 5; let x: int = 0 * a;",
+        ] {
+            let program = simplified(source);
+            assert_eq!(line(&program, 1), "x.1 = 0", "{}", source);
+        }
+
+        // `0 - x` is a negation, not an identity
+        let mut program = lower_source("let a: int = 5; let x: int = 0 - a;");
+        assert!(!run_pass(&mut program, "simplify").unwrap());
+    }
+
+    #[test]
+    fn test_peephole() {
+        let mut program = lower_source("let a: int = 5; let x: int = a - a; let y: int = 6 / 3;");
+        assert!(run_pass(&mut program, "peephole").unwrap());
+        assert_eq!(program.to_string(), "a.1 = 5\nx.1 = 0\ny.1 = 2\n");
+
+        // `a - b` may not be 0, and `1 / 0` is left for folding to report
+        let mut program =
+            lower_source("let a: int = 5; let b: int = 5; let x: int = a - b; let y: int = 1 / 0;");
+        assert!(!run_pass(&mut program, "peephole").unwrap());
+    }
+
+    #[test]
+    fn test_simplify_then_dce() {
+        let mut program = simplified("let a: int = 5; let x: int = a * 0; let y: int = x + 1;");
+        run_pass(&mut program, "dce").unwrap();
+        // a.1 was only used by the multiply, which no longer reads it
+        assert!(!has_line(&program, "a.1 = 5"));
+    }
+
+    #[test]
+    fn test_optimize_snapshots() {
+        let source = include_str!("../samples/basic.crucible");
+        let mut program = lower_source(source);
+        let snapshots = optimize(&mut program, OverflowPolicy::default(), true).unwrap();
+        // reassoc, simplify, peephole, cse, copyprop, dce, select and fold
+        assert_eq!(snapshots.len(), 8);
+        assert_eq!(snapshots.last().unwrap().to_string(), program.to_string());
+
+        let mut program = lower_source(source);
+        assert!(optimize(&mut program, OverflowPolicy::default(), false)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_optimize_reports_dead_division_by_zero() {
+        for source in [
+            "let x: int = 1 / 0;",
+            "let d: int = 0; let x: int = 1 % d;",
+            "let x: int = 1 / 0; let y: int = x;",
+        ] {
+            let mut program = lower_source(source);
+            let err = optimize(&mut program, OverflowPolicy::default(), false).unwrap_err();
+            assert!(err.to_string().contains("Division by zero"), "{}", source);
+        }
+
+        // a dead division by a nonzero literal can't fail, so it goes
+        let mut program = lower_source("let a: int = 7; let x: int = a / 2;");
+        optimize(&mut program, OverflowPolicy::default(), false).unwrap();
+        assert!(!program.to_string().contains("x.1"));
+    }
+
+    #[test]
+    fn test_diff_ir() {
+        let source = include_str!("../samples/basic.crucible");
+        let before = lower_source(source);
+        let mut after = lower_source(source);
+        optimize(&mut after, OverflowPolicy::default(), false).unwrap();
+
+        let diff = diff_ir(&before, &after);
+        assert!(diff.contains("- unused.1 = 0\n"));
+        assert!(diff.contains("- y.1 = x.1 + 1\n+ y.1 = 4\n"));
+        assert!(diff_ir(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn test_textual_dump() {
+        let program = lower_source("let a: int = 2; let b: int = a; let c: int = -b * 3;");
+        assert_eq!(
+            program.to_string(),
+            "a.1 = 2\nb.1 = a.1\nbin.1 = 0 - b.1\nc.1 = bin.1 * 3\n"
+        );
+    }
+
+    #[test]
+    fn test_fold_builtins() {
+        let mut program = lower_source("let a: int = abs(-5); let p: int = pow(2, 10);");
+        assert_eq!(line(&program, 1), "a.1 = abs(bin.1)");
+        run_pass(&mut program, "fold").unwrap();
+        assert!(matches!(
+            &program.instructions[1],
+            Instruction::Constant { value: 5, .. }
+        ));
+        assert!(matches!(
+            &program.instructions[2],
+            Instruction::Constant { value: 1024, .. }
+        ));
+
+        let mut program = lower_source("let p: int = pow(10, 19);");
+        run_pass(&mut program, "fold").unwrap();
+        assert!(matches!(&program.instructions[0], Instruction::Call { .. }));
+        assert!(program.warnings[0].contains("overflow computing p.1"));
+    }
+
+    #[test]
+    fn test_builtin_with_unknown_argument() {
+        let mut program = Program::new();
+        let instructions = vec![Instruction::Call {
+            result: program.intern("y.1"),
+            builtin: Builtin::Abs,
+            args: vec![program.intern("x.1")],
+            span: None,
+        }];
+        program.instructions = instructions;
+        assert!(!run_pass(&mut program, "fold").unwrap());
+        assert_eq!(program.to_string(), "y.1 = abs(x.1)\n");
+    }
+
+    #[test]
+    fn test_fold_min_max() {
+        let mut program = lower_source("let m: int = max(3, 7);");
+        assert_eq!(
+            program.to_string(),
+            "bin.1 = 3 > 7\nm.1 = select bin.1, 3, 7\n"
+        );
+        run_pass(&mut program, "fold").unwrap();
+        assert_eq!(line(&program, 1), "m.1 = 7");
+
+        // `x` comes out of the loop as a phi, which folding leaves alone
+        let mut program =
+            lower_source("let x: int = 0; while (x < 3) { x = x + 1; } let m: int = min(x, 2);");
+        run_pass(&mut program, "fold").unwrap();
+        assert!(has_line(&program, "bin.2 = x.2 < 2"));
+        assert!(has_line(&program, "m.1 = select bin.2, x.2, 2"));
+    }
+
+    #[test]
+    fn test_select_diamond() {
+        let mut program = lower_source(
+            "let i: int = 0; while (i < 3) { i = i + 1; }
+             let x: int = 0; if (i > 1) { x = i; } else { x = 5; }
+             if (x > 4) { x = 4; }",
+        );
+        assert!(run_pass(&mut program, "select").unwrap());
+        let listing = program.to_string();
+        let collapsed = listing.split_once("endwhile.1:\n").unwrap().1;
+        // both arms' copies run, and the join's block was merged into the
+        // loop exit, which the `if` without an else now comes from
+        assert_eq!(
+            collapsed,
+            "x.1 = 0\n\
+             bin.2 = i.2 > 1\n\
+             x.2 = i.2\n\
+             x.3 = 5\n\
+             x.4 = select bin.2, x.2, x.3\n\
+             bin.3 = x.4 > 4\n\
+             branch_if_zero bin.3, endif.2\n\
+             then.2:\n\
+             x.5 = 4\n\
+             endif.2:\n\
+             x.6 = phi [x.5, then.2], [x.4, endwhile.1]\n"
+        );
+
+        // an arm that computes something keeps its branch
+        let mut program =
+            lower_source("let i: int = 0; while (i < 3) { i = i + 1; } let x: int = 0; if (i > 1) { x = i * 2; } else { x = 5; }");
+        assert!(!run_pass(&mut program, "select").unwrap());
+    }
+
+    #[test]
+    fn test_merge_programs() {
+        let mut program = lower_source("let x: int = 1; x = x + 1; let y: int = x;");
+        program.merge(lower_source("let x: int = 5; let z: int = x * 2; x = z;"));
+
+        let mut results: Vec<&str> = program
+            .instructions
+            .iter()
+            .filter_map(|i| i.result())
+            .map(|result| program.name(result))
+            .collect();
+        let count = results.len();
+        results.sort();
+        results.dedup();
+        assert_eq!(results.len(), count);
+
+        // the merged snippet still reads its own x, under its new symbol
+        assert!(has_line(&program, "z.1 = x.3 * 2"));
+        assert_eq!(program.variables["x"], 4);
+    }
+
+    #[test]
+    fn test_lower_if_else() {
+        let program =
+            lower_source("let a: int = 1; let x: int = 0; if (a > 0) { x = 1; } else { x = 2; }");
+        assert_eq!(
+            program.to_string(),
+            "a.1 = 1
+x.1 = 0
+bin.1 = a.1 > 0
+branch_if_zero bin.1, else.1
+then.1:
+x.2 = 1
+jump endif.1
+else.1:
+x.3 = 2
+endif.1:
+x.4 = phi [x.2, then.1], [x.3, else.1]
+"
+        );
+
+        let program = lower_source("let a: int = 1; if (true) { a = 2; }");
+        assert_eq!(
+            program.to_string(),
+            "a.1 = 1\nbranch_if_zero 1, endif.1\nthen.1:\na.2 = 2\nendif.1:\na.3 = phi [a.2, then.1], [a.1, entry]\n"
+        );
+    }
+
+    #[test]
+    fn test_lower_if_branch_lets_stay_local() {
+        // `y` only exists in the first branch, so the second if can't assign
+        // it and never needs a phi for it
+        let tokens =
+            lexer::lex("let c: bool = true; if (c) { let y: int = 1; } if (c) { y = 2; }").unwrap();
+        let err = lower(parser::parse(tokens).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("undeclared variable `y`"));
+
+        let program = lower_source("let c: bool = true; if (c) { let y: int = 1; }");
+        assert!(!program.to_string().contains("phi"));
+    }
+
+    #[test]
+    fn test_lower_shadowing_let() {
+        let source = "let x: int = 5; let c: bool = true;
+             if (c) { let x: bool = false; }
+             let y: int = x + 1;";
+        let ast = parser::parse(lexer::lex(source).unwrap()).unwrap();
+        let expected = crate::interp::eval(&ast).unwrap()["y"];
+        assert_eq!(expected, 6);
+
+        let mut program = lower(ast).unwrap();
+        // the inner `x` is a version of its own that never reaches a phi
+        assert!(!program.to_string().contains("phi"));
+        assert!(has_line(&program, "y.1 = x.1 + 1"));
+        run_pass(&mut program, "fold").unwrap();
+        assert!(has_line(&program, &format!("y.1 = {}", expected)));
+    }
+
+    #[test]
+    fn test_display_annotated() {
+        let program = lower_source("let x: int = 4; let y: int = x * 2 + x;");
+        assert_eq!(
+            program.to_annotated_string(),
+            "x@1 = #4
+%bin1 = x@1 * #2
+y@1 = %bin1 + x@1
+"
+        );
+
+        // labels are left alone
+        let program =
+            lower_source("let c: bool = true; let x: int = 0; if (c) { x = abs(0 - 1); }");
+        assert!(program.to_annotated_string().contains(
+            "branch_if_zero c@1, endif.1
+then.1:
+%bin1 = #0 - #1
+x@2 = abs(%bin1)
+endif.1:
+x@3 = phi [x@2, then.1], [x@1, entry]
+"
+        ));
+    }
+
+    #[test]
+    fn test_lower_while() {
+        let mut program =
+            lower_source("let i: int = 0; while (i < 3) { i = i + 1; } let j: int = i;");
+        assert_eq!(
+            program.to_string(),
+            "i.1 = 0
+while.1:
+i.2 = phi [i.1, entry], [i.3, do.1]
+bin.1 = i.2 < 3
+branch_if_zero bin.1, endwhile.1
+do.1:
+i.3 = i.2 + 1
+jump while.1
+endwhile.1:
+j.1 = i.2
+"
+        );
+        // the value coming back around the loop isn't known, so neither is
+        // the phi, even though its first source is
+        run_pass(&mut program, "fold").unwrap();
+        assert!(has_line(&program, "i.2 = phi [0, entry], [i.3, do.1]"));
+        assert!(has_line(&program, "j.1 = i.2"));
+
+        // a shadowing `let` in the body leaves the outer variable alone
+        let program = lower_source("let x: int = 1; while (x < 3) { let x: int = 5; x = 6; }");
+        assert!(!program.to_string().contains("phi"));
+    }
+
+    #[test]
+    fn test_lower_break_continue() {
+        let program = lower_source(
+            "let i: int = 0; let s: int = 0;
+             while (i < 5) {
+                 i = i + 1;
+                 if (i == 2) { continue; }
+                 if (i == 4) { break; }
+                 s = s + i;
+             }
+             let r: int = s;",
+        );
+        // continue and the end of the body both go back to the top
+        assert!(has_line(
+            &program,
+            "i.2 = phi [i.1, entry], [i.3, then.1], [i.3, endif.2]"
+        ));
+        assert!(has_line(
+            &program,
+            "s.2 = phi [s.1, entry], [s.2, then.1], [s.3, endif.2]"
+        ));
+        // the break leaves with s.2 as well, so only i needs merging
+        assert!(has_line(
+            &program,
+            "i.4 = phi [i.2, while.1], [i.3, then.2]"
+        ));
+        assert!(has_line(&program, "r.1 = s.2"));
+        // an if whose only branch breaks doesn't merge anything
+        assert_eq!(program.to_string().matches("phi").count(), 3);
+
+        // code after a break never runs, and a label picks the loop to leave
+        let program = lower_source(
+            "let n: int = 0;
+             outer: while (true) { while (true) { n = n + 1; break outer; n = 7; } n = 100; }
+             let m: int = n;",
+        );
+        assert!(!has_line(&program, "n.4 = 7"));
+        assert!(has_line(&program, "jump endwhile.1"));
+        assert!(has_line(&program, "n.6 = phi [n.2, while.1], [n.4, do.2]"));
+        assert!(has_line(&program, "m.1 = n.6"));
+    }
+
+    #[test]
+    fn test_inline_call() {
+        let program =
+            lower_source("fn double(n: int) -> int { return n * 2; } let x: int = double(21);");
+        assert_eq!(
+            program.to_string(),
+            "x.1 = 21 * 2
+return.1:
+"
+        );
+
+        // each return is a way out of the call, merged like a break
+        let program = lower_source(
+            "fn sign(n: int) -> int { if (n < 0) { return 0 - 1; } return 1; }
+             let s: int = sign(0 - 5);",
+        );
+        assert!(has_line(
+            &program,
+            "s.3 = phi [s.1, then.1], [s.2, endif.1]"
+        ));
+
+        let err = lower(
+            parser::parse(
+                lexer::lex("fn f(n: int) -> int { return f(n); } let x: int = f(1);").unwrap(),
+            )
+            .unwrap(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Lowering error: recursive function `f` cannot be inlined"
+        );
+        let err = lower(
+            parser::parse(
+                lexer::lex("fn f(n: int) -> int { if (n > 0) { return 1; } } let x: int = f(1);")
+                    .unwrap(),
+            )
+            .unwrap(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Lowering error: `f` can end without returning a value"
+        );
+    }
+
+    #[test]
+    fn test_inline_tuple_call() {
+        // each element of the returned tuple is merged on its own, named
+        // after the variable it's destructured into
+        let program = lower_source(
+            "fn order(a: int, b: int) -> (int, int) { if (a < b) { return (a, b); } return (b, a); }
+             let (low, high): (int, int) = order(5, 3);",
+        );
+        assert!(has_line(
+            &program,
+            "low.3 = phi [low.1, then.1], [low.2, endif.1]"
+        ));
+        assert!(has_line(
+            &program,
+            "high.3 = phi [high.1, then.1], [high.2, endif.1]"
+        ));
+        assert_eq!(program.final_values.len(), 2);
+    }
+
+    #[test]
+    fn test_lower_main() {
+        // main runs after the top-level statements, and its value is the exit code
+        let program = lower_source("let base: int = 40; fn main() -> int { return base + 2; }");
+        assert_eq!(
+            program.to_string(),
+            "base.1 = 40
+bin.1 = base.1 + 2
+return.1:
+exit bin.1
+"
+        );
+    }
+
+    #[test]
+    fn test_inline_call_assigning_outer_variable() {
+        // the loop doesn't assign `count` itself, but the call it makes does
+        let program = lower_source(
+            "let count: int = 0;
+             fn bump() -> void { count = count + 1; }
+             let i: int = 0;
+             while (i < 3) { bump(); i = i + 1; }
+             let c: int = count;",
+        );
+        assert!(has_line(
+            &program,
+            "count.2 = phi [count.1, entry], [count.3, return.1]"
+        ));
+        assert!(has_line(&program, "c.1 = count.2"));
+
+        // and so does one on the right of a `&&`, which may not run
+        let program = lower_source(
+            "let count: int = 0;
+             fn bump() -> bool { count = count + 1; return true; }
+             let b: bool = false && bump();
+             let c: int = count;",
+        );
+        assert!(has_line(
+            &program,
+            "count.3 = phi [count.1, entry], [count.2, return.1]"
+        ));
+        assert!(has_line(&program, "c.1 = count.3"));
+    }
+
+    #[test]
+    fn test_phi_merges_branches() {
+        let program = lower_source(
+            "let a: int = 1; let x: int = 0; let y: int = 5;
+             if (a > 0) { x = 1; } else { x = 2; }
+             let z: int = x + y;",
+        );
+        assert!(has_line(&program, "x.4 = phi [x.2, then.1], [x.3, else.1]"));
+        // `y` is untouched by either branch, so needs no phi
+        assert_eq!(program.variables["y"], 1);
+        assert!(program.to_string().contains("z.1 = x.4 + y.1"));
+
+        // both sources are the same constant, so the merge folds away
+        let mut program = lower_source(
+            "let a: int = 1; let x: int = 0; if (a > 0) { x = 3; } else { x = 3; } let z: int = x;",
+        );
+        run_pass(&mut program, "fold").unwrap();
+        assert!(has_line(&program, "z.1 = 3"));
+    }
+
+    #[test]
+    fn test_cse_stops_at_labels() {
+        let mut program = lower_source(
+            "let a: int = 1; let x: int = 0; if (a > 0) { x = a + 1; } else { x = a + 1; }",
+        );
+        run_pass(&mut program, "cse").unwrap();
+        assert!(!program
+            .instructions
+            .iter()
+            .any(|inst| matches!(inst, Instruction::Copy { .. })));
+    }
+
+    #[test]
+    fn test_lower_and_fold_many_statements() {
+        let mut source = String::from("let x0: int = 1;");
+        for i in 1..3000 {
+            source.push_str(&format!(" let x{}: int = x{} + 1;", i, i - 1));
+        }
+        let mut program = lower_source(&source);
+        assert_eq!(program.instructions.len(), 3000);
+
+        let started = std::time::Instant::now();
+        run_pass(&mut program, "fold").unwrap();
+        assert_eq!(line(&program, 2999), "x2999.1 = 3000");
+        // not a real benchmark, only a guard against accidental blowups
+        assert!(started.elapsed().as_secs() < 10);
+    }
+
+    #[test]
+    fn test_fold_output() {
+        let mut program = lower_source(
+            "let a: int = 6; let b: int = a * 7; let big: int = 9223372036854775807;
+             let c: int = big + b; let x: int = 0;
+             if (b > 40) { x = abs(a - b); } else { x = pow(a, 2); }
+             let y: int = x + c; let z: int = -b / 3;",
+        );
+        assert!(run_pass(&mut program, "fold").unwrap());
+        // the same listing the pass produced before it stopped cloning
+        // instructions and collecting operands, except that the else branch
+        // can never run and is left alone
+        assert_eq!(
+            program.to_string(),
+            "a.1 = 6
+b.1 = 42
+big.1 = 9223372036854775807
+c.1 = 9223372036854775807 + 42
+x.1 = 0
+bin.1 = 1
+branch_if_zero 1, else.1
+then.1:
+bin.2 = -36
+x.2 = 36
+jump endif.1
+else.1:
+x.3 = pow(a.1, 2)
+endif.1:
+x.4 = 36
+y.1 = 36 + c.1
+bin.3 = -42
+z.1 = -14
+"
+        );
+        assert_eq!(
+            program.warnings,
+            ["Arithmetic overflow computing c.1; not folded"]
+        );
+        // a second run finds nothing left to do
+        assert!(!run_pass(&mut program, "fold").unwrap());
+    }
+
+    #[test]
+    fn test_run_unknown_pass() {
+        let mut program = lower_source("let x: int = 3;");
+        let err = run_pass(&mut program, "inline").unwrap_err();
+        assert!(matches!(&err, PassError::UnknownPass(UnknownPass { name }) if name == "inline"));
+        assert_eq!(err.to_string(), "Unknown optimization pass: inline");
+
+        let mut program = lower_source("let x: int = 1 / 0;");
+        assert!(matches!(
+            run_pass(&mut program, "fold"),
+            Err(PassError::Fold(_))
+        ));
+    }
+
+    #[test]
+    fn test_instruction_spans() {
+        let source = "let a: int = 1;\nlet b: int = a * 2;\nlet c: int = 2 * 3;";
+        let mut program = lower_source(source);
+        let text = |inst: &Instruction| {
+            let span = inst.span().unwrap();
+            (&source[span.start..span.end], span.line, span.column)
+        };
+        assert_eq!(line(&program, 1), "b.1 = a.1 * 2");
+        assert_eq!(text(&program.instructions[1]), ("a * 2", 2, 14));
+
+        // the folded constant stands for the multiplication it replaced
+        run_pass(&mut program, "fold").unwrap();
+        assert_eq!(line(&program, 2), "c.1 = 6");
+        assert_eq!(text(&program.instructions[2]), ("2 * 3", 3, 14));
+    }
 }
@@ -1,19 +1,36 @@
+use crate::span::{Span, Spanned};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // Keywords
+    Fn,
+    Return,
     Let,
+    Const,
     If,
     Else,
+    While,
+    Break,
+    Continue,
+    Import,
 
     // Types
     TypeInt,
+    TypeBool,
+    TypeVoid,
 
     // Ident and literals
     Identifier(String),
     Integer(i64),
+    // written as `0x...`
+    HexInteger(i64),
+    Char(char),
+    Str(String),
+    True,
+    False,
 
     // Symbols
     LeftParen,
@@ -22,13 +39,18 @@ pub enum Token {
     RightBrace,
     Colon,
     Semicolon,
+    Comma,
+    Arrow,
     Equal,
 
     // Arithmetic ops
     Plus,
+    PlusPercent,
+    PlusPipe,
     Minus,
     Star,
     Slash,
+    Percent,
     EqualEqual,
     NotEqual,
     Less,
@@ -36,34 +58,200 @@ pub enum Token {
     Greater,
     GreaterEqual,
 
+    // Logical ops
+    And,
+    Or,
+    Bang,
+
     // End of file
     Eof,
 }
 
+impl Token {
+    // The variant name, ignoring any payload: `Identifier("x")` is "Identifier"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Token::Fn => "Fn",
+            Token::Return => "Return",
+            Token::Let => "Let",
+            Token::Const => "Const",
+            Token::If => "If",
+            Token::Else => "Else",
+            Token::While => "While",
+            Token::Break => "Break",
+            Token::Continue => "Continue",
+            Token::Import => "Import",
+            Token::TypeInt => "TypeInt",
+            Token::TypeBool => "TypeBool",
+            Token::TypeVoid => "TypeVoid",
+            Token::Identifier(_) => "Identifier",
+            Token::Integer(_) => "Integer",
+            Token::HexInteger(_) => "HexInteger",
+            Token::Char(_) => "Char",
+            Token::Str(_) => "Str",
+            Token::True => "True",
+            Token::False => "False",
+            Token::LeftParen => "LeftParen",
+            Token::RightParen => "RightParen",
+            Token::LeftBrace => "LeftBrace",
+            Token::RightBrace => "RightBrace",
+            Token::Colon => "Colon",
+            Token::Semicolon => "Semicolon",
+            Token::Comma => "Comma",
+            Token::Arrow => "Arrow",
+            Token::Equal => "Equal",
+            Token::Plus => "Plus",
+            Token::PlusPercent => "PlusPercent",
+            Token::PlusPipe => "PlusPipe",
+            Token::Minus => "Minus",
+            Token::Star => "Star",
+            Token::Slash => "Slash",
+            Token::Percent => "Percent",
+            Token::EqualEqual => "EqualEqual",
+            Token::NotEqual => "NotEqual",
+            Token::Less => "Less",
+            Token::LessEqual => "LessEqual",
+            Token::Greater => "Greater",
+            Token::GreaterEqual => "GreaterEqual",
+            Token::And => "And",
+            Token::Or => "Or",
+            Token::Bang => "Bang",
+            Token::Eof => "Eof",
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Token::Identifier(name) => return write!(f, "identifier '{}'", name),
+            Token::Integer(value) => return write!(f, "integer {}", value),
+            Token::HexInteger(value) => return write!(f, "integer {:#X}", value),
+            Token::Char(value) => return write!(f, "character {:?}", value),
+            Token::Str(value) => return write!(f, "string {:?}", value),
+            Token::Eof => return write!(f, "end of input"),
+            Token::True => "true",
+            Token::False => "false",
+            Token::Fn => "fn",
+            Token::Return => "return",
+            Token::Let => "let",
+            Token::Const => "const",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::While => "while",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::Import => "import",
+            Token::TypeInt => "int",
+            Token::TypeBool => "bool",
+            Token::TypeVoid => "void",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::Colon => ":",
+            Token::Semicolon => ";",
+            Token::Comma => ",",
+            Token::Arrow => "->",
+            Token::Equal => "=",
+            Token::Plus => "+",
+            Token::PlusPercent => "+%",
+            Token::PlusPipe => "+|",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::EqualEqual => "==",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Bang => "!",
+        };
+        write!(f, "'{}'", text)
+    }
+}
+
 #[derive(Debug)]
 pub struct LexerError {
     message: String,
-    position: usize,
+    // the offending characters; most errors point at a single position
+    span: Span,
+    // 1-based, counted in chars like `span`
+    line: usize,
+    column: usize,
+}
+
+impl LexerError {
+    fn new(input: &str, message: String, position: usize) -> Self {
+        LexerError::spanning(input, message, Span::new(position, position))
+    }
+
+    fn spanning(input: &str, message: String, span: Span) -> Self {
+        let before: Vec<char> = input.chars().take(span.start).collect();
+        let line_start = before
+            .iter()
+            .rposition(|&ch| ch == '\n')
+            .map_or(0, |newline| newline + 1);
+        LexerError {
+            message,
+            span,
+            line: before.iter().filter(|&&ch| ch == '\n').count() + 1,
+            column: span.start - line_start + 1,
+        }
+    }
 }
 
 impl fmt::Display for LexerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Span { start, end, .. } = self.span;
+        if end > start + 1 {
+            // columns are inclusive, positions half-open like the span
+            return write!(
+                f,
+                "Lexer error at line {}, columns {}-{} (positions {}..{}): {}",
+                self.line,
+                self.column,
+                self.column + end - start - 1,
+                start,
+                end,
+                self.message
+            );
+        }
         write!(
             f,
-            "Lexer error at position {}: {}",
-            self.position, self.message
+            "Lexer error at line {}, column {} (position {}): {}",
+            self.line, self.column, start, self.message
         )
     }
 }
 
 impl Error for LexerError {}
 
-pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+// Counts tokens by kind, leaving out the Eof every token stream ends with
+pub fn token_histogram(tokens: &[Token]) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for token in tokens.iter().filter(|token| **token != Token::Eof) {
+        *counts.entry(token.kind().to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
+// Whether `ch` begins something `lex` recognizes, if only whitespace
+fn starts_token(ch: char) -> bool {
+    ch.is_whitespace() || ch.is_alphanumeric() || "-+*%/\"=<>!&|'(){}:,;".contains(ch)
+}
+
+pub fn lex(input: &str) -> Result<Vec<Spanned<Token>>, Box<dyn Error>> {
     let mut tokens = Vec::new();
     let mut chars = input.chars().peekable();
     let mut position = 0;
 
     while let Some(&ch) = chars.peek() {
+        let start = position;
         match ch {
             ch if ch.is_whitespace() => {
                 chars.next();
@@ -82,18 +270,36 @@ pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
                 }
 
                 let token = match identifier.as_str() {
+                    "fn" => Token::Fn,
+                    "return" => Token::Return,
                     "let" => Token::Let,
+                    "const" => Token::Const,
                     "if" => Token::If,
                     "else" => Token::Else,
+                    "while" => Token::While,
+                    "break" => Token::Break,
+                    "continue" => Token::Continue,
+                    "import" => Token::Import,
                     "int" => Token::TypeInt,
+                    "bool" => Token::TypeBool,
+                    "void" => Token::TypeVoid,
+                    "true" => Token::True,
+                    "false" => Token::False,
                     _ => Token::Identifier(identifier),
                 };
-                tokens.push(token);
+                tokens.push(Spanned::new(token, Span::new(start, position)));
             }
-            ch if ch.is_digit(10) => {
+            ch if ch.is_ascii_digit() => {
+                let mut ahead = chars.clone();
+                ahead.next();
+                let hex = ch == '0' && matches!(ahead.peek(), Some('x' | 'X'));
                 let mut number = String::new();
+                if hex {
+                    chars.nth(1);
+                    position += 2;
+                }
                 while let Some(&ch) = chars.peek() {
-                    if ch.is_digit(10) {
+                    if ch.is_ascii_digit() || (hex && ch.is_ascii_hexdigit()) {
                         number.push(ch);
                         chars.next();
                         position += 1;
@@ -102,31 +308,126 @@ pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
                     }
                 }
 
-                let value = number.parse::<i64>().map_err(|_| LexerError {
-                    message: format!("Invalid integer: {}", number),
-                    position,
+                let (value, token): (_, fn(i64) -> Token) = if hex {
+                    (i64::from_str_radix(&number, 16), Token::HexInteger)
+                } else {
+                    (number.parse::<i64>(), Token::Integer)
+                };
+                let value = value.map_err(|_| {
+                    let number = if hex { format!("0x{}", number) } else { number };
+                    LexerError::new(input, format!("Invalid integer: {}", number), position)
                 })?;
-                tokens.push(Token::Integer(value));
+                tokens.push(Spanned::new(token(value), Span::new(start, position)));
             }
             '-' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::Minus);
+                if let Some(&'>') = chars.peek() {
+                    chars.next();
+                    position += 1;
+                    tokens.push(Spanned::new(Token::Arrow, Span::new(start, position)));
+                } else {
+                    tokens.push(Spanned::new(Token::Minus, Span::new(start, position)));
+                }
             }
             '+' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::Plus);
+                // `+%` wraps and `+|` saturates
+                if let Some(&'%') = chars.peek() {
+                    chars.next();
+                    position += 1;
+                    tokens.push(Spanned::new(Token::PlusPercent, Span::new(start, position)));
+                } else if let Some(&'|') = chars.peek() {
+                    chars.next();
+                    position += 1;
+                    tokens.push(Spanned::new(Token::PlusPipe, Span::new(start, position)));
+                } else {
+                    tokens.push(Spanned::new(Token::Plus, Span::new(start, position)));
+                }
             }
             '*' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::Star);
+                tokens.push(Spanned::new(Token::Star, Span::new(start, position)));
+            }
+            '%' => {
+                chars.next();
+                position += 1;
+                tokens.push(Spanned::new(Token::Percent, Span::new(start, position)));
             }
             '/' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::Slash);
+                if let Some(&'/') = chars.peek() {
+                    // line comment, up to but not including the newline
+                    while let Some(&ch) = chars.peek() {
+                        if ch == '\n' {
+                            break;
+                        }
+                        chars.next();
+                        position += 1;
+                    }
+                } else if let Some(&'*') = chars.peek() {
+                    chars.next();
+                    position += 1;
+                    // block comment; comments nest, so each `/*` needs its own `*/`
+                    let mut depth = 1;
+                    let mut previous = None;
+                    while depth > 0 {
+                        match chars.next() {
+                            Some(ch) => {
+                                position += 1;
+                                previous = match (previous, ch) {
+                                    (Some('*'), '/') => {
+                                        depth -= 1;
+                                        None
+                                    }
+                                    (Some('/'), '*') => {
+                                        depth += 1;
+                                        None
+                                    }
+                                    _ => Some(ch),
+                                };
+                            }
+                            None => {
+                                return Err(Box::new(LexerError::new(
+                                    input,
+                                    "Unterminated block comment".to_string(),
+                                    start,
+                                )));
+                            }
+                        }
+                    }
+                } else {
+                    tokens.push(Spanned::new(Token::Slash, Span::new(start, position)));
+                }
+            }
+            // No escapes: a string runs to the next quote on the same line
+            '"' => {
+                chars.next();
+                position += 1;
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => {
+                            position += 1;
+                            break;
+                        }
+                        Some(ch) if ch != '\n' => {
+                            value.push(ch);
+                            position += 1;
+                        }
+                        _ => {
+                            return Err(Box::new(LexerError::new(
+                                input,
+                                "Unterminated string".to_string(),
+                                start,
+                            )));
+                        }
+                    }
+                }
+                tokens.push(Spanned::new(Token::Str(value), Span::new(start, position)));
             }
             '=' => {
                 chars.next();
@@ -134,9 +435,9 @@ pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
                 if let Some(&'=') = chars.peek() {
                     chars.next();
                     position += 1;
-                    tokens.push(Token::EqualEqual);
+                    tokens.push(Spanned::new(Token::EqualEqual, Span::new(start, position)));
                 } else {
-                    tokens.push(Token::Equal)
+                    tokens.push(Spanned::new(Token::Equal, Span::new(start, position)))
                 }
             }
             '<' => {
@@ -145,9 +446,9 @@ pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
                 if let Some(&'=') = chars.peek() {
                     chars.next();
                     position += 1;
-                    tokens.push(Token::LessEqual);
+                    tokens.push(Spanned::new(Token::LessEqual, Span::new(start, position)));
                 } else {
-                    tokens.push(Token::Less)
+                    tokens.push(Spanned::new(Token::Less, Span::new(start, position)))
                 }
             }
             '>' => {
@@ -156,9 +457,12 @@ pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
                 if let Some(&'=') = chars.peek() {
                     chars.next();
                     position += 1;
-                    tokens.push(Token::GreaterEqual);
+                    tokens.push(Spanned::new(
+                        Token::GreaterEqual,
+                        Span::new(start, position),
+                    ));
                 } else {
-                    tokens.push(Token::Greater)
+                    tokens.push(Spanned::new(Token::Greater, Span::new(start, position)))
                 }
             }
             '!' => {
@@ -167,56 +471,205 @@ pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
                 if let Some(&'=') = chars.peek() {
                     chars.next();
                     position += 1;
-                    tokens.push(Token::NotEqual);
+                    tokens.push(Spanned::new(Token::NotEqual, Span::new(start, position)));
+                } else {
+                    tokens.push(Spanned::new(Token::Bang, Span::new(start, position)));
+                }
+            }
+            '\\' => {
+                // A backslash at end of line continues onto the next one
+                chars.next();
+                position += 1;
+                if let Some(&'\r') = chars.peek() {
+                    chars.next();
+                    position += 1;
+                }
+                if let Some(&'\n') = chars.peek() {
+                    chars.next();
+                    position += 1;
                 } else {
-                    return Err(Box::new(LexerError {
-                        message: "Expected '=' after '!'".to_string(),
+                    return Err(Box::new(LexerError::new(
+                        input,
+                        "Expected newline after '\\'".to_string(),
                         position,
-                    }));
+                    )));
+                }
+            }
+            '&' => {
+                chars.next();
+                position += 1;
+                if let Some(&'&') = chars.peek() {
+                    chars.next();
+                    position += 1;
+                    tokens.push(Spanned::new(Token::And, Span::new(start, position)));
+                } else {
+                    return Err(Box::new(LexerError::new(
+                        input,
+                        "Expected '&' after '&'".to_string(),
+                        position,
+                    )));
+                }
+            }
+            '|' => {
+                chars.next();
+                position += 1;
+                if let Some(&'|') = chars.peek() {
+                    chars.next();
+                    position += 1;
+                    tokens.push(Spanned::new(Token::Or, Span::new(start, position)));
+                } else {
+                    return Err(Box::new(LexerError::new(
+                        input,
+                        "Expected '|' after '|'".to_string(),
+                        position,
+                    )));
+                }
+            }
+            '\'' => {
+                chars.next();
+                position += 1;
+                let value = match chars.next() {
+                    Some('\\') => {
+                        position += 1;
+                        let escaped = match chars.next() {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('0') => '\0',
+                            Some('\\') => '\\',
+                            Some('\'') => '\'',
+                            Some(other) => {
+                                return Err(Box::new(LexerError::new(
+                                    input,
+                                    format!("Unknown escape sequence: \\{}", other),
+                                    position,
+                                )));
+                            }
+                            None => {
+                                return Err(Box::new(LexerError::new(
+                                    input,
+                                    "Unterminated character literal".to_string(),
+                                    start,
+                                )));
+                            }
+                        };
+                        position += 1;
+                        escaped
+                    }
+                    Some('\'') => {
+                        return Err(Box::new(LexerError::new(
+                            input,
+                            "Empty character literal".to_string(),
+                            position,
+                        )));
+                    }
+                    Some(ch) => {
+                        position += 1;
+                        ch
+                    }
+                    None => {
+                        return Err(Box::new(LexerError::new(
+                            input,
+                            "Unterminated character literal".to_string(),
+                            start,
+                        )));
+                    }
+                };
+                match chars.next() {
+                    Some('\'') => {}
+                    Some(_) => {
+                        return Err(Box::new(LexerError::new(
+                            input,
+                            "Character literal must contain exactly one character".to_string(),
+                            start,
+                        )));
+                    }
+                    None => {
+                        return Err(Box::new(LexerError::new(
+                            input,
+                            "Unterminated character literal".to_string(),
+                            start,
+                        )));
+                    }
                 }
+                position += 1;
+                tokens.push(Spanned::new(Token::Char(value), Span::new(start, position)));
             }
             '(' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::LeftParen);
+                tokens.push(Spanned::new(Token::LeftParen, Span::new(start, position)));
             }
             ')' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::RightParen);
+                tokens.push(Spanned::new(Token::RightParen, Span::new(start, position)));
             }
             '{' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::LeftBrace);
+                tokens.push(Spanned::new(Token::LeftBrace, Span::new(start, position)));
             }
             '}' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::RightBrace);
+                tokens.push(Spanned::new(Token::RightBrace, Span::new(start, position)));
             }
             ':' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::Colon);
+                tokens.push(Spanned::new(Token::Colon, Span::new(start, position)));
+            }
+            ',' => {
+                chars.next();
+                position += 1;
+                tokens.push(Spanned::new(Token::Comma, Span::new(start, position)));
             }
             ';' => {
                 chars.next();
                 position += 1;
-                tokens.push(Token::Semicolon);
+                tokens.push(Spanned::new(Token::Semicolon, Span::new(start, position)));
             }
             _ => {
-                return Err(Box::new(LexerError {
-                    message: format!("Unexpected character: {}", ch),
-                    position,
-                }));
+                // Report a run of unexpected characters as a single error
+                let mut run = String::new();
+                while let Some(ch) = chars.next_if(|&ch| !starts_token(ch)) {
+                    run.push(ch);
+                    position += 1;
+                }
+                let message = if run.chars().count() > 1 {
+                    format!("Unexpected characters: {}", run)
+                } else {
+                    format!("Unexpected character: {}", run)
+                };
+                let span = Span::new(start, position);
+                return Err(Box::new(LexerError::spanning(input, message, span)));
             }
         }
     }
-    tokens.push(Token::Eof);
+    tokens.push(Spanned::new(Token::Eof, Span::new(position, position)));
+    locate(input, &mut tokens);
     Ok(tokens)
 }
 
+// Sets the line and column each token starts at from its offset
+fn locate(input: &str, tokens: &mut [Spanned<Token>]) {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(
+            input
+                .chars()
+                .enumerate()
+                .filter(|&(_, ch)| ch == '\n')
+                .map(|(offset, _)| offset + 1),
+        )
+        .collect();
+    for token in tokens {
+        let span = &mut token.span;
+        span.line = line_starts.partition_point(|&start| start <= span.start);
+        span.column = span.start - line_starts[span.line - 1] + 1;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -224,7 +677,7 @@ mod tests {
     #[test]
     fn test_lex_function() {
         let input = "let x: int = 3; let y: int = 2; let z: int = x + y;";
-        let tokens = lex(input).unwrap();
+        let tokens: Vec<Token> = lex(input).unwrap().into_iter().map(|t| t.node).collect();
         let expected = [
             Token::Let,
             Token::Identifier("x".to_string()),
@@ -253,4 +706,326 @@ mod tests {
         ];
         assert_eq!(tokens, expected);
     }
+
+    #[test]
+    fn test_token_histogram() {
+        let input = "let x: int = 3; let y: int = 2; let z: int = x + y;";
+        let tokens: Vec<Token> = lex(input).unwrap().into_iter().map(|t| t.node).collect();
+        let histogram = token_histogram(&tokens);
+        assert_eq!(histogram["TypeInt"], 3);
+        assert_eq!(histogram["Identifier"], 5);
+        assert!(!histogram.contains_key("Eof"));
+    }
+
+    #[test]
+    fn test_lex_coalesces_unexpected_characters() {
+        let err = lex("let x: int = @@@;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Lexer error at line 1, columns 14-16 (positions 13..16): Unexpected characters: @@@"
+        );
+
+        // a run of different characters is one error too
+        let err = lex("let x: int = @#$ 1;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Lexer error at line 1, columns 14-16 (positions 13..16): Unexpected characters: @#$"
+        );
+
+        let err = lex("let x: int = 1;\nlet y: int = ##;").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("line 2, columns 14-15 (positions 29..31)"));
+    }
+
+    #[test]
+    fn test_lex_function_definition() {
+        let input = "fn add(a: int, b: int) -> int { }";
+        let tokens: Vec<Token> = lex(input).unwrap().into_iter().map(|t| t.node).collect();
+        let expected = [
+            Token::Fn,
+            Token::Identifier("add".to_string()),
+            Token::LeftParen,
+            Token::Identifier("a".to_string()),
+            Token::Colon,
+            Token::TypeInt,
+            Token::Comma,
+            Token::Identifier("b".to_string()),
+            Token::Colon,
+            Token::TypeInt,
+            Token::RightParen,
+            Token::Arrow,
+            Token::TypeInt,
+            Token::LeftBrace,
+            Token::RightBrace,
+            Token::Eof,
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_lex_logical_operators() {
+        let tokens: Vec<Token> = lex("a && b || c")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.node)
+            .collect();
+        let expected = [
+            Token::Identifier("a".to_string()),
+            Token::And,
+            Token::Identifier("b".to_string()),
+            Token::Or,
+            Token::Identifier("c".to_string()),
+            Token::Eof,
+        ];
+        assert_eq!(tokens, expected);
+        assert!(lex("a & b").is_err());
+    }
+
+    #[test]
+    fn test_lex_bang() {
+        let tokens: Vec<Token> = lex("!x").unwrap().into_iter().map(|t| t.node).collect();
+        assert_eq!(
+            tokens,
+            [Token::Bang, Token::Identifier("x".to_string()), Token::Eof]
+        );
+        let tokens: Vec<Token> = lex("x != y").unwrap().into_iter().map(|t| t.node).collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Identifier("x".to_string()),
+                Token::NotEqual,
+                Token::Identifier("y".to_string()),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_char_literals() {
+        let tokens: Vec<Token> = lex(r"'a' '\n' '\\' '\''")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.node)
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Char('a'),
+                Token::Char('\n'),
+                Token::Char('\\'),
+                Token::Char('\''),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_invalid_char_literals() {
+        assert!(lex("''")
+            .unwrap_err()
+            .to_string()
+            .contains("Empty character literal"));
+        assert!(lex("'ab'")
+            .unwrap_err()
+            .to_string()
+            .contains("exactly one character"));
+        assert!(lex("'a")
+            .unwrap_err()
+            .to_string()
+            .contains("Unterminated character literal"));
+    }
+
+    #[test]
+    fn test_lex_line_comments() {
+        let tokens: Vec<Token> = lex("// header\nlet x = a / b; // trailing\n// at eof")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.node)
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Let,
+                Token::Identifier("x".to_string()),
+                Token::Equal,
+                Token::Identifier("a".to_string()),
+                Token::Slash,
+                Token::Identifier("b".to_string()),
+                Token::Semicolon,
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_block_comments() {
+        let tokens = lex("a /* inline */ b").unwrap();
+        let nodes: Vec<&Token> = tokens.iter().map(|t| &t.node).collect();
+        assert_eq!(
+            nodes,
+            [
+                &Token::Identifier("a".to_string()),
+                &Token::Identifier("b".to_string()),
+                &Token::Eof
+            ]
+        );
+        assert_eq!(tokens[1].span, Span::new(15, 16));
+
+        let tokens: Vec<Token> = lex("let /* spans\nseveral\nlines **/ x")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.node)
+            .collect();
+        assert_eq!(
+            tokens,
+            [Token::Let, Token::Identifier("x".to_string()), Token::Eof]
+        );
+    }
+
+    #[test]
+    fn test_lex_nested_block_comments() {
+        let tokens: Vec<Token> = lex("a /* outer /* inner */ still in comment */ b")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.node)
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Identifier("a".to_string()),
+                Token::Identifier("b".to_string()),
+                Token::Eof
+            ]
+        );
+
+        // the outer comment is still open, and is the one reported
+        let err = lex("x /* outer /* inner */ never closed").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("(position 2): Unterminated block comment"));
+    }
+
+    #[test]
+    fn test_lex_unterminated_block_comment() {
+        let err = lex("let x = 1; /* never closed").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Lexer error at line 1, column 12 (position 11): Unterminated block comment"
+        );
+        // `*/` needs its own `*`, not the one that opened the comment
+        assert!(lex("/*/").is_err());
+    }
+
+    #[test]
+    fn test_lex_error_line_and_column() {
+        let err = lex("let x = 1;\nlet y = @;").unwrap_err();
+        assert!(err.to_string().contains("line 2, column 9"));
+        assert!(err.to_string().contains("Unexpected character: @"));
+    }
+
+    #[test]
+    fn test_lex_percent() {
+        let tokens: Vec<Token> = lex("a % 2").unwrap().into_iter().map(|t| t.node).collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Identifier("a".to_string()),
+                Token::Percent,
+                Token::Integer(2),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_hex_integers() {
+        let tokens: Vec<Token> = lex("0xFF00 0x1f 0 07")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.node)
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::HexInteger(0xFF00),
+                Token::HexInteger(0x1f),
+                Token::Integer(0),
+                Token::Integer(7),
+                Token::Eof
+            ]
+        );
+        assert_eq!(Token::HexInteger(0xFF00).to_string(), "integer 0xFF00");
+        let err = lex("let x: int = 0x;").unwrap_err();
+        assert!(err.to_string().contains("Invalid integer: 0x"));
+        assert!(lex("0x8000000000000000").is_err());
+    }
+
+    #[test]
+    fn test_lex_import() {
+        let tokens: Vec<Token> = lex(r#"import "lib.crucible";"#)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.node)
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Import,
+                Token::Str("lib.crucible".to_string()),
+                Token::Semicolon,
+                Token::Eof
+            ]
+        );
+
+        let err = lex("import \"lib;\n").unwrap_err();
+        assert!(err.to_string().contains("Unterminated string"));
+    }
+
+    #[test]
+    fn test_lex_explicit_overflow_operators() {
+        let tokens: Vec<Token> = lex("a +% b +| c + d")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.node)
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Identifier("a".to_string()),
+                Token::PlusPercent,
+                Token::Identifier("b".to_string()),
+                Token::PlusPipe,
+                Token::Identifier("c".to_string()),
+                Token::Plus,
+                Token::Identifier("d".to_string()),
+                Token::Eof
+            ]
+        );
+
+        // `||` is still logical or, but a lone `|` means nothing
+        assert!(lex("a | b").is_err());
+    }
+
+    #[test]
+    fn test_lex_spans() {
+        let tokens = lex("let xy = 10;").unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|t| t.span).collect();
+        assert_eq!(
+            spans,
+            [
+                Span::new(0, 3),
+                Span::new(4, 6),
+                Span::new(7, 8),
+                Span::new(9, 11),
+                Span::new(11, 12),
+                Span::new(12, 12),
+            ]
+        );
+
+        let tokens = lex("let x = 1;\n  x = 2;").unwrap();
+        let second = &tokens[5].span;
+        assert_eq!((second.start, second.line, second.column), (13, 2, 3));
+        assert_eq!(second.to_string(), "line 2, column 3");
+    }
 }
@@ -0,0 +1,17 @@
+pub mod arith;
+pub mod ast;
+pub mod bytecode;
+pub mod callgraph;
+pub mod cfg;
+pub mod codegen_c;
+pub mod const_eval;
+pub mod driver;
+pub mod intern;
+pub mod interp;
+pub mod ir;
+pub mod lexer;
+pub mod lint;
+pub mod parser;
+pub mod preprocess;
+pub mod span;
+pub mod typecheck;
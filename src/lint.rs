@@ -0,0 +1,264 @@
+use crate::ast::{Expr, Statement};
+use crate::lexer::Token;
+use crate::span::Spanned;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseConvention {
+    SnakeCase,
+    CamelCase,
+}
+
+impl CaseConvention {
+    fn matches(self, name: &str) -> bool {
+        let starts_lowercase = name.chars().next().is_some_and(|c| c.is_ascii_lowercase());
+        match self {
+            CaseConvention::SnakeCase => {
+                starts_lowercase
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            }
+            CaseConvention::CamelCase => {
+                starts_lowercase && name.chars().all(|c| c.is_ascii_alphanumeric())
+            }
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            CaseConvention::SnakeCase => "snake_case",
+            CaseConvention::CamelCase => "camelCase",
+        }
+    }
+}
+
+// Every lint is off by default
+#[derive(Debug, Default)]
+pub struct LintOptions {
+    pub identifier_case: Option<CaseConvention>,
+    pub mixed_logical_operators: bool,
+    pub no_effect_statements: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    pub message: String,
+}
+
+pub fn lint(statements: &[Spanned<Statement>], options: &LintOptions) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    if let Some(convention) = options.identifier_case {
+        check_identifier_case(statements, convention, &mut warnings);
+    }
+    if options.no_effect_statements {
+        check_no_effect_statements(statements, &mut warnings);
+    }
+    warnings
+}
+
+// Lints that need the token stream, since the AST doesn't record parentheses
+pub fn lint_tokens(tokens: &[Spanned<Token>], options: &LintOptions) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    if options.mixed_logical_operators {
+        check_mixed_logical_operators(tokens, &mut warnings);
+    }
+    warnings
+}
+
+// Operators seen at one parenthesis depth of an expression
+#[derive(Default)]
+struct LogicalLevel {
+    and: bool,
+    or: bool,
+    warned: bool,
+}
+
+// Warns when `&&` and `||` appear at the same parenthesis depth of one
+// expression, where `&&` silently binds tighter
+fn check_mixed_logical_operators(tokens: &[Spanned<Token>], warnings: &mut Vec<Warning>) {
+    let mut levels = vec![LogicalLevel::default()];
+
+    for token in tokens {
+        match token.node {
+            Token::LeftParen => levels.push(LogicalLevel::default()),
+            Token::RightParen if levels.len() > 1 => {
+                levels.pop();
+            }
+            // each argument of a call is an expression of its own
+            Token::Comma => *levels.last_mut().unwrap() = LogicalLevel::default(),
+            Token::Semicolon | Token::LeftBrace | Token::RightBrace => {
+                levels = vec![LogicalLevel::default()];
+            }
+            Token::And | Token::Or => {
+                let level = levels.last_mut().unwrap();
+                if matches!(token.node, Token::And) {
+                    level.and = true;
+                } else {
+                    level.or = true;
+                }
+                if level.and && level.or && !level.warned {
+                    level.warned = true;
+                    warnings.push(Warning {
+                        message: format!(
+                            "Mixing '&&' and '||' without parentheses at {}; add parentheses to make the precedence explicit",
+                            token.span
+                        ),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Only `let` names are checked; consts are conventionally upper case
+fn check_identifier_case(
+    statements: &[Spanned<Statement>],
+    convention: CaseConvention,
+    warnings: &mut Vec<Warning>,
+) {
+    for stmt in statements {
+        match &stmt.node {
+            Statement::Let { name, .. } => {
+                if !convention.matches(name) {
+                    warnings.push(Warning {
+                        message: format!("Variable `{}` should be {}", name, convention.name()),
+                    });
+                }
+            }
+            Statement::Destructure { names, .. } => {
+                for name in names.iter().filter(|name| !convention.matches(name)) {
+                    warnings.push(Warning {
+                        message: format!("Variable `{}` should be {}", name, convention.name()),
+                    });
+                }
+            }
+            Statement::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                check_identifier_case(then_branch, convention, warnings);
+                if let Some(else_branch) = else_branch {
+                    check_identifier_case(else_branch, convention, warnings);
+                }
+            }
+            Statement::While { body, .. } => check_identifier_case(body, convention, warnings),
+            Statement::Function { body, .. } => check_identifier_case(body, convention, warnings),
+            Statement::Const { .. }
+            | Statement::Assignment { .. }
+            | Statement::Return(_)
+            | Statement::Break(_)
+            | Statement::Continue(_)
+            | Statement::Expression(_)
+            | Statement::Result(_)
+            | Statement::Import(_) => {}
+        }
+    }
+}
+
+// Warns on expression statements other than calls, which compute a value
+// only to throw it away
+fn check_no_effect_statements(statements: &[Spanned<Statement>], warnings: &mut Vec<Warning>) {
+    for stmt in statements {
+        match &stmt.node {
+            Statement::Expression(Spanned {
+                node: Expr::Call { .. },
+                ..
+            }) => {}
+            Statement::Expression(expr) => warnings.push(Warning {
+                message: format!("Statement `{};` has no effect", expr),
+            }),
+            Statement::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                check_no_effect_statements(then_branch, warnings);
+                if let Some(else_branch) = else_branch {
+                    check_no_effect_statements(else_branch, warnings);
+                }
+            }
+            Statement::While { body, .. } | Statement::Function { body, .. } => {
+                check_no_effect_statements(body, warnings)
+            }
+            Statement::Let { .. }
+            | Statement::Destructure { .. }
+            | Statement::Const { .. }
+            | Statement::Assignment { .. }
+            | Statement::Return(_)
+            | Statement::Break(_)
+            | Statement::Continue(_)
+            | Statement::Result(_)
+            | Statement::Import(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    fn lint_source(input: &str, options: &LintOptions) -> Vec<Warning> {
+        let tokens = lexer::lex(input).unwrap();
+        lint(&parser::parse(tokens).unwrap(), options)
+    }
+
+    #[test]
+    fn test_identifier_case() {
+        let options = LintOptions {
+            identifier_case: Some(CaseConvention::SnakeCase),
+            ..LintOptions::default()
+        };
+        let warnings = lint_source("let myVar: int = 1;", &options);
+        assert_eq!(
+            warnings,
+            [Warning {
+                message: "Variable `myVar` should be snake_case".to_string()
+            }]
+        );
+        assert!(lint_source("let my_var: int = 1;", &options).is_empty());
+    }
+
+    #[test]
+    fn test_mixed_logical_operators() {
+        let options = LintOptions {
+            mixed_logical_operators: true,
+            ..LintOptions::default()
+        };
+        let tokens = lexer::lex("let x: bool = a || b && c;").unwrap();
+        let warnings = lint_tokens(&tokens, &options);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("at line 1, column 22"));
+
+        let tokens = lexer::lex("let x: bool = a || (b && c);").unwrap();
+        assert!(lint_tokens(&tokens, &options).is_empty());
+
+        let tokens = lexer::lex("let x: bool = f(a && b, c || d);").unwrap();
+        assert!(lint_tokens(&tokens, &options).is_empty());
+        let tokens = lexer::lex("let x: bool = f(a, b || c && d);").unwrap();
+        assert_eq!(lint_tokens(&tokens, &options).len(), 1);
+    }
+
+    #[test]
+    fn test_no_effect_statements() {
+        let options = LintOptions {
+            no_effect_statements: true,
+            ..LintOptions::default()
+        };
+        let warnings = lint_source("let x: int = 1; while (x > 0) { x + 1; }", &options);
+        assert_eq!(
+            warnings,
+            [Warning {
+                message: "Statement `(x + 1);` has no effect".to_string()
+            }]
+        );
+        assert!(lint_source("let x: int = 1; print(x);", &options).is_empty());
+    }
+
+    #[test]
+    fn test_identifier_case_off_by_default() {
+        assert!(lint_source("let myVar: int = 1;", &LintOptions::default()).is_empty());
+    }
+}
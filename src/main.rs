@@ -1,27 +1,165 @@
-mod ast;
-mod ir;
-mod lexer;
-mod parser;
-
+use crucible::arith::OverflowPolicy;
+use crucible::driver::{self, CompileOptions};
+use crucible::ir;
+use std::env;
 use std::error::Error;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::ExitCode;
+
+// What the binary does with the program once it's parsed
+enum Mode {
+    Compile,
+    // interpret it, exiting with the code its `main` returns
+    Run,
+    // interpret the top level, printing the program's result
+    Eval,
+    // print how optimization changed the lowered IR
+    OptDiff,
+}
+
+// Exit codes reported by the binary
+const EXIT_COMPILE_ERROR: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+
+fn main() -> ExitCode {
+    let Some((path, options, mode)) = parse_args(env::args().skip(1)) else {
+        eprintln!(
+            "usage: crucible [-D FLAG]... [--overflow POLICY] [--run | --eval | --emit opt-diff] <file>\n       \
+             crucible [-D FLAG]... [--overflow POLICY] [--run | --eval | --emit opt-diff] -    (read the program from stdin)\n\
+             POLICY is checked (the default), wrapping or saturating\n\
+             --run interprets the program and exits with the code its `main` returns\n\
+             --eval interprets the top level and prints the expression it ends with\n\
+             --emit opt-diff prints the lowered IR's changes under optimization"
+        );
+        return ExitCode::from(EXIT_USAGE);
+    };
+    match mode {
+        Mode::Compile => {}
+        Mode::Run => {
+            return match run_main(&path, &options) {
+                // a shell only sees the low byte of the code anyway
+                Ok(code) => ExitCode::from(code as u8),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    ExitCode::from(EXIT_COMPILE_ERROR)
+                }
+            };
+        }
+        Mode::Eval => {
+            return match eval(&path, &options) {
+                Ok(result) => {
+                    if let Some(result) = result {
+                        println!("{}", result);
+                    }
+                    ExitCode::SUCCESS
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    ExitCode::from(EXIT_COMPILE_ERROR)
+                }
+            };
+        }
+        Mode::OptDiff => {
+            return match opt_diff(&path, &options) {
+                Ok(diff) => {
+                    print!("{}", diff);
+                    ExitCode::SUCCESS
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    ExitCode::from(EXIT_COMPILE_ERROR)
+                }
+            };
+        }
+    }
+    match run(&path, &options) {
+        Ok(result) => {
+            println!("Compilation successful: {}", result);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::from(EXIT_COMPILE_ERROR)
+        }
+    }
+}
+
+// The program path, plus the flags each `-D FLAG` defines for `#if` guards,
+// the policy `--overflow` picks for constant folding and the mode `--run`,
+// `--eval` or `--emit opt-diff` picked
+fn parse_args(mut args: impl Iterator<Item = String>) -> Option<(String, CompileOptions, Mode)> {
+    let mut path = None;
+    let mut options = CompileOptions::default();
+    let mut mode = Mode::Compile;
+    while let Some(arg) = args.next() {
+        if arg == "-D" {
+            options.flags.insert(args.next()?);
+        } else if arg == "--overflow" {
+            options.overflow_policy = OverflowPolicy::from_name(&args.next()?)?;
+        } else if arg == "--run" {
+            mode = Mode::Run;
+        } else if arg == "--eval" {
+            mode = Mode::Eval;
+        } else if arg == "--emit" {
+            if args.next()? != "opt-diff" {
+                return None;
+            }
+            mode = Mode::OptDiff;
+        } else if path.replace(arg).is_some() {
+            return None;
+        }
+    }
+    Some((path?, options, mode))
+}
+
+fn read_stdin() -> Result<String, Box<dyn Error>> {
+    let mut source = String::new();
+    io::stdin()
+        .read_to_string(&mut source)
+        .map_err(|err| format!("Could not read stdin: {}", err))?;
+    Ok(source)
+}
+
+// Interprets the program instead, returning the exit code from its `main`
+fn run_main(path: &str, options: &CompileOptions) -> Result<i64, Box<dyn Error>> {
+    if path == "-" {
+        driver::run(&read_stdin()?, options)
+    } else {
+        driver::run_file(Path::new(path), options)
+    }
+}
+
+// The value of the program's trailing expression
+fn eval(path: &str, options: &CompileOptions) -> Result<Option<i64>, Box<dyn Error>> {
+    if path == "-" {
+        driver::eval(&read_stdin()?, options)
+    } else {
+        driver::eval_file(Path::new(path), options)
+    }
+}
+
+// The diff of the lowered program against its optimized version
+fn opt_diff(path: &str, options: &CompileOptions) -> Result<String, Box<dyn Error>> {
+    let before = if path == "-" {
+        driver::lower_with_options(&read_stdin()?, options)?
+    } else {
+        driver::lower_file_with_options(Path::new(path), options)?
+    };
+    let mut after = before.clone();
+    ir::optimize(&mut after, options.overflow_policy, false)?;
+    Ok(ir::diff_ir(&before, &after))
+}
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let source = "
-        let x: int = 3;
-        let unused: int = 0;
-        let y: int = x + 1;
-        let z: int = x * y / 2;
-        z = z + 1;
-    ";
-    let result = compile(source)?;
-    println!("Compilation successful: {}", result);
-    Ok(())
-}
-
-fn compile(source: &str) -> Result<String, Box<dyn Error>> {
-    let tokens = lexer::lex(source)?;
-    let ast = parser::parse(tokens)?;
-    let mut ir = ir::lower(ast);
-    ir::optimize(&mut ir);
+// A path of `-` reads the program from stdin
+fn run(path: &str, options: &CompileOptions) -> Result<String, Box<dyn Error>> {
+    let program = if path == "-" {
+        driver::compile_with_options(&read_stdin()?, options)?
+    } else {
+        driver::compile_file_with_options(Path::new(path), options)?
+    };
+    for warning in &program.warnings {
+        eprintln!("warning: {}", warning);
+    }
     Ok("ok".to_string())
 }
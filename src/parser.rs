@@ -1,5 +1,6 @@
-use crate::ast::{BinaryOp, Expr, Statement, Type};
+use crate::ast::{BinaryOp, Expr, Radix, Statement, Type, UnaryOp};
 use crate::lexer::Token;
+use crate::span::{Span, Spanned};
 use std::{error::Error, fmt};
 
 #[derive(Debug)]
@@ -16,27 +17,49 @@ impl fmt::Display for ParseError {
 impl Error for ParseError {}
 
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<Spanned<Token>>,
     current: usize,
+    // the declared return type of the function whose body is being parsed,
+    // which tells a returned tuple from a sequence
+    return_type: Option<Type>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+    pub fn new(tokens: Vec<Spanned<Token>>) -> Self {
+        Parser {
+            tokens,
+            current: 0,
+            return_type: None,
+        }
     }
     fn peek(&self) -> &Token {
-        &self.tokens[self.current]
+        &self.tokens[self.current].node
+    }
+
+    fn peek_next(&self) -> Option<&Token> {
+        self.tokens.get(self.current + 1).map(|t| &t.node)
     }
 
+    // Eof is never consumed, so advancing past the end keeps returning it
     fn advance(&mut self) -> &Token {
-        if !self.is_at_end() {
-            self.current += 1;
+        if self.is_at_end() {
+            return self.peek();
         }
+        self.current += 1;
         self.previous()
     }
 
     fn previous(&self) -> &Token {
-        &self.tokens[self.current - 1]
+        &self.tokens[self.current - 1].node
+    }
+
+    fn peek_span(&self) -> Span {
+        self.tokens[self.current].span
+    }
+
+    // Wraps a node that began at `start` and ended with the last token consumed
+    fn spanned<T>(&self, node: T, start: Span) -> Spanned<T> {
+        Spanned::new(node, start.to(self.tokens[self.current - 1].span))
     }
 
     fn is_at_end(&self) -> bool {
@@ -56,103 +79,604 @@ impl Parser {
     fn parse_type(&mut self) -> Result<Type, Box<dyn Error>> {
         match self.advance() {
             Token::TypeInt => Ok(Type::Int),
+            Token::TypeBool => Ok(Type::Bool),
+            Token::TypeVoid => Ok(Type::Void),
+            Token::LeftParen => {
+                let mut elements = Vec::new();
+                loop {
+                    match self.parse_type()? {
+                        typ @ (Type::Int | Type::Bool) => elements.push(typ),
+                        typ => {
+                            return Err(Box::new(ParseError {
+                                message: format!("A tuple can only hold int and bool, not {}", typ),
+                            }))
+                        }
+                    }
+                    if !matches!(self.peek(), Token::Comma) {
+                        break;
+                    }
+                    self.advance(); // consume ','
+                }
+                self.consume(Token::RightParen, "Expected ')' after tuple element types")?;
+                if elements.len() < 2 {
+                    return Err(Box::new(ParseError {
+                        message: "A tuple type needs at least two elements".to_string(),
+                    }));
+                }
+                Ok(Type::Tuple(elements))
+            }
             _ => Err(Box::new(ParseError {
                 message: "Expected type".to_string(),
             })),
         }
     }
 
+    // Parses an expression where a value of type `expected` is wanted. There a
+    // parenthesized list is a tuple if a tuple is expected, where anywhere
+    // else it would be a sequence.
+    fn parse_value(&mut self, expected: Option<&Type>) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        if !matches!(expected, Some(Type::Tuple(_))) || !matches!(self.peek(), Token::LeftParen) {
+            return self.parse_expression();
+        }
+        let (start, before) = (self.peek_span(), self.current);
+        self.advance(); // consume '('
+        let mut elements = vec![self.parse_expression()?];
+        while matches!(self.peek(), Token::Comma) {
+            self.advance(); // consume ','
+            elements.push(self.parse_expression()?);
+        }
+        // a lone parenthesized expression is parsed again as just that
+        if elements.len() == 1 {
+            self.current = before;
+            return self.parse_expression();
+        }
+        self.consume(Token::RightParen, "Expected ')' after tuple elements")?;
+        Ok(self.spanned(Expr::Tuple(elements), start))
+    }
+
+    // Parses the `name: type = value;` tail shared by `let` and `const`
+    fn parse_declaration(&mut self) -> Result<(String, Type, Spanned<Expr>), Box<dyn Error>> {
+        let name = match self.advance() {
+            Token::Identifier(name) => name.clone(),
+            token @ (Token::TypeInt | Token::TypeBool | Token::TypeVoid) => {
+                return Err(Box::new(ParseError {
+                    message: format!("type keyword {} cannot be used as a variable name", token),
+                }))
+            }
+            _ => {
+                return Err(Box::new(ParseError {
+                    message: "Expected variable name".to_string(),
+                }))
+            }
+        };
+        self.consume(Token::Colon, "Expected ':' after variable name")?;
+        let typ = self.parse_type()?;
+        self.consume(Token::Equal, "Expected '=' after type")?;
+        let value = self.parse_value(Some(&typ))?;
+        self.consume(Token::Semicolon, "Expected ';' after expression")?;
+        Ok((name, typ, value))
+    }
+
+    // Parses the `(x, y): (int, int) = value;` tail of a destructuring `let`
+    fn parse_destructure(&mut self) -> Result<Statement, Box<dyn Error>> {
+        self.advance(); // consume '('
+        let mut names: Vec<String> = Vec::new();
+        loop {
+            let name = match self.advance() {
+                Token::Identifier(name) => name.clone(),
+                _ => {
+                    return Err(Box::new(ParseError {
+                        message: "Expected variable name".to_string(),
+                    }))
+                }
+            };
+            if names.contains(&name) {
+                return Err(Box::new(ParseError {
+                    message: format!("`{}` is bound twice in one `let`", name),
+                }));
+            }
+            names.push(name);
+            if !matches!(self.peek(), Token::Comma) {
+                break;
+            }
+            self.advance(); // consume ','
+        }
+        self.consume(Token::RightParen, "Expected ')' after variable names")?;
+        self.consume(Token::Colon, "Expected ':' after variable names")?;
+        let typ = self.parse_type()?;
+        self.consume(Token::Equal, "Expected '=' after type")?;
+        let value = self.parse_value(Some(&typ))?;
+        self.consume(Token::Semicolon, "Expected ';' after expression")?;
+        Ok(Statement::Destructure { names, typ, value })
+    }
+
+    // Parses statements up to and including `terminator`, which must appear
+    // before the end of input unless it is Eof itself
+    pub fn parse_until(
+        &mut self,
+        terminator: Token,
+    ) -> Result<Vec<Spanned<Statement>>, Box<dyn Error>> {
+        let mut statements = Vec::new();
+
+        while self.peek() != &terminator {
+            if self.is_at_end() {
+                return Err(Box::new(ParseError {
+                    message: format!("Expected {} before end of input", terminator),
+                }));
+            }
+            let start = self.peek_span();
+            let stmt = self.parse_statement()?;
+            statements.push(self.spanned(stmt, start));
+        }
+        self.advance();
+
+        Ok(statements)
+    }
+
     fn parse_statement(&mut self) -> Result<Statement, Box<dyn Error>> {
         match self.peek() {
             Token::Let => {
                 self.advance(); // consume 'let'
-                let name = match self.advance() {
-                    Token::Identifier(name) => name.clone(),
+                if matches!(self.peek(), Token::LeftParen) {
+                    return self.parse_destructure();
+                }
+                let (name, typ, value) = self.parse_declaration()?;
+                Ok(Statement::Let { name, typ, value })
+            }
+            Token::Const => {
+                self.advance(); // consume 'const'
+                let (name, typ, value) = self.parse_declaration()?;
+                Ok(Statement::Const { name, typ, value })
+            }
+            Token::Fn => self.parse_function(),
+            Token::Return => {
+                self.advance(); // consume 'return'
+                let value = if matches!(self.peek(), Token::Semicolon) {
+                    None
+                } else {
+                    let expected = self.return_type.clone();
+                    Some(self.parse_value(expected.as_ref())?)
+                };
+                self.consume(Token::Semicolon, "Expected ';' after return")?;
+                Ok(Statement::Return(value))
+            }
+            Token::Import => {
+                self.advance(); // consume 'import'
+                let path = match self.advance() {
+                    Token::Str(path) => path.clone(),
                     _ => {
                         return Err(Box::new(ParseError {
-                            message: "Expected variable name".to_string(),
+                            message: "Expected a file name in quotes after 'import'".to_string(),
                         }))
                     }
                 };
-                self.consume(Token::Colon, "Expected ':' after variable name")?;
-                let typ = self.parse_type()?;
-                self.consume(Token::Equal, "Expected '=' after type")?;
-                let value = self.parse_binary()?;
-                self.consume(Token::Semicolon, "Expected ';' after expression")?;
-                Ok(Statement::Let { name, typ, value })
+                self.consume(Token::Semicolon, "Expected ';' after import")?;
+                Ok(Statement::Import(path))
             }
-            Token::Identifier(_) => {
-                if let Some(Token::Equal) = self.tokens.get(self.current + 1) {
-                    let name = match self.advance() {
-                        Token::Identifier(name) => name.clone(),
-                        _ => unreachable!(),
-                    };
-                    self.advance();
-                    let value = self.parse_binary()?;
-                    self.consume(Token::Semicolon, "Expected ';' after assignment")?;
-                    Ok(Statement::Assignment {
-                        target: name,
-                        value,
-                    })
+            Token::If => self.parse_if(),
+            Token::While => self.parse_while(None),
+            // `label: while (...)`
+            Token::Identifier(_) if self.peek_next() == Some(&Token::Colon) => {
+                let label = match self.advance() {
+                    Token::Identifier(name) => name.clone(),
+                    _ => unreachable!(),
+                };
+                self.advance(); // consume ':'
+                if !matches!(self.peek(), Token::While) {
+                    return Err(Box::new(ParseError {
+                        message: format!("Expected a loop after label '{}'", label),
+                    }));
+                }
+                self.parse_while(Some(label))
+            }
+            Token::Break | Token::Continue => {
+                let is_break = matches!(self.advance(), Token::Break);
+                let label = match self.peek() {
+                    Token::Identifier(name) => {
+                        let name = name.clone();
+                        self.advance();
+                        Some(name)
+                    }
+                    _ => None,
+                };
+                if is_break {
+                    self.consume(Token::Semicolon, "Expected ';' after break")?;
+                    Ok(Statement::Break(label))
                 } else {
+                    self.consume(Token::Semicolon, "Expected ';' after continue")?;
+                    Ok(Statement::Continue(label))
+                }
+            }
+            Token::Identifier(_) if self.peek_next() == Some(&Token::Equal) => {
+                let name = match self.advance() {
+                    Token::Identifier(name) => name.clone(),
+                    _ => unreachable!(),
+                };
+                self.advance();
+                let value = self.parse_expression()?;
+                self.consume(Token::Semicolon, "Expected ';' after assignment")?;
+                Ok(Statement::Assignment {
+                    target: name,
+                    value,
+                })
+            }
+            _ => {
+                let expr = self.parse_expression()?;
+                if self.peek() == &Token::Equal {
                     return Err(Box::new(ParseError {
-                        message: "Unexpected expressions used as statement".to_string(),
+                        message: "Cannot assign to a non-lvalue".to_string(),
                     }));
                 }
+                // only the top level can end here, rather than at a `}`
+                if self.is_at_end() {
+                    return Ok(Statement::Result(expr));
+                }
+                self.consume(Token::Semicolon, "Expected ';' after expression")?;
+                Ok(Statement::Expression(expr))
             }
+        }
+    }
+
+    fn parse_function(&mut self) -> Result<Statement, Box<dyn Error>> {
+        self.advance(); // consume 'fn'
+        let name = match self.advance() {
+            Token::Identifier(name) => name.clone(),
             _ => {
                 return Err(Box::new(ParseError {
-                    message: "Expected statement".to_string(),
+                    message: "Expected function name".to_string(),
                 }))
             }
+        };
+        self.consume(Token::LeftParen, "Expected '(' after function name")?;
+
+        let mut params = Vec::new();
+        // where each parameter was named, to point at both of a duplicate pair
+        let mut declared: Vec<Span> = Vec::new();
+        if !matches!(self.peek(), Token::RightParen) {
+            loop {
+                let span = self.peek_span();
+                let param = match self.advance() {
+                    Token::Identifier(name) => name.clone(),
+                    _ => {
+                        return Err(Box::new(ParseError {
+                            message: "Expected parameter name".to_string(),
+                        }))
+                    }
+                };
+                if let Some(index) = params.iter().position(|(name, _)| *name == param) {
+                    return Err(Box::new(ParseError {
+                        message: format!(
+                            "duplicate parameter `{}` in `{}` at {}, first declared at {}",
+                            param, name, span, declared[index]
+                        ),
+                    }));
+                }
+                self.consume(Token::Colon, "Expected ':' after parameter name")?;
+                params.push((param, self.parse_type()?));
+                declared.push(span);
+                if !matches!(self.peek(), Token::Comma) {
+                    break;
+                }
+                self.advance(); // consume ','
+            }
+        }
+        self.consume(Token::RightParen, "Expected ')' after parameters")?;
+        // left out, the checker infers it from the body's returns
+        let return_type = if matches!(self.peek(), Token::Arrow) {
+            self.advance(); // consume '->'
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        let outer = std::mem::replace(&mut self.return_type, return_type.clone());
+        let body = self.parse_block();
+        self.return_type = outer;
+        let body = body?;
+
+        Ok(Statement::Function {
+            name,
+            params,
+            return_type,
+            body,
+        })
+    }
+
+    fn parse_if(&mut self) -> Result<Statement, Box<dyn Error>> {
+        self.advance(); // consume 'if'
+        self.consume(Token::LeftParen, "Expected '(' after 'if'")?;
+        let condition = self.parse_expression()?;
+        self.consume(Token::RightParen, "Expected ')' after if condition")?;
+        let then_branch = self.parse_block()?;
+
+        let else_branch = if matches!(self.peek(), Token::Else) {
+            self.advance(); // consume 'else'
+            if matches!(self.peek(), Token::If) {
+                // `else if` is an else branch holding a single nested if
+                let start = self.peek_span();
+                let nested = self.parse_if()?;
+                Some(vec![self.spanned(nested, start)])
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+
+        Ok(Statement::If {
+            condition,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    fn parse_while(&mut self, label: Option<String>) -> Result<Statement, Box<dyn Error>> {
+        self.advance(); // consume 'while'
+        self.consume(Token::LeftParen, "Expected '(' after 'while'")?;
+        let condition = self.parse_expression()?;
+        self.consume(Token::RightParen, "Expected ')' after while condition")?;
+        let body = self.parse_block()?;
+        Ok(Statement::While {
+            label,
+            condition,
+            body,
+        })
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Spanned<Statement>>, Box<dyn Error>> {
+        self.consume(Token::LeftBrace, "Expected '{' before block")?;
+        self.parse_until(Token::RightBrace)
+    }
+
+    fn parse_expression(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        self.parse_logical_or()
+    }
+
+    fn parse_logical_or(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        let mut expr = self.parse_logical_and()?;
+
+        while matches!(self.peek(), Token::Or) {
+            self.advance();
+            let right = self.parse_logical_and()?;
+            let span = expr.span.to(right.span);
+            expr = Spanned::new(
+                Expr::Binary {
+                    op: BinaryOp::Or,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_logical_and(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        let mut expr = self.parse_comparison()?;
+
+        while matches!(self.peek(), Token::And) {
+            self.advance();
+            let right = self.parse_comparison()?;
+            let span = expr.span.to(right.span);
+            expr = Spanned::new(
+                Expr::Binary {
+                    op: BinaryOp::And,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        let mut expr = self.parse_additive()?;
+
+        while matches!(
+            self.peek(),
+            Token::EqualEqual
+                | Token::NotEqual
+                | Token::Less
+                | Token::LessEqual
+                | Token::Greater
+                | Token::GreaterEqual
+        ) {
+            let op = match self.advance() {
+                Token::EqualEqual => BinaryOp::Equal,
+                Token::NotEqual => BinaryOp::NotEqual,
+                Token::Less => BinaryOp::Less,
+                Token::LessEqual => BinaryOp::LessEqual,
+                Token::Greater => BinaryOp::Greater,
+                Token::GreaterEqual => BinaryOp::GreaterEqual,
+                _ => unreachable!(),
+            };
+            let right = self.parse_additive()?;
+            let span = expr.span.to(right.span);
+            expr = Spanned::new(
+                Expr::Binary {
+                    op,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
         }
+        Ok(expr)
     }
 
-    fn parse_binary(&mut self) -> Result<Expr, Box<dyn Error>> {
-        let mut expr = self.parse_primary()?;
+    fn parse_additive(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        let mut expr = self.parse_term()?;
 
         while matches!(
             self.peek(),
-            Token::Plus | Token::Minus | Token::Star | Token::Slash
+            Token::Plus | Token::PlusPercent | Token::PlusPipe | Token::Minus
         ) {
             let op = match self.advance() {
                 Token::Plus => BinaryOp::Add,
+                Token::PlusPercent => BinaryOp::WrappingAdd,
+                Token::PlusPipe => BinaryOp::SaturatingAdd,
                 Token::Minus => BinaryOp::Subtract,
+                _ => unreachable!(),
+            };
+            let right = self.parse_term()?;
+            let span = expr.span.to(right.span);
+            expr = Spanned::new(
+                Expr::Binary {
+                    op,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_term(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        let mut expr = self.parse_unary()?;
+
+        while matches!(self.peek(), Token::Star | Token::Slash | Token::Percent) {
+            let op = match self.advance() {
                 Token::Star => BinaryOp::Multiply,
                 Token::Slash => BinaryOp::Divide,
+                Token::Percent => BinaryOp::Modulo,
                 _ => unreachable!(),
             };
-            let right = self.parse_primary()?;
-            expr = Expr::Binary {
-                op,
-                left: Box::new(expr),
-                right: Box::new(right),
-            };
+            let right = self.parse_unary()?;
+            let span = expr.span.to(right.span);
+            expr = Spanned::new(
+                Expr::Binary {
+                    op,
+                    left: Box::new(expr),
+                    right: Box::new(right),
+                },
+                span,
+            );
         }
         Ok(expr)
     }
 
-    fn parse_primary(&mut self) -> Result<Expr, Box<dyn Error>> {
-        let token = self.advance();
-        match token {
-            Token::Integer(value) => Ok(Expr::Integer(*value)),
-            Token::Identifier(name) => Ok(Expr::Variable(name.clone())),
-            _ => Err(Box::new(ParseError {
-                message: "Expected expression".to_string(),
-            })),
+    fn parse_unary(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        let start = self.peek_span();
+        let op = match self.peek() {
+            Token::Minus => UnaryOp::Negate,
+            Token::Bang => UnaryOp::Not,
+            _ => return self.parse_primary(),
+        };
+        self.advance();
+        let operand = self.parse_unary()?;
+        let expr = Expr::Unary {
+            op,
+            operand: Box::new(operand),
+        };
+        Ok(self.spanned(expr, start))
+    }
+
+    // One expression of a parenthesized sequence, which may be an assignment
+    fn parse_sequenced(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        let start = self.peek_span();
+        let Token::Identifier(target) = self.peek().clone() else {
+            return self.parse_expression();
+        };
+        if self.peek_next() != Some(&Token::Equal) {
+            return self.parse_expression();
         }
+        self.advance(); // consume the target
+        self.advance(); // consume '='
+        let value = self.parse_expression()?;
+        let expr = Expr::Assign {
+            target,
+            value: Box::new(value),
+        };
+        Ok(self.spanned(expr, start))
     }
-}
 
-pub fn parse(tokens: Vec<Token>) -> Result<Vec<Statement>, Box<dyn Error>> {
-    let mut parser = Parser::new(tokens);
-    let mut functions = Vec::new();
+    // Parses a comma-separated argument list after its opening '('
+    fn parse_arguments(&mut self) -> Result<Vec<Spanned<Expr>>, Box<dyn Error>> {
+        let mut args = Vec::new();
+        if !matches!(self.peek(), Token::RightParen) {
+            loop {
+                args.push(self.parse_argument()?);
+                if !matches!(self.peek(), Token::Comma) {
+                    break;
+                }
+                self.advance(); // consume ','
+            }
+        }
+        self.consume(Token::RightParen, "Expected ')' after arguments")?;
+        Ok(args)
+    }
 
-    while !parser.is_at_end() {
-        functions.push(parser.parse_statement()?);
+    // One argument of a call, which may name the parameter it's passed to
+    fn parse_argument(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        let start = self.peek_span();
+        let Token::Identifier(name) = self.peek().clone() else {
+            return self.parse_expression();
+        };
+        if self.peek_next() != Some(&Token::Colon) {
+            return self.parse_expression();
+        }
+        self.advance(); // consume the name
+        self.advance(); // consume ':'
+        let value = self.parse_expression()?;
+        let expr = Expr::Named {
+            name,
+            value: Box::new(value),
+        };
+        Ok(self.spanned(expr, start))
+    }
+
+    fn parse_primary(&mut self) -> Result<Spanned<Expr>, Box<dyn Error>> {
+        let start = self.peek_span();
+        let token = self.advance().clone();
+        let expr = match token {
+            Token::Integer(value) => Expr::Integer(value, Radix::Decimal),
+            Token::HexInteger(value) => Expr::Integer(value, Radix::Hex),
+            Token::True => Expr::Boolean(true),
+            Token::False => Expr::Boolean(false),
+            Token::Identifier(name) if matches!(self.peek(), Token::LeftParen) => {
+                let mut expr = self.spanned(Expr::Variable(name), start);
+                // each trailing `(...)` calls the result of the previous call
+                while matches!(self.peek(), Token::LeftParen) {
+                    self.advance(); // consume '('
+                    let args = self.parse_arguments()?;
+                    let call = Expr::Call {
+                        callee: Box::new(expr),
+                        args,
+                    };
+                    expr = self.spanned(call, start);
+                }
+                return Ok(expr);
+            }
+            Token::Identifier(name) => Expr::Variable(name),
+            // commas in parentheses that aren't a call's sequence expressions,
+            // looser than any operator
+            Token::LeftParen => {
+                let mut exprs = vec![self.parse_sequenced()?];
+                while matches!(self.peek(), Token::Comma) {
+                    self.advance(); // consume ','
+                    exprs.push(self.parse_sequenced()?);
+                }
+                self.consume(Token::RightParen, "Expected ')' to close unmatched '('")?;
+                if exprs.len() == 1 {
+                    exprs.remove(0).node
+                } else {
+                    Expr::Seq(exprs)
+                }
+            }
+            _ => {
+                return Err(Box::new(ParseError {
+                    message: "Expected expression".to_string(),
+                }))
+            }
+        };
+        Ok(self.spanned(expr, start))
     }
+}
 
-    Ok(functions)
+pub fn parse(tokens: Vec<Spanned<Token>>) -> Result<Vec<Spanned<Statement>>, Box<dyn Error>> {
+    let mut parser = Parser::new(tokens);
+    parser.parse_until(Token::Eof)
 }
 
 #[cfg(test)]
@@ -160,6 +684,28 @@ mod tests {
     use super::*;
     use crate::lexer;
 
+    // Expr's Display is fully parenthesized, so tests can check tree shape
+    fn render(expr: &Expr) -> String {
+        expr.to_string()
+    }
+
+    fn initializer(input: &str) -> Spanned<Expr> {
+        let tokens = lexer::lex(&format!("let v: int = {};", input)).unwrap();
+        match parse(tokens).unwrap().remove(0).node {
+            Statement::Let { value, .. } => value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_value(input: &str) -> String {
+        render(&initializer(input))
+    }
+
+    // Drops the spans of a block so slice patterns can match its statements
+    fn nodes(statements: &[Spanned<Statement>]) -> Vec<&Statement> {
+        statements.iter().map(|stmt| &stmt.node).collect()
+    }
+
     #[test]
     fn test_parse_simple_function() {
         let input = "let x: int = 3; let y: int = 2; let z: int = x + y;";
@@ -168,4 +714,473 @@ mod tests {
         assert_eq!(stmts.len(), 3);
         // TODO
     }
+
+    #[test]
+    fn test_parse_empty_input() {
+        assert!(parse(lexer::lex("").unwrap()).unwrap().is_empty());
+        assert!(parse(lexer::lex("  // nothing here\n").unwrap())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        assert_eq!(parse_value("1 + 2 * 3"), "(1 + (2 * 3))");
+        assert_eq!(parse_value("6 / 2 / 3"), "((6 / 2) / 3)");
+        assert_eq!(parse_value("a * b + c * d"), "((a * b) + (c * d))");
+    }
+
+    #[test]
+    fn test_parse_modulo_precedence() {
+        assert_eq!(parse_value("1 + 7 % 3"), "(1 + (7 % 3))");
+        assert_eq!(parse_value("a * b % c"), "((a * b) % c)");
+    }
+
+    #[test]
+    fn test_parse_comparison() {
+        assert_eq!(parse_value("x < y"), "(x < y)");
+        assert_eq!(parse_value("a + b < c"), "((a + b) < c)");
+        assert_eq!(parse_value("a * 2 >= b - 1"), "((a * 2) >= (b - 1))");
+        assert_eq!(parse_value("a == b != c"), "((a == b) != c)");
+    }
+
+    #[test]
+    fn test_parse_logical() {
+        assert_eq!(
+            parse_value("a < b && c > d || e"),
+            "(((a < b) && (c > d)) || e)"
+        );
+        assert_eq!(parse_value("a || b && c"), "(a || (b && c))");
+        assert_eq!(parse_value("a && b && c"), "((a && b) && c)");
+        assert_eq!(parse_value("a || b || c"), "((a || b) || c)");
+    }
+
+    #[test]
+    fn test_parse_unary() {
+        assert_eq!(parse_value("-5"), "(-5)");
+        assert_eq!(parse_value("-(a + b)"), "(-(a + b))");
+        assert_eq!(parse_value("--x"), "(-(-x))");
+        assert_eq!(parse_value("-a * b"), "((-a) * b)");
+        assert_eq!(parse_value("a - -b"), "(a - (-b))");
+        assert_eq!(parse_value("!done && ready"), "((!done) && ready)");
+        assert_eq!(parse_value("!!x"), "(!(!x))");
+    }
+
+    #[test]
+    fn test_parse_boolean_literals() {
+        let tokens = lexer::lex("let t: int = true; let f: int = false;").unwrap();
+        let stmts = parse(tokens).unwrap();
+        assert!(matches!(
+            &stmts[0].node,
+            Statement::Let { value, .. } if **value == Expr::Boolean(true)
+        ));
+        assert!(matches!(
+            &stmts[1].node,
+            Statement::Let { value, .. } if **value == Expr::Boolean(false)
+        ));
+        assert_eq!(parse_value("true && false"), "(true && false)");
+    }
+
+    #[test]
+    fn test_parse_call() {
+        let value = initializer("f(1, g(2), x + 3)");
+        match &value.node {
+            Expr::Call { callee, args } => {
+                assert_eq!(render(callee), "f");
+                assert_eq!(args.len(), 3);
+                assert!(matches!(
+                    &args[1].node,
+                    Expr::Call { callee, args } if render(callee) == "g" && args.len() == 1
+                ));
+            }
+            _ => panic!("expected a call, got {:?}", value),
+        }
+        assert_eq!(parse_value("f() * 2"), "(f() * 2)");
+    }
+
+    #[test]
+    fn test_parse_named_arguments() {
+        let value = initializer("add(b: 2, a: x + 1)");
+        let Expr::Call { args, .. } = &value.node else {
+            panic!("expected a call, got {:?}", value);
+        };
+        assert!(matches!(
+            &args[..],
+            [first, second]
+                if matches!(&first.node, Expr::Named { name, .. } if name == "b")
+                    && matches!(&second.node, Expr::Named { name, .. } if name == "a")
+        ));
+        assert_eq!(render(&value), "add(b: 2, a: (x + 1))");
+        // mixing them is left for the checker to reject
+        assert_eq!(parse_value("add(1, b: 2)"), "add(1, b: 2)");
+    }
+
+    #[test]
+    fn test_parse_chained_call() {
+        let value = initializer("f(x)(y)");
+        match &value.node {
+            Expr::Call { callee, args } => {
+                assert!(matches!(&args[..], [arg] if **arg == Expr::Variable("y".to_string())));
+                assert!(matches!(
+                    &callee.node,
+                    Expr::Call { callee, args }
+                        if callee.node == Expr::Variable("f".to_string())
+                            && matches!(&args[..], [arg] if **arg == Expr::Variable("x".to_string()))
+                ));
+            }
+            _ => panic!("expected a call, got {:?}", value),
+        }
+        assert_eq!(parse_value("f(x)(y)(z) + 1"), "(f(x)(y)(z) + 1)");
+    }
+
+    #[test]
+    fn test_parse_spans() {
+        let tokens = lexer::lex("let a: int = 1;\nif (a < 2) { a = a * 3; }").unwrap();
+        let stmts = parse(tokens).unwrap();
+        assert_eq!((stmts[1].span.line, stmts[1].span.column), (2, 1));
+        assert_eq!((stmts[1].span.start, stmts[1].span.end), (16, 41));
+        match &stmts[1].node {
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                assert_eq!((condition.span.start, condition.span.end), (20, 25));
+                assert_eq!(then_branch[0].span.to_string(), "line 2, column 14");
+            }
+            _ => panic!("expected an if statement, got {:?}", stmts),
+        }
+
+        // a parenthesized expression covers its parentheses, a call its arguments
+        let value = initializer("(1 + 2) * f(x)");
+        let Expr::Binary { left, right, .. } = &value.node else {
+            panic!("expected a binary expression, got {:?}", value);
+        };
+        assert_eq!((left.span.start, left.span.end), (13, 20));
+        assert_eq!((right.span.start, right.span.end), (23, 27));
+        assert_eq!((value.span.start, value.span.end), (13, 27));
+    }
+
+    #[test]
+    fn test_parse_parenthesized() {
+        assert_eq!(parse_value("(1 + 2) * 3"), "((1 + 2) * 3)");
+        assert_eq!(parse_value("((1))"), "1");
+        assert_eq!(parse_value("a * (b + (c - d))"), "(a * (b + (c - d)))");
+    }
+
+    #[test]
+    fn test_parse_sequence() {
+        let value = initializer("(a = 1, a + 1)");
+        match &value.node {
+            Expr::Seq(exprs) => {
+                assert!(matches!(
+                    &exprs[..],
+                    [first, second]
+                        if matches!(&first.node, Expr::Assign { target, .. } if target == "a")
+                            && matches!(second.node, Expr::Binary { .. })
+                ));
+            }
+            _ => panic!("expected a sequence, got {:?}", value),
+        }
+        assert_eq!(parse_value("(a = 1, a + 1)"), "((a = 1), (a + 1))");
+        // looser than any operator, and not inside a call's parentheses
+        assert_eq!(parse_value("(a || b, c) * 2"), "(((a || b), c) * 2)");
+        assert_eq!(parse_value("f(a, b)"), "f(a, b)");
+    }
+
+    #[test]
+    fn test_parse_unmatched_paren() {
+        let tokens = lexer::lex("let x: int = (1 + 2;").unwrap();
+        let err = parse(tokens).unwrap_err();
+        assert!(err.to_string().contains("unmatched '('"));
+    }
+
+    #[test]
+    fn test_parse_line_continuation() {
+        let tokens = lexer::lex("let x: int = 1 + \\\n    2;").unwrap();
+        let stmts = parse(tokens).unwrap();
+        assert_eq!(stmts.len(), 1);
+        assert!(matches!(
+            &stmts[0].node,
+            Statement::Let { value, .. } if matches!(value.node, Expr::Binary { op: BinaryOp::Add, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_assignment_to_non_lvalue() {
+        for input in ["5 = x;", "x + 1 = 2;", "(a < b) = 1;"] {
+            let tokens = lexer::lex(input).unwrap();
+            let err = parse(tokens).unwrap_err();
+            assert!(err.to_string().contains("Cannot assign to a non-lvalue"));
+        }
+    }
+
+    #[test]
+    fn test_parse_until_terminator() {
+        let tokens = lexer::lex("let x: int = 1; x = 2; } let y: int = 3;").unwrap();
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse_until(Token::RightBrace).unwrap();
+        assert_eq!(stmts.len(), 2);
+        assert!(matches!(parser.peek(), Token::Let));
+
+        let tokens = lexer::lex("let x: int = 1;").unwrap();
+        let err = Parser::new(tokens)
+            .parse_until(Token::RightBrace)
+            .unwrap_err();
+        assert!(err.to_string().contains("Expected '}' before end of input"));
+    }
+
+    #[test]
+    fn test_parse_types() {
+        let tokens = lexer::lex("let a: int = 1; let ok: bool = true; let v: void = 0;").unwrap();
+        let stmts = parse(tokens).unwrap();
+        let types: Vec<&Type> = stmts
+            .iter()
+            .map(|stmt| match &stmt.node {
+                Statement::Let { typ, .. } => typ,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert!(matches!(types[..], [Type::Int, Type::Bool, Type::Void]));
+    }
+
+    #[test]
+    fn test_parse_unexpected_type() {
+        let tokens = lexer::lex("let a: 5 = 1;").unwrap();
+        let err = parse(tokens).unwrap_err();
+        assert!(err.to_string().contains("Expected type"));
+    }
+
+    #[test]
+    fn test_parse_if_without_else() {
+        let tokens = lexer::lex("if (x < 1) { x = 1; let y: int = 2; }").unwrap();
+        let stmts = parse(tokens).unwrap();
+        match &nodes(&stmts)[..] {
+            [Statement::If {
+                condition,
+                then_branch,
+                else_branch: None,
+            }] => {
+                assert_eq!(render(condition), "(x < 1)");
+                assert_eq!(then_branch.len(), 2);
+            }
+            _ => panic!("expected an if statement, got {:?}", stmts),
+        }
+    }
+
+    #[test]
+    fn test_parse_if_else() {
+        let tokens =
+            lexer::lex("if (a) { x = 1; } else if (b) { if (c) { x = 2; } } else { x = 3; }")
+                .unwrap();
+        let stmts = parse(tokens).unwrap();
+        match &nodes(&stmts)[..] {
+            [Statement::If {
+                else_branch: Some(else_branch),
+                ..
+            }] => match &nodes(else_branch)[..] {
+                [Statement::If {
+                    then_branch,
+                    else_branch: Some(last),
+                    ..
+                }] => {
+                    assert!(matches!(nodes(then_branch)[..], [Statement::If { .. }]));
+                    assert_eq!(last.len(), 1);
+                }
+                _ => panic!("expected else if, got {:?}", else_branch),
+            },
+            _ => panic!("expected an if statement, got {:?}", stmts),
+        }
+    }
+
+    #[test]
+    fn test_parse_while() {
+        let tokens = lexer::lex("while (i < 10) { i = i + 1; }").unwrap();
+        let stmts = parse(tokens).unwrap();
+        match &nodes(&stmts)[..] {
+            [Statement::While {
+                label: None,
+                condition,
+                body,
+            }] => {
+                assert_eq!(render(condition), "(i < 10)");
+                assert!(matches!(
+                    &nodes(body)[..],
+                    [Statement::Assignment { target, value }]
+                        if target == "i" && render(value) == "(i + 1)"
+                ));
+            }
+            _ => panic!("expected a while statement, got {:?}", stmts),
+        }
+    }
+
+    #[test]
+    fn test_parse_labeled_break() {
+        let tokens =
+            lexer::lex("outer: while (true) { while (true) { break outer; } continue; }").unwrap();
+        let stmts = parse(tokens).unwrap();
+        match &nodes(&stmts)[..] {
+            [Statement::While {
+                label: Some(label),
+                body,
+                ..
+            }] => {
+                assert_eq!(label, "outer");
+                assert!(matches!(
+                    &nodes(body)[..],
+                    [Statement::While { label: None, body: inner, .. }, Statement::Continue(None)]
+                        if matches!(&nodes(inner)[..], [Statement::Break(Some(target))] if target == "outer")
+                ));
+            }
+            _ => panic!("expected a labeled while statement, got {:?}", stmts),
+        }
+    }
+
+    #[test]
+    fn test_parse_while_empty_body() {
+        let tokens = lexer::lex("while (false) {}").unwrap();
+        let stmts = parse(tokens).unwrap();
+        assert!(matches!(&nodes(&stmts)[..], [Statement::While { body, .. }] if body.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_function_without_params() {
+        let tokens = lexer::lex("fn answer() -> int { let x: int = 42; }").unwrap();
+        let stmts = parse(tokens).unwrap();
+        assert!(matches!(
+            &nodes(&stmts)[..],
+            [Statement::Function { name, params, return_type: Some(Type::Int), body }]
+                if name == "answer" && params.is_empty() && body.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_parse_function_with_params() {
+        let tokens = lexer::lex("fn pick(a: int, flag: bool, b: int) -> void { a = b; }").unwrap();
+        let stmts = parse(tokens).unwrap();
+        match &nodes(&stmts)[..] {
+            [Statement::Function {
+                params,
+                return_type: Some(Type::Void),
+                ..
+            }] => {
+                let names: Vec<&str> = params.iter().map(|(name, _)| name.as_str()).collect();
+                assert_eq!(names, ["a", "flag", "b"]);
+                assert!(matches!(params[1].1, Type::Bool));
+            }
+            _ => panic!("expected a function, got {:?}", stmts),
+        }
+    }
+
+    #[test]
+    fn test_parse_duplicate_params() {
+        let tokens = lexer::lex("fn f(a: int,\n     a: int) -> int { return a; }").unwrap();
+        let err = parse(tokens).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse error: duplicate parameter `a` in `f` at line 2, column 6, \
+             first declared at line 1, column 6"
+        );
+    }
+
+    #[test]
+    fn test_parse_return_value() {
+        let tokens = lexer::lex("fn inc(x: int) -> int { return x + 1; }").unwrap();
+        let stmts = parse(tokens).unwrap();
+        match &nodes(&stmts)[..] {
+            [Statement::Function { body, .. }] => match &nodes(body)[..] {
+                [Statement::Return(Some(value))] => assert_eq!(render(value), "(x + 1)"),
+                _ => panic!("expected a return with a value, got {:?}", body),
+            },
+            _ => panic!("expected a function, got {:?}", stmts),
+        }
+    }
+
+    #[test]
+    fn test_parse_tuples() {
+        let tokens = lexer::lex(
+            "fn divmod(a: int, b: int) -> (int, int) { return (a / b, a % b); }
+             let (q, r): (int, int) = divmod(7, 2);",
+        )
+        .unwrap();
+        let stmts = parse(tokens).unwrap();
+        let pair = Type::Tuple(vec![Type::Int, Type::Int]);
+        match &nodes(&stmts)[..] {
+            [Statement::Function {
+                return_type, body, ..
+            }, Statement::Destructure { names, typ, value }] => {
+                assert_eq!(return_type.as_ref(), Some(&pair));
+                assert!(matches!(
+                    &nodes(body)[..],
+                    [Statement::Return(Some(value))]
+                        if matches!(&value.node, Expr::Tuple(elements) if elements.len() == 2)
+                ));
+                assert_eq!(
+                    (&names[..], typ),
+                    (&["q".to_string(), "r".to_string()][..], &pair)
+                );
+                assert_eq!(render(value), "divmod(7, 2)");
+            }
+            _ => panic!(
+                "expected a function and a destructuring let, got {:?}",
+                stmts
+            ),
+        }
+
+        // where no tuple is expected the same parentheses are a sequence
+        assert!(matches!(initializer("(1, 2)").node, Expr::Seq(_)));
+        let tokens = lexer::lex("let x: (int) = 1;").unwrap();
+        let err = parse(tokens).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("A tuple type needs at least two elements"));
+    }
+
+    #[test]
+    fn test_parse_bare_return() {
+        // accepted syntactically even though the function returns int
+        let tokens = lexer::lex("fn f() -> int { return; }").unwrap();
+        let stmts = parse(tokens).unwrap();
+        assert!(matches!(
+            &nodes(&stmts)[..],
+            [Statement::Function { body, .. }] if matches!(nodes(body)[..], [Statement::Return(None)])
+        ));
+    }
+
+    #[test]
+    fn test_parse_import() {
+        let tokens = lexer::lex(r#"import "lib.crucible"; let x: int = 1;"#).unwrap();
+        let stmts = parse(tokens).unwrap();
+        assert!(matches!(&stmts[0].node, Statement::Import(path) if path == "lib.crucible"));
+
+        let tokens = lexer::lex("import lib;").unwrap();
+        assert!(parse(tokens).is_err());
+    }
+
+    #[test]
+    fn test_parse_type_keyword_as_variable_name() {
+        let tokens = lexer::lex("let int: int = 1;").unwrap();
+        let err = parse(tokens).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("type keyword 'int' cannot be used as a variable name"));
+    }
+
+    #[test]
+    fn test_parse_trailing_result() {
+        let tokens = lexer::lex("let x: int = 5;\nx + 1").unwrap();
+        let stmts = parse(tokens).unwrap();
+        match &nodes(&stmts)[..] {
+            [Statement::Let { .. }, Statement::Result(value)] => {
+                assert_eq!(render(value), "(x + 1)")
+            }
+            _ => panic!("expected a let and a result, got {:?}", stmts),
+        }
+
+        // only the top level ends without a `;`
+        let tokens = lexer::lex("if (true) { 1 }").unwrap();
+        assert!(parse(tokens).is_err());
+        let tokens = lexer::lex("x + 1 let y: int = 2;").unwrap();
+        assert!(parse(tokens).is_err());
+    }
 }
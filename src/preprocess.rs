@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+pub struct PreprocessError {
+    message: String,
+    line: usize,
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Preprocess error at line {}: {}",
+            self.line, self.message
+        )
+    }
+}
+
+impl Error for PreprocessError {}
+
+// Evaluates `#if FLAG` ... `#endif` guards, which may nest, against the
+// defined flags. Each directive sits on a line of its own. Directives and
+// excluded lines are blanked out rather than removed, so every character the
+// lexer sees keeps its offset and spans still point into the original source.
+pub fn preprocess(source: &str, flags: &HashSet<String>) -> Result<String, PreprocessError> {
+    let mut out = String::with_capacity(source.len());
+    // for each open guard, outermost first: whether its flag is defined and
+    // the line it opened on
+    let mut guards: Vec<(bool, usize)> = Vec::new();
+
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        let number = index + 1;
+        let error = |message: String| {
+            Err(PreprocessError {
+                message,
+                line: number,
+            })
+        };
+        let directive = line.trim();
+        let included = guards.iter().all(|&(defined, _)| defined);
+        if let Some(rest) = directive.strip_prefix('#') {
+            match rest.split_whitespace().collect::<Vec<_>>()[..] {
+                ["if", flag] => {
+                    guards.push((flags.contains(flag), number));
+                }
+                ["endif"] => {
+                    if guards.pop().is_none() {
+                        return error("`#endif` without a matching `#if`".to_string());
+                    }
+                }
+                ["if"] => return error("`#if` needs a flag name".to_string()),
+                _ => return error(format!("Unknown directive `{}`", directive)),
+            }
+        } else if included {
+            out.push_str(line);
+            continue;
+        }
+        out.extend(line.chars().map(|ch| if ch == '\n' { ch } else { ' ' }));
+    }
+
+    if let Some(&(_, line)) = guards.last() {
+        return Err(PreprocessError {
+            message: "`#if` is never closed with `#endif`".to_string(),
+            line,
+        });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_preprocess_guards() {
+        let source = "a\n#if X\nb\n#if Y\nc\n#endif\n#endif\nd";
+        assert_eq!(
+            preprocess(source, &flags(&["X"])).unwrap(),
+            "a\n     \nb\n     \n \n      \n      \nd"
+        );
+        let output = preprocess(source, &flags(&[])).unwrap();
+        assert_eq!(output.split_whitespace().collect::<Vec<_>>(), ["a", "d"]);
+        // blanking keeps every offset where it was
+        assert_eq!(output.chars().count(), source.chars().count());
+    }
+
+    #[test]
+    fn test_preprocess_errors() {
+        let err = preprocess("let x: int = 1;\n#endif", &flags(&[])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Preprocess error at line 2: `#endif` without a matching `#if`"
+        );
+        let err = preprocess("#if X\nlet x: int = 1;", &flags(&[])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Preprocess error at line 1: `#if` is never closed with `#endif`"
+        );
+        let err = preprocess("#else", &flags(&[])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Preprocess error at line 1: Unknown directive `#else`"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+
+// Half-open range of character offsets into the source, along with the
+// 1-based line and column it starts at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    // Assumes the span is on the first line; the lexer fixes up line and
+    // column once it has seen the whole input
+    pub fn new(start: usize, end: usize) -> Self {
+        Span {
+            start,
+            end,
+            line: 1,
+            column: start + 1,
+        }
+    }
+
+    // From the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+// Spans are left out of comparisons and hashing, so the same code laid out
+// differently still compares equal
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T: Eq> Eq for Spanned<T> {}
+
+impl<T: Hash> Hash for Spanned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.node.hash(state);
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T> DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Expr, Radix};
+
+    #[test]
+    fn test_spanned_deref() {
+        let expr = Spanned::new(Expr::Integer(3, Radix::Decimal), Span::new(4, 5));
+        assert!(matches!(*expr, Expr::Integer(3, Radix::Decimal)));
+        assert_eq!(expr.span, Span::new(4, 5));
+        assert_eq!(
+            expr,
+            Spanned::new(Expr::Integer(3, Radix::Decimal), Span::new(0, 1))
+        );
+    }
+}
@@ -0,0 +1,919 @@
+use crate::arith::Builtin;
+use crate::ast::{positional, BinaryOp, Expr, Statement, Type, UnaryOp};
+use crate::span::Spanned;
+use std::collections::HashMap;
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+pub struct TypeError {
+    message: String,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Type error: {}", self.message)
+    }
+}
+
+impl Error for TypeError {}
+
+impl TypeError {
+    pub(crate) fn new(message: String) -> Self {
+        TypeError { message }
+    }
+
+    pub(crate) fn mismatched_initializer(
+        name: &str,
+        expected: Type,
+        found: Type,
+        value: &Spanned<Expr>,
+    ) -> Self {
+        TypeError::new(format!(
+            "Mismatched types in declaration of `{}`: expected {}, found {} in initializer `{}` at {}",
+            name, expected, found, value, value.span
+        ))
+    }
+}
+
+// The special form that reports an expression's type
+pub const TYPEOF: &str = "typeof";
+
+fn error<T>(message: String) -> Result<T, TypeError> {
+    Err(TypeError::new(message))
+}
+
+#[derive(Debug)]
+pub struct CheckOptions {
+    // When false, a `let` or `const` may not reuse any visible name, including
+    // one from an enclosing scope. On by default, since a redeclaration just
+    // introduces a new variable.
+    pub allow_shadowing: bool,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        CheckOptions {
+            allow_shadowing: true,
+        }
+    }
+}
+
+struct Signature {
+    params: Vec<Type>,
+    // what the arguments can be named; builtins' can't be
+    names: Vec<String>,
+    // None until the body of a function declared without one is checked
+    return_type: Option<Type>,
+}
+
+// What the `return`s of the function being checked must give
+enum Returns {
+    Declared(Type),
+    // the return type was left out, so the first `return` decides it for
+    // the rest
+    Inferred {
+        function: String,
+        found: Option<Type>,
+    },
+}
+
+struct Variable {
+    typ: Type,
+    // declared with `const`, so it can never be assigned
+    constant: bool,
+}
+
+// Functions and variables live in separate namespaces: a name is only ever
+// looked up in `functions` when it is called and in `scopes` otherwise, so a
+// variable may share its name with a function without either hiding the other
+struct Checker {
+    // innermost scope last; a block's declarations go out of scope with it
+    scopes: Vec<HashMap<String, Variable>>,
+    functions: HashMap<String, Signature>,
+    // the returns of the function being checked, None at the top level
+    return_type: Option<Returns>,
+    // labels of the enclosing loops, innermost last
+    loops: Vec<Option<String>>,
+    allow_shadowing: bool,
+    // each `typeof(expr)` checked so far, with the type it resolved to
+    queries: Vec<(String, Type)>,
+    warnings: Vec<String>,
+}
+
+// Whether control can run off the end of `statements`, rather than leaving
+// through a `return`, `break` or `continue` on every path
+fn falls_through(statements: &[Spanned<Statement>]) -> bool {
+    statements.iter().all(|stmt| match &stmt.node {
+        Statement::Return(_) | Statement::Break(_) | Statement::Continue(_) => false,
+        Statement::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => falls_through(then_branch) || falls_through(else_branch),
+        _ => true,
+    })
+}
+
+// Returns warnings about statements that can never run
+pub fn check(statements: &[Spanned<Statement>]) -> Result<Vec<String>, TypeError> {
+    check_with_options(statements, &CheckOptions::default())
+}
+
+pub fn check_with_options(
+    statements: &[Spanned<Statement>],
+    options: &CheckOptions,
+) -> Result<Vec<String>, TypeError> {
+    let mut checker = Checker::new(options);
+    checker.block(statements)?;
+    Ok(checker.warnings)
+}
+
+// Checks `statements` and returns the type of each `typeof(expr)` in them in
+// the order they were checked, along with the queried expression
+pub fn type_queries(statements: &[Spanned<Statement>]) -> Result<Vec<(String, Type)>, TypeError> {
+    let mut checker = Checker::new(&CheckOptions::default());
+    checker.block(statements)?;
+    Ok(checker.queries)
+}
+
+impl Checker {
+    fn new(options: &CheckOptions) -> Self {
+        let builtins = Builtin::ALL.map(|builtin| {
+            let signature = Signature {
+                params: vec![Type::Int; builtin.arity()],
+                names: Vec::new(),
+                return_type: Some(Type::Int),
+            };
+            (builtin.name().to_string(), signature)
+        });
+        Checker {
+            scopes: Vec::new(),
+            functions: HashMap::from(builtins),
+            return_type: None,
+            loops: Vec::new(),
+            allow_shadowing: options.allow_shadowing,
+            queries: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn declare(&mut self, name: &str, typ: Type, constant: bool) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), Variable { typ, constant });
+    }
+
+    // Checks statements in a new scope. Functions are registered up front so
+    // they can be called before their declaration, or recursively. Only the
+    // first statement control can't reach is warned about.
+    fn block(&mut self, statements: &[Spanned<Statement>]) -> Result<(), TypeError> {
+        for stmt in statements {
+            if let Statement::Function {
+                name,
+                params,
+                return_type,
+                ..
+            } = &stmt.node
+            {
+                let signature = Signature {
+                    params: params.iter().map(|(_, typ)| typ.clone()).collect(),
+                    names: params.iter().map(|(name, _)| name.clone()).collect(),
+                    return_type: return_type.clone(),
+                };
+                self.functions.insert(name.clone(), signature);
+            }
+        }
+
+        self.scopes.push(HashMap::new());
+        let result = statements.iter().try_for_each(|stmt| self.statement(stmt));
+        self.scopes.pop();
+        result?;
+        let unreachable = statements
+            .windows(2)
+            .find(|pair| !falls_through(&pair[..1]))
+            .map(|pair| &pair[1]);
+        if let Some(stmt) = unreachable {
+            self.warnings
+                .push(format!("Unreachable statement at {}", stmt.span));
+        }
+        Ok(())
+    }
+
+    // Checks an assignment, returning the type of the value assigned
+    fn assign(&mut self, target: &str, value: &Expr) -> Result<Type, TypeError> {
+        let found = self.expr(value)?;
+        match self.lookup(target) {
+            Some(Variable { constant: true, .. }) => {
+                error(format!("Cannot assign to constant `{}`", target))
+            }
+            Some(Variable { typ: expected, .. }) if *expected != found => error(format!(
+                "Cannot assign {} to variable `{}` of type {}",
+                found, target, expected
+            )),
+            Some(_) => Ok(found),
+            None => error(format!("Assignment to undeclared variable `{}`", target)),
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) -> Result<(), TypeError> {
+        match stmt {
+            Statement::Let { name, typ, value } | Statement::Const { name, typ, value } => {
+                let constant = matches!(stmt, Statement::Const { .. });
+                // consts stand for their value everywhere after them, like a
+                // `#define`, which only makes sense for the whole program
+                if constant && (self.scopes.len() > 1 || self.return_type.is_some()) {
+                    return error("`const` is only allowed at the top level".to_string());
+                }
+                let found = self.expr(value)?;
+                if found != *typ {
+                    return Err(TypeError::mismatched_initializer(
+                        name,
+                        typ.clone(),
+                        found,
+                        value,
+                    ));
+                }
+                if let Type::Tuple(_) = typ {
+                    return error(format!(
+                        "Variable `{}` can't hold a tuple; destructure it with `let (...)`",
+                        name
+                    ));
+                }
+                if !self.allow_shadowing && self.lookup(name).is_some() {
+                    return error(format!("Variable `{}` shadows an existing variable", name));
+                }
+                self.declare(name, typ.clone(), constant);
+            }
+            Statement::Destructure { names, typ, value } => {
+                let Type::Tuple(elements) = typ else {
+                    return error(format!("Only a tuple can be destructured, not {}", typ));
+                };
+                if elements.len() != names.len() {
+                    return error(format!(
+                        "Cannot destructure {} into {} names",
+                        typ,
+                        names.len()
+                    ));
+                }
+                let found = self.expr(value)?;
+                if found != *typ {
+                    return error(format!(
+                        "Destructuring `({})` expected {}, found {}",
+                        names.join(", "),
+                        typ,
+                        found
+                    ));
+                }
+                for (name, element) in names.iter().zip(elements) {
+                    if !self.allow_shadowing && self.lookup(name).is_some() {
+                        return error(format!("Variable `{}` shadows an existing variable", name));
+                    }
+                    self.declare(name, element.clone(), false);
+                }
+            }
+            Statement::Assignment { target, value } => {
+                self.assign(target, value)?;
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.condition("if", condition)?;
+                self.block(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch)?;
+                }
+            }
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => {
+                self.condition("while", condition)?;
+                self.loops.push(label.clone());
+                let result = self.block(body);
+                self.loops.pop();
+                result?;
+            }
+            Statement::Break(label) | Statement::Continue(label) => {
+                let keyword = if matches!(stmt, Statement::Break(_)) {
+                    "break"
+                } else {
+                    "continue"
+                };
+                match label {
+                    Some(label) if !self.loops.iter().flatten().any(|name| name == label) => {
+                        return error(format!(
+                            "`{}` refers to unknown loop label `{}`",
+                            keyword, label
+                        ));
+                    }
+                    None if self.loops.is_empty() => {
+                        return error(format!("`{}` outside of a loop", keyword));
+                    }
+                    _ => {}
+                }
+            }
+            Statement::Function {
+                name,
+                params,
+                return_type,
+                body,
+            } => {
+                if let Some((param, _)) =
+                    params.iter().find(|(_, typ)| matches!(typ, Type::Tuple(_)))
+                {
+                    return error(format!(
+                        "Parameter `{}` of `{}` can't be a tuple",
+                        param, name
+                    ));
+                }
+                self.scopes.push(
+                    params
+                        .iter()
+                        .map(|(name, typ)| {
+                            let param = Variable {
+                                typ: typ.clone(),
+                                constant: false,
+                            };
+                            (name.clone(), param)
+                        })
+                        .collect(),
+                );
+                // loops don't extend into a nested function's body
+                let returns = match return_type {
+                    Some(typ) => Returns::Declared(typ.clone()),
+                    None => Returns::Inferred {
+                        function: name.clone(),
+                        found: None,
+                    },
+                };
+                let outer = self.return_type.replace(returns);
+                let outer_loops = std::mem::take(&mut self.loops);
+                let result = self.block(body);
+                let returns = std::mem::replace(&mut self.return_type, outer);
+                self.loops = outer_loops;
+                self.scopes.pop();
+                result?;
+                let gives_value = match &returns {
+                    Some(Returns::Declared(typ)) => *typ != Type::Void,
+                    Some(Returns::Inferred { found, .. }) => {
+                        found.as_ref().is_some_and(|typ| *typ != Type::Void)
+                    }
+                    None => false,
+                };
+                if gives_value && falls_through(body) {
+                    return error(format!("`{}` doesn't return a value on every path", name));
+                }
+                // without any returns giving a value, it gives none
+                if let Some(Returns::Inferred { found, .. }) = returns {
+                    if let Some(signature) = self.functions.get_mut(name) {
+                        signature.return_type = Some(found.unwrap_or(Type::Void));
+                    }
+                }
+            }
+            Statement::Return(value) => {
+                if self.return_type.is_none() {
+                    return error("`return` outside of a function".to_string());
+                }
+                let found = match value {
+                    Some(value) => self.expr(value)?,
+                    None => Type::Void,
+                };
+                match self.return_type.as_mut() {
+                    Some(Returns::Declared(expected)) if found != *expected => {
+                        return error(format!(
+                            "Function returning {} cannot return {}",
+                            expected, found
+                        ));
+                    }
+                    Some(Returns::Inferred {
+                        function,
+                        found: Some(first),
+                    }) if found != *first => {
+                        return error(format!(
+                            "Conflicting return types in `{}`: returns {} and also {}",
+                            function, first, found
+                        ));
+                    }
+                    Some(Returns::Inferred {
+                        found: inferred, ..
+                    }) => *inferred = Some(found),
+                    _ => {}
+                }
+            }
+            Statement::Expression(expr) => {
+                self.expr(expr)?;
+            }
+            Statement::Result(expr) => match self.expr(expr)? {
+                Type::Void => {
+                    return error(format!("The program's result `{}` has no value", expr));
+                }
+                Type::Tuple(_) => {
+                    return error(format!("The program's result `{}` is a tuple", expr));
+                }
+                _ => {}
+            },
+            // the driver resolves top-level imports, so any left are nested
+            Statement::Import(_) => {
+                return error("`import` is only allowed at the top level".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self, keyword: &str, condition: &Expr) -> Result<(), TypeError> {
+        let found = self.expr(condition)?;
+        if found != Type::Bool {
+            return error(format!(
+                "`{}` condition must be bool, found {}",
+                keyword, found
+            ));
+        }
+        Ok(())
+    }
+
+    // `typeof(expr)` is a query for tests of the checker, answered here and
+    // rejected by lowering; as a void call it can only be a statement of its
+    // own
+    fn type_query(&mut self, args: &[Spanned<Expr>]) -> Result<Type, TypeError> {
+        let [arg] = args else {
+            return error(format!(
+                "`{}` takes 1 argument but {} were given",
+                TYPEOF,
+                args.len()
+            ));
+        };
+        let found = self.expr(arg)?;
+        self.queries.push((arg.to_string(), found));
+        Ok(Type::Void)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Type, TypeError> {
+        match expr {
+            Expr::Integer(..) => Ok(Type::Int),
+            Expr::Boolean(_) => Ok(Type::Bool),
+            Expr::Variable(name) => match self.lookup(name) {
+                Some(variable) => Ok(variable.typ.clone()),
+                None => error(format!("Use of undeclared variable `{}`", name)),
+            },
+            Expr::Binary {
+                op,
+                left: left_expr,
+                right: right_expr,
+            } => {
+                let left = self.expr(left_expr)?;
+                let right = self.expr(right_expr)?;
+                let (operand, result) = match op {
+                    BinaryOp::Add
+                    | BinaryOp::WrappingAdd
+                    | BinaryOp::SaturatingAdd
+                    | BinaryOp::Subtract
+                    | BinaryOp::Multiply
+                    | BinaryOp::Divide
+                    | BinaryOp::Modulo => (Type::Int, Type::Int),
+                    // equality also compares bools, as long as both sides agree
+                    BinaryOp::Equal | BinaryOp::NotEqual if left == Type::Bool => {
+                        (Type::Bool, Type::Bool)
+                    }
+                    BinaryOp::Equal
+                    | BinaryOp::NotEqual
+                    | BinaryOp::Less
+                    | BinaryOp::LessEqual
+                    | BinaryOp::Greater
+                    | BinaryOp::GreaterEqual => (Type::Int, Type::Bool),
+                    BinaryOp::And | BinaryOp::Or => (Type::Bool, Type::Bool),
+                };
+                if left != operand || right != operand {
+                    // name whichever operands are the wrong type
+                    let offending: Vec<String> = [(left_expr, &left), (right_expr, &right)]
+                        .iter()
+                        .filter(|(_, typ)| **typ != operand)
+                        .map(|(expr, typ)| format!("`{}` is {}", expr, typ))
+                        .collect();
+                    return error(format!(
+                        "Operator `{}` expects {} operands, found {} and {}: {}",
+                        op,
+                        operand,
+                        left,
+                        right,
+                        offending.join(" and ")
+                    ));
+                }
+                Ok(result)
+            }
+            Expr::Unary {
+                op,
+                operand: operand_expr,
+            } => {
+                let found = self.expr(operand_expr)?;
+                let (expected, symbol) = match op {
+                    UnaryOp::Negate => (Type::Int, "-"),
+                    UnaryOp::Not => (Type::Bool, "!"),
+                };
+                if found != expected {
+                    return error(format!(
+                        "Operator `{}` expects {}, found {}: `{}` is {}",
+                        symbol, expected, found, operand_expr, found
+                    ));
+                }
+                Ok(expected)
+            }
+            Expr::Call { callee, args } => {
+                if matches!(&callee.node, Expr::Variable(name) if name == TYPEOF) {
+                    return self.type_query(args);
+                }
+                let found = args
+                    .iter()
+                    .map(|arg| self.expr(arg))
+                    .collect::<Result<Vec<Type>, TypeError>>()?;
+                let callee = match &callee.node {
+                    Expr::Variable(name) => name,
+                    // there are no function values, so only names are callable
+                    callee => {
+                        let found = self.expr(callee)?;
+                        return error(format!("Cannot call a value of type {}", found));
+                    }
+                };
+                let Some(signature) = self.functions.get(callee) else {
+                    return error(format!("Call to unknown function `{}`", callee));
+                };
+                let names: Vec<&str> = signature.names.iter().map(String::as_str).collect();
+                let found = positional(callee, args, found, &names).map_err(TypeError::new)?;
+                if found.len() != signature.params.len() {
+                    return error(format!(
+                        "Function `{}` takes {} arguments but {} were given",
+                        callee,
+                        signature.params.len(),
+                        found.len()
+                    ));
+                }
+                for (index, (expected, found)) in signature.params.iter().zip(&found).enumerate() {
+                    if expected != found {
+                        return error(format!(
+                            "Argument {} of `{}` should be {}, found {}",
+                            index + 1,
+                            callee,
+                            expected,
+                            found
+                        ));
+                    }
+                }
+                let Some(return_type) = signature.return_type.clone() else {
+                    return error(format!(
+                        "Return type of `{}` isn't known where it is called; declare it with `-> type`",
+                        callee
+                    ));
+                };
+                Ok(return_type)
+            }
+            Expr::Tuple(exprs) => {
+                let elements = exprs
+                    .iter()
+                    .map(|expr| self.expr(expr))
+                    .collect::<Result<Vec<Type>, TypeError>>()?;
+                Ok(Type::Tuple(elements))
+            }
+            Expr::Seq(exprs) => {
+                let mut found = Type::Void;
+                for expr in exprs {
+                    found = self.expr(expr)?;
+                }
+                Ok(found)
+            }
+            Expr::Assign { target, value } => self.assign(target, value),
+            Expr::Named { value, .. } => self.expr(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    fn check_source(input: &str) -> Result<Vec<String>, TypeError> {
+        check_source_with(input, &CheckOptions::default())
+    }
+
+    fn check_source_with(input: &str, options: &CheckOptions) -> Result<Vec<String>, TypeError> {
+        let tokens = lexer::lex(input).unwrap();
+        check_with_options(&parser::parse(tokens).unwrap(), options)
+    }
+
+    fn error_of(input: &str) -> String {
+        check_source(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_well_typed_program() {
+        check_source(
+            "fn double(n: int) -> int { return n * 2; }
+             let x: int = double(3);
+             let big: bool = x > 5 && !(x == 7);
+             if (big) { x = -x; } else { x = x + 1; }",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_let_type_mismatch() {
+        let message = error_of("let x: int = true;");
+        assert!(message.contains("expected int, found bool"));
+        assert!(message.ends_with("in initializer `true` at line 1, column 14"));
+
+        let message = error_of("let a: int = 1;\nlet b: bool = 1 + 2;");
+        assert!(message.contains(
+            "`b`: expected bool, found int in initializer `(1 + 2)` at line 2, column 15"
+        ));
+    }
+
+    #[test]
+    fn test_operand_type_errors() {
+        let message = error_of("let x: int = 1 + true;");
+        assert!(message.contains("`+` expects int operands, found int and bool: `true` is bool"));
+
+        let message = error_of("let b: bool = 1 && true;");
+        assert!(message.contains("`&&` expects bool operands, found int and bool: `1` is int"));
+
+        let message = error_of("let flag: bool = true; let x: int = flag * (2 < 3);");
+        assert!(message.contains(
+            "`*` expects int operands, found bool and bool: `flag` is bool and `(2 < 3)` is bool"
+        ));
+
+        let message = error_of("let n: int = 1; let b: bool = !n;");
+        assert!(message.contains("`!` expects bool, found int: `n` is int"));
+    }
+
+    #[test]
+    fn test_bool_equality() {
+        assert!(check_source(
+            "let a: bool = true; let b: bool = a == false; let c: bool = a != b;"
+        )
+        .is_ok());
+
+        let message = error_of("let b: bool = true == 1;");
+        assert!(message.contains("`==` expects bool operands, found bool and int"));
+        let message = error_of("let b: bool = true < false;");
+        assert!(message.contains("`<` expects int operands, found bool and bool"));
+    }
+
+    #[test]
+    fn test_assignment_type_mismatch() {
+        let message = error_of("let x: int = 1; x = false;");
+        assert!(message.contains("Cannot assign bool to variable `x` of type int"));
+    }
+
+    #[test]
+    fn test_condition_and_return_types() {
+        let message = error_of("let x: int = 1; while (x) { x = x - 1; }");
+        assert!(message.contains("`while` condition must be bool, found int"));
+
+        let message = error_of("fn f() -> bool { return 1; }");
+        assert!(message.contains("Function returning bool cannot return int"));
+    }
+
+    #[test]
+    fn test_loop_labels() {
+        check_source("outer: while (true) { while (true) { break outer; } continue; }").unwrap();
+
+        let message = error_of("outer: while (true) { break inner; }");
+        assert!(message.contains("`break` refers to unknown loop label `inner`"));
+
+        let message = error_of("continue;");
+        assert!(message.contains("`continue` outside of a loop"));
+    }
+
+    #[test]
+    fn test_builtin_calls() {
+        check_source("let x: int = pow(abs(-2), 3);").unwrap();
+
+        let message = error_of("let x: int = abs(true);");
+        assert!(message.contains("Argument 1 of `abs` should be int, found bool"));
+    }
+
+    #[test]
+    fn test_typeof() {
+        let tokens = lexer::lex(
+            "let a: int = 1; let b: int = 2;
+             typeof(a < b); typeof(a + b); typeof(typeof(a));",
+        )
+        .unwrap();
+        let queries = type_queries(&parser::parse(tokens).unwrap()).unwrap();
+        let types: Vec<(&str, Type)> = queries
+            .iter()
+            .map(|(expr, typ)| (expr.as_str(), typ.clone()))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("(a < b)", Type::Bool),
+                ("(a + b)", Type::Int),
+                ("a", Type::Int),
+                ("typeof(a)", Type::Void)
+            ]
+        );
+
+        let message = error_of("let x: int = 1; let y: bool = typeof(x);");
+        assert!(message.contains("expected bool, found void"));
+        let message = error_of("typeof(1, 2);");
+        assert!(message.contains("`typeof` takes 1 argument but 2 were given"));
+    }
+
+    #[test]
+    fn test_inferred_return_type() {
+        let tokens = lexer::lex(
+            "fn inc(a: int) { return a + 1; }
+             fn check(a: int) { if (a > 0) { return true; } return false; }
+             fn nothing() { }
+             typeof(inc(1)); typeof(check(1)); typeof(nothing());
+             let x: int = inc(inc(1));",
+        )
+        .unwrap();
+        let queries = type_queries(&parser::parse(tokens).unwrap()).unwrap();
+        let types: Vec<Type> = queries.iter().map(|(_, typ)| typ.clone()).collect();
+        assert_eq!(types, [Type::Int, Type::Bool, Type::Void]);
+
+        let message = error_of("fn f(a: int) { if (a > 0) { return a; } return a > 0; }");
+        assert!(message.contains("Conflicting return types in `f`: returns int and also bool"));
+        let message = error_of("fn f(a: int) { return f(a); }");
+        assert!(message.contains("Return type of `f` isn't known where it is called"));
+    }
+
+    #[test]
+    fn test_undeclared_variables() {
+        let message = error_of("let y: int = x + 1; let x: int = 1;");
+        assert!(message.contains("Use of undeclared variable `x`"));
+
+        let message = error_of("y = 5; let y: int = 1;");
+        assert!(message.contains("Assignment to undeclared variable `y`"));
+    }
+
+    #[test]
+    fn test_chained_call_is_rejected() {
+        let message = error_of("fn f(x: int) -> int { return x; } let y: int = f(1)(2);");
+        assert!(message.contains("Cannot call a value of type int"));
+    }
+
+    #[test]
+    fn test_named_arguments() {
+        // resolved to their parameters, so the types are checked by name
+        check_source(
+            "fn pick(n: int, first: bool) -> int { if (first) { return n; } return 0; }
+             let x: int = pick(first: true, n: 1);",
+        )
+        .unwrap();
+        let message = error_of(
+            "fn pick(n: int, first: bool) -> int { return n; } let x: int = pick(first: 1, n: 2);",
+        );
+        assert!(message.contains("Argument 2 of `pick` should be bool, found int"));
+
+        let add = "fn add(a: int, b: int) -> int { return a + b; }";
+        for (call, expected) in [
+            ("add(a: 1, c: 2)", "Function `add` has no parameter `c`"),
+            (
+                "add(1, b: 2)",
+                "Call to `add` mixes positional and named arguments",
+            ),
+            (
+                "add(a: 1, a: 2)",
+                "Parameter `a` of `add` is given more than once",
+            ),
+            ("add(b: 1)", "Call to `add` doesn't give parameter `a`"),
+            ("abs(n: 1)", "Function `abs` has no parameter `n`"),
+        ] {
+            let message = error_of(&format!("{} let x: int = {};", add, call));
+            assert!(message.contains(expected), "{}: {}", call, message);
+        }
+    }
+
+    #[test]
+    fn test_tuples() {
+        let divmod = "fn divmod(a: int, b: int) -> (int, int) { return (a / b, a % b); }";
+        check_source(&format!(
+            "{} let (q, r): (int, int) = divmod(7, 2); let sum: int = q + r;",
+            divmod
+        ))
+        .unwrap();
+
+        for (source, expected) in [
+            (
+                "let t: (int, int) = divmod(7, 2);",
+                "Variable `t` can't hold a tuple",
+            ),
+            (
+                "let (q, r): (int, bool) = divmod(7, 2);",
+                "Destructuring `(q, r)` expected (int, bool), found (int, int)",
+            ),
+            (
+                "let (a, b, c): (int, int) = divmod(7, 2);",
+                "Cannot destructure (int, int) into 3 names",
+            ),
+            ("let x: int = divmod(7, 2) + 1;", "found (int, int) and int"),
+            (
+                "fn f(p: (int, int)) -> int { return 0; }",
+                "Parameter `p` of `f` can't be a tuple",
+            ),
+            (
+                "fn f() -> (int, int) { return (1, true); }",
+                "Function returning (int, int) cannot return (int, bool)",
+            ),
+            (
+                "divmod(7, 2)",
+                "The program's result `divmod(7, 2)` is a tuple",
+            ),
+        ] {
+            let message = error_of(&format!("{} {}", divmod, source));
+            assert!(message.contains(expected), "{}: {}", source, message);
+        }
+    }
+
+    #[test]
+    fn test_shadowing_option() {
+        let source = "let x: int = 1; if (x > 0) { let x: bool = true; }";
+        check_source(source).unwrap();
+
+        let options = CheckOptions {
+            allow_shadowing: false,
+        };
+        let message = check_source_with(source, &options).unwrap_err().to_string();
+        assert!(message.contains("`x` shadows an existing variable"));
+        check_source_with("if (true) { let x: int = 1; } let x: int = 2;", &options).unwrap();
+    }
+
+    #[test]
+    fn test_consts() {
+        check_source("const PI: int = 3; let r: int = PI * 2;").unwrap();
+
+        let message = error_of("const PI: int = 3; PI = 4; let r: int = PI * 2;");
+        assert!(message.contains("Cannot assign to constant `PI`"));
+
+        for source in [
+            "let b: bool = true; if (b) { const K: int = 1; }",
+            "fn f() -> int { const K: int = 1; return K; }",
+        ] {
+            assert!(error_of(source).contains("`const` is only allowed at the top level"));
+        }
+    }
+
+    #[test]
+    fn test_block_scoping() {
+        let message = error_of("if (true) { let inner: int = 1; } let x: int = inner;");
+        assert!(message.contains("`inner`"));
+    }
+
+    #[test]
+    fn test_function_and_variable_namespaces() {
+        check_source(
+            "fn add(a: int, b: int) -> int { return a + b; }
+             let add: int = 1;
+             let sum: int = add(add, 2);",
+        )
+        .unwrap();
+
+        let message = error_of("let add: int = 1; let x: bool = add;");
+        assert!(message.contains("expected bool, found int"));
+    }
+
+    #[test]
+    fn test_unreachable_statements() {
+        let warnings = check_source("fn f() -> int { return 1; let x: int = 2; }").unwrap();
+        assert_eq!(warnings, ["Unreachable statement at line 1, column 27"]);
+
+        let warnings = check_source(
+            "while (true) { break; let a: int = 1; let b: int = 2; }
+             fn g(a: int) -> int { if (a > 0) { return 1; } else { return 2; } return 3; }",
+        )
+        .unwrap();
+        assert_eq!(
+            warnings,
+            [
+                "Unreachable statement at line 1, column 23",
+                "Unreachable statement at line 2, column 80"
+            ]
+        );
+        assert!(
+            check_source("fn f(a: int) -> int { if (a > 0) { return 1; } return 2; }")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_missing_returns() {
+        for source in [
+            "fn f(a: int) -> int { if (a > 0) { return 1; } }",
+            "fn f(a: int) -> int { while (a > 0) { return 1; } }",
+            "fn f(a: int) { if (a > 0) { return a; } }",
+        ] {
+            assert!(error_of(source).contains("`f` doesn't return a value on every path"));
+        }
+        check_source("fn f(a: int) { if (a > 0) { return; } }").unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::{env, fs};
+
+fn crucible(args: &[&str], stdin: Option<&str>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_crucible"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = child.stdin.take().unwrap();
+    // the binary may exit on a usage error before reading any of it
+    let _ = input.write_all(stdin.unwrap_or("").as_bytes());
+    drop(input);
+    child.wait_with_output().unwrap()
+}
+
+// A program file under the system temp dir, unique to the test
+fn write_program(test: &str, source: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!(
+        "crucible-cli-{}-{}.crucible",
+        test,
+        std::process::id()
+    ));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn stderr_of(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_success_exit_code() {
+    let path = write_program("ok", "let x: int = 1; let y: int = x + 1;");
+    let output = crucible(&[path.to_str().unwrap()], None);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Compilation successful"));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_compile_error_exit_code() {
+    let path = write_program("lex-error", "let x: int = @;");
+    let output = crucible(&[path.to_str().unwrap()], None);
+    assert_eq!(output.status.code(), Some(1));
+    // errors are printed with Display, not Debug
+    assert!(stderr_of(&output).starts_with("Lexer error at line 1, column 14"));
+    fs::remove_file(path).unwrap();
+
+    let output = crucible(&["/nonexistent/crucible/program.crucible"], None);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr_of(&output).contains("Could not read"));
+}
+
+#[test]
+fn test_usage_exit_code() {
+    for args in [&[][..], &["a.crucible", "b.crucible"][..]] {
+        let output = crucible(args, None);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(stderr_of(&output).starts_with("usage: crucible"));
+    }
+}
+
+#[test]
+fn test_overflow_option() {
+    let source = "let x: int = 9223372036854775807 + 1; let y: int = x - 1;";
+    let output = crucible(&["-"], Some(source));
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr_of(&output).contains("warning: Arithmetic overflow"));
+
+    let output = crucible(&["--overflow", "wrapping", "-"], Some(source));
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!stderr_of(&output).contains("warning"));
+
+    let output = crucible(&["--overflow", "sometimes", "-"], Some(source));
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_stdin_input() {
+    let output = crucible(&["-"], Some("let x: int = 2 * 21;"));
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = crucible(&["-"], Some("let x: int = true;"));
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr_of(&output).contains("Type error"));
+}
+
+#[test]
+fn test_run_option() {
+    let output = crucible(&["--run", "-"], Some("fn main() -> int { return 42; }"));
+    assert_eq!(output.status.code(), Some(42));
+
+    let output = crucible(&["--run", "-"], Some("let x: int = 1;"));
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr_of(&output).starts_with("Entry point error"));
+}
+
+#[test]
+fn test_emit_opt_diff() {
+    let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/basic.crucible");
+    let output = crucible(&["--emit", "opt-diff", sample], None);
+    assert_eq!(output.status.code(), Some(0));
+    let diff = String::from_utf8_lossy(&output.stdout);
+    assert!(diff.contains("- unused.1 = 0\n"));
+    assert!(diff.contains("- y.1 = x.1 + 1\n+ y.1 = 4\n"));
+
+    let output = crucible(&["--emit", "asm", sample], None);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_eval_option() {
+    let output = crucible(&["--eval", "-"], Some("let x: int = 5;\nx + 1"));
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "6\n");
+}